@@ -1,8 +1,9 @@
 use serde::{Deserialize, Serialize};
-use std::{fs, io, path::PathBuf};
+use std::{fs, io, path::PathBuf, sync::Arc, time::Duration};
+use sysinfo::System;
 use tauri::{AppHandle, Manager, Runtime, State};
 
-use super::{server, setup, state::AppState};
+use super::{mcp, server, setup, state::AppState};
 
 const CONFIGURATION_FILE_NAME: &str = "settings.json";
 
@@ -340,6 +341,7 @@ pub fn app_token(state: State<'_, AppState>) -> Option<String> {
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn start_server(
     app: AppHandle,
     host: String,
@@ -347,33 +349,124 @@ pub async fn start_server(
     prefix: String,
     api_key: String,
     trusted_hosts: Vec<String>,
+    upstreams: Option<Vec<server::UpstreamConfig>>,
+    model_routes: Option<Vec<server::ModelRoute>>,
+    rate_limits: Option<server::RateLimitConfig>,
+    tls: Option<server::TlsConfig>,
+    response_cache: Option<server::CacheConfig>,
+    mcp_tools: Option<server::McpToolsConfig>,
+    hooks: Option<Vec<server::Hook>>,
+    model_aliases: Option<Vec<server::ModelAlias>>,
+    queue: Option<server::QueueConfig>,
+    audit_log: Option<bool>,
+    advertise: Option<bool>,
+    unix_socket: Option<bool>,
+    cors: Option<server::CorsConfig>,
 ) -> Result<bool, String> {
     let state = app.state::<AppState>();
     let auth_token = state.app_token.clone().unwrap_or_default();
     let server_handle = state.server_handle.clone();
+    let data_folder = get_jan_data_folder_path(app.clone());
+    let log_dir = data_folder.join("logs");
+    fs::create_dir_all(&log_dir).map_err(|e| e.to_string())?;
+
+    let tls = tls
+        .map(|tls| server::TlsIdentity::load(&tls, &data_folder.join("certs"), &host))
+        .transpose()
+        .map_err(|e| format!("Failed to load TLS certificate: {}", e))?;
+    let tls_fingerprint = tls.as_ref().map(|tls| tls.fingerprint.clone());
 
     server::start_server(
         server_handle,
-        host,
-        port,
-        prefix,
-        auth_token,
-        api_key,
-        trusted_hosts,
+        server::ServerConfig {
+            host,
+            port,
+            prefix,
+            auth_token,
+            api_key,
+            api_keys: state.api_keys.clone(),
+            trusted_hosts,
+            cors: cors.unwrap_or_default(),
+            upstreams: upstreams.unwrap_or_default(),
+            model_routes: model_routes.unwrap_or_default(),
+            model_aliases: model_aliases.unwrap_or_default(),
+            rate_limits: rate_limits.unwrap_or_default(),
+            queue: queue.unwrap_or_default(),
+            hooks: hooks.unwrap_or_default(),
+            log_dir,
+            audit_log: audit_log.unwrap_or(false),
+            metrics: state.server_metrics.clone(),
+            cortex_restart_count: state.cortex_restart_count.clone(),
+            mcp_connected: state.mcp_successfully_connected.clone(),
+            tls,
+            unix_socket: unix_socket
+                .unwrap_or(false)
+                .then(|| data_folder.join(server::UNIX_SOCKET_FILE)),
+            advertise_host: advertise
+                .unwrap_or(false)
+                .then(|| System::host_name().unwrap_or_else(|| "jan".to_string())),
+            response_cache: response_cache.unwrap_or_default(),
+            cache_dir: data_folder.join(server::CACHE_DIR),
+            conversations: Arc::new(app.clone()),
+            mcp_tools: mcp_tools.unwrap_or_default(),
+            tool_provider: Arc::new(mcp::McpToolProvider::new(state.mcp_servers.clone())),
+        },
     )
     .await
     .map_err(|e| e.to_string())?;
+
+    *state.server_tls_fingerprint.lock().await = tls_fingerprint;
     Ok(true)
 }
 
+/// Applies settings to the running server without dropping its connections
+#[tauri::command]
+pub async fn update_server_config(
+    state: State<'_, AppState>,
+    prefix: Option<String>,
+    api_key: Option<String>,
+    trusted_hosts: Option<Vec<String>>,
+    model_routes: Option<Vec<server::ModelRoute>>,
+    rate_limits: Option<server::RateLimitConfig>,
+    hooks: Option<Vec<server::Hook>>,
+    model_aliases: Option<Vec<server::ModelAlias>>,
+    queue: Option<server::QueueConfig>,
+    cors: Option<server::CorsConfig>,
+) -> Result<(), String> {
+    let server_handle = state.server_handle.clone();
+
+    server::update_server_config(
+        server_handle,
+        server::ServerConfigUpdate {
+            prefix,
+            api_key,
+            trusted_hosts,
+            model_routes,
+            rate_limits,
+            queue,
+            hooks,
+            model_aliases,
+            cors,
+        },
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Stops the server, giving active streams `drain_timeout_secs` (30 by default) to finish
 #[tauri::command]
-pub async fn stop_server(state: State<'_, AppState>) -> Result<(), String> {
+pub async fn stop_server(
+    state: State<'_, AppState>,
+    drain_timeout_secs: Option<u64>,
+) -> Result<server::ShutdownSummary, String> {
     let server_handle = state.server_handle.clone();
+    let drain_timeout = Duration::from_secs(drain_timeout_secs.unwrap_or(30));
 
-    server::stop_server(server_handle)
+    let summary = server::stop_server(server_handle, drain_timeout)
         .await
         .map_err(|e| e.to_string())?;
-    Ok(())
+    *state.server_tls_fingerprint.lock().await = None;
+    Ok(summary)
 }
 
 #[tauri::command]
@@ -383,6 +476,85 @@ pub async fn get_server_status(state: State<'_, AppState>) -> Result<bool, Strin
     Ok(server::is_server_running(server_handle).await)
 }
 
+/// SHA-256 fingerprint of the certificate the running server presents, if it serves HTTPS
+#[tauri::command]
+pub async fn get_server_tls_fingerprint(
+    state: State<'_, AppState>,
+) -> Result<Option<String>, String> {
+    Ok(state.server_tls_fingerprint.lock().await.clone())
+}
+
+#[tauri::command]
+pub fn get_server_stats(state: State<'_, AppState>) -> server::ServerStats {
+    state.server_metrics.stats()
+}
+
+/// Looks for Jan servers advertised on the local network for `timeout_secs` (3 by default)
+#[tauri::command]
+pub async fn browse_servers(
+    timeout_secs: Option<u64>,
+) -> Result<Vec<server::DiscoveredServer>, String> {
+    server::browse_servers(Duration::from_secs(timeout_secs.unwrap_or(3))).await
+}
+
+/// Checks the hash chain of the server audit log
+#[tauri::command]
+pub async fn verify_audit_log(app: AppHandle) -> Result<server::AuditVerification, String> {
+    let log_dir = get_jan_data_folder_path(app).join("logs");
+    tokio::task::spawn_blocking(move || server::verify_audit_log(&log_dir))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("Failed to read audit log: {}", e))
+}
+
+#[tauri::command]
+pub async fn list_api_keys(state: State<'_, AppState>) -> Result<Vec<server::ApiKey>, String> {
+    Ok(state.api_keys.lock().await.list())
+}
+
+#[tauri::command]
+pub async fn create_api_key(
+    app: AppHandle,
+    name: String,
+    allowed_paths: Option<Vec<String>>,
+    allowed_models: Option<Vec<String>>,
+    expires_at: Option<i64>,
+    interactive: Option<bool>,
+) -> Result<server::CreatedApiKey, String> {
+    let state = app.state::<AppState>();
+    let mut api_keys = state.api_keys.lock().await;
+    let created = api_keys.create(
+        name,
+        allowed_paths.unwrap_or_default(),
+        allowed_models.unwrap_or_default(),
+        expires_at,
+        interactive.unwrap_or(false),
+    );
+    api_keys.save(&get_jan_data_folder_path(app.clone()))?;
+    log::info!("Created API key '{}'", created.key.name);
+    Ok(created)
+}
+
+#[tauri::command]
+pub async fn revoke_api_key(app: AppHandle, id: String) -> Result<(), String> {
+    let state = app.state::<AppState>();
+    let mut api_keys = state.api_keys.lock().await;
+    api_keys.revoke(&id)?;
+    api_keys.save(&get_jan_data_folder_path(app.clone()))?;
+    log::info!("Revoked API key {}", id);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_api_key(app: AppHandle, id: String) -> Result<(), String> {
+    let state = app.state::<AppState>();
+    let mut api_keys = state.api_keys.lock().await;
+    api_keys.delete(&id)?;
+    api_keys.save(&get_jan_data_folder_path(app.clone()))?;
+    log::info!("Deleted API key {}", id);
+    Ok(())
+}
+
 #[tauri::command]
 pub async fn read_logs(app: AppHandle) -> Result<String, String> {
     let log_path = get_jan_data_folder_path(app).join("logs").join("app.log");
@@ -1,3 +1,4 @@
+use futures_util::future::BoxFuture;
 use rmcp::model::{CallToolRequestParam, CallToolResult, Tool};
 use rmcp::{service::RunningService, transport::TokioChildProcess, RoleClient, ServiceExt};
 use serde_json::{Map, Value};
@@ -10,7 +11,7 @@ use tokio::{
     time::{sleep, timeout},
 };
 
-use super::{cmd::get_jan_data_folder_path, state::AppState};
+use super::{cmd::get_jan_data_folder_path, server::ToolProvider, state::AppState};
 
 const DEFAULT_MCP_CONFIG: &str = r#"{
   "mcpServers": {
@@ -808,7 +809,13 @@ pub async fn get_connected_servers(
 /// 5. Returns the combined list of all available tools
 #[tauri::command]
 pub async fn get_tools(state: State<'_, AppState>) -> Result<Vec<Tool>, String> {
-    let servers = state.mcp_servers.lock().await;
+    list_tools(&state.mcp_servers).await
+}
+
+async fn list_tools(
+    servers: &Mutex<HashMap<String, RunningService<RoleClient, ()>>>,
+) -> Result<Vec<Tool>, String> {
+    let servers = servers.lock().await;
     let mut all_tools: Vec<Tool> = Vec::new();
 
     for (_, service) in servers.iter() {
@@ -854,7 +861,15 @@ pub async fn call_tool(
     tool_name: String,
     arguments: Option<Map<String, Value>>,
 ) -> Result<CallToolResult, String> {
-    let servers = state.mcp_servers.lock().await;
+    call_tool_by_name(&state.mcp_servers, tool_name, arguments).await
+}
+
+async fn call_tool_by_name(
+    servers: &Mutex<HashMap<String, RunningService<RoleClient, ()>>>,
+    tool_name: String,
+    arguments: Option<Map<String, Value>>,
+) -> Result<CallToolResult, String> {
+    let servers = servers.lock().await;
 
     // Iterate through servers and find the first one that contains the tool
     for (_, service) in servers.iter() {
@@ -888,6 +903,40 @@ pub async fn call_tool(
     Err(format!("Tool {} not found", tool_name))
 }
 
+/// Gives the local API server access to the tools of the connected MCP servers
+pub struct McpToolProvider {
+    servers: Arc<Mutex<HashMap<String, RunningService<RoleClient, ()>>>>,
+}
+
+impl McpToolProvider {
+    pub fn new(servers: Arc<Mutex<HashMap<String, RunningService<RoleClient, ()>>>>) -> Self {
+        Self { servers }
+    }
+}
+
+impl ToolProvider for McpToolProvider {
+    fn list_tools(&self) -> BoxFuture<'_, Result<Vec<Value>, String>> {
+        Box::pin(async move {
+            list_tools(&self.servers)
+                .await?
+                .into_iter()
+                .map(|tool| serde_json::to_value(tool).map_err(|e| e.to_string()))
+                .collect()
+        })
+    }
+
+    fn call_tool(
+        &self,
+        name: String,
+        arguments: Option<Map<String, Value>>,
+    ) -> BoxFuture<'_, Result<Value, String>> {
+        Box::pin(async move {
+            let result = call_tool_by_name(&self.servers, name, arguments).await?;
+            serde_json::to_value(result).map_err(|e| e.to_string())
+        })
+    }
+}
+
 #[tauri::command]
 pub async fn get_mcp_configs(app: AppHandle) -> Result<String, String> {
     let mut path = get_jan_data_folder_path(app);
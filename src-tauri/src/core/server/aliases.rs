@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Another name a local model answers to, e.g. `gpt-4o` for tools that
+/// hardcode OpenAI model ids
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModelAlias {
+    pub alias: String,
+    /// Id of the model requests for the alias are sent to
+    pub model: String,
+}
+
+/// Aliases in configuration order, the first one with a matching name wins
+#[derive(Debug, Default)]
+pub struct ModelAliases {
+    aliases: Vec<ModelAlias>,
+}
+
+impl ModelAliases {
+    pub fn new(aliases: Vec<ModelAlias>) -> Self {
+        Self { aliases }
+    }
+
+    /// The model an alias stands for
+    pub fn resolve(&self, alias: &str) -> Option<&str> {
+        self.aliases
+            .iter()
+            .find(|entry| entry.alias == alias)
+            .map(|entry| entry.model.as_str())
+    }
+
+    /// Replaces an aliased `model` field of a JSON request body, returning the
+    /// rewritten body when there was one
+    pub fn rewrite_request(&self, body: &[u8]) -> Option<Vec<u8>> {
+        if self.aliases.is_empty() {
+            return None;
+        }
+        let mut request: Value = serde_json::from_slice(body).ok()?;
+        let model = self.resolve(request.get("model")?.as_str()?)?;
+        log::debug!("Model alias {} resolved to {}", request["model"], model);
+        request["model"] = Value::String(model.to_string());
+        serde_json::to_vec(&request).ok()
+    }
+
+    /// Adds an entry for every alias whose model is listed, copied from the
+    /// model's own entry
+    pub fn extend_models(&self, models: &mut Vec<Value>) {
+        let mut entries = Vec::new();
+        for entry in &self.aliases {
+            let listed = |id: &str| {
+                models
+                    .iter()
+                    .any(|model| model.get("id").and_then(Value::as_str) == Some(id))
+            };
+            // A real model with the same id is listed already
+            if listed(&entry.alias) {
+                continue;
+            }
+            let Some(target) = models
+                .iter()
+                .find(|model| model.get("id").and_then(Value::as_str) == Some(&entry.model))
+            else {
+                continue;
+            };
+            let mut alias = target.clone();
+            alias["id"] = Value::String(entry.alias.clone());
+            if alias.get("model").is_some() {
+                alias["model"] = Value::String(entry.alias.clone());
+            }
+            entries.push(alias);
+        }
+        models.extend(entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_model_aliases() {
+        let aliases = ModelAliases::new(vec![
+            ModelAlias {
+                alias: "gpt-4o".to_string(),
+                model: "llama3.1-8b-instruct-q4".to_string(),
+            },
+            ModelAlias {
+                alias: "gpt-4o-mini".to_string(),
+                model: "qwen2.5-0.5b".to_string(),
+            },
+        ]);
+
+        let body = json!({"model": "gpt-4o", "messages": []}).to_string();
+        let rewritten: Value =
+            serde_json::from_slice(&aliases.rewrite_request(body.as_bytes()).unwrap()).unwrap();
+        assert_eq!(rewritten["model"], "llama3.1-8b-instruct-q4");
+        let body = json!({"model": "llama3.1-8b-instruct-q4"}).to_string();
+        assert!(aliases.rewrite_request(body.as_bytes()).is_none());
+
+        // Aliases of models that aren't downloaded are not listed
+        let mut models = vec![json!({"id": "llama3.1-8b-instruct-q4", "object": "model"})];
+        aliases.extend_models(&mut models);
+        assert_eq!(models.len(), 2);
+        assert_eq!(models[1]["id"], "gpt-4o");
+        assert_eq!(models[1]["object"], "model");
+    }
+}
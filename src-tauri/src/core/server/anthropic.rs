@@ -0,0 +1,561 @@
+use futures_util::{stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::{Body, StatusCode};
+use serde_json::{json, Map, Value};
+
+use super::errors::ApiError;
+use super::sse::SseReader;
+
+/// Destination path of the Anthropic Messages API
+pub const MESSAGES_PATH: &str = "/v1/messages";
+
+/// Translates an Anthropic Messages request into a chat completions request
+pub fn to_openai_request(request: &Value) -> Result<Value, ApiError> {
+    let request = request.as_object().ok_or_else(|| {
+        ApiError::invalid_request("The request body must be a JSON object", "invalid_type")
+    })?;
+    let mut chat = Map::new();
+
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or_else(|| {
+            ApiError::invalid_request("model: Field required", "missing_required_parameter")
+                .with_param("model")
+        })?;
+    chat.insert("model".to_string(), json!(model));
+
+    let mut messages = Vec::new();
+    match request.get("system") {
+        Some(Value::String(system)) => messages.push(json!({"role": "system", "content": system})),
+        Some(Value::Array(blocks)) => {
+            let system = text_of(blocks);
+            messages.push(json!({"role": "system", "content": system}));
+        }
+        _ => {}
+    }
+
+    let input = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .ok_or_else(|| {
+            ApiError::invalid_request("messages: Field required", "missing_required_parameter")
+                .with_param("messages")
+        })?;
+    for message in input {
+        let role = message
+            .get("role")
+            .and_then(Value::as_str)
+            .unwrap_or("user");
+        match (role, message.get("content")) {
+            (_, Some(Value::String(content))) => {
+                messages.push(json!({"role": role, "content": content}));
+            }
+            ("assistant", Some(Value::Array(blocks))) => messages.push(assistant_message(blocks)),
+            (_, Some(Value::Array(blocks))) => messages.extend(user_messages(blocks)),
+            _ => {
+                return Err(ApiError::invalid_request(
+                    "messages: each message requires a 'content' string or array",
+                    "invalid_type",
+                )
+                .with_param("messages"))
+            }
+        }
+    }
+    chat.insert("messages".to_string(), Value::Array(messages));
+
+    for field in ["max_tokens", "temperature", "top_p", "top_k", "stream"] {
+        if let Some(value) = request.get(field) {
+            chat.insert(field.to_string(), value.clone());
+        }
+    }
+    if let Some(stop) = request.get("stop_sequences") {
+        chat.insert("stop".to_string(), stop.clone());
+    }
+    if let Some(user) = request
+        .get("metadata")
+        .and_then(|metadata| metadata.get("user_id"))
+    {
+        chat.insert("user".to_string(), user.clone());
+    }
+
+    if let Some(tools) = request.get("tools").and_then(Value::as_array) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .map(|tool| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.get("name"),
+                        "description": tool.get("description"),
+                        "parameters": tool.get("input_schema"),
+                    }
+                })
+            })
+            .collect();
+        chat.insert("tools".to_string(), Value::Array(tools));
+    }
+    if let Some(tool_choice) = request.get("tool_choice") {
+        let tool_choice = match tool_choice.get("type").and_then(Value::as_str) {
+            Some("any") => json!("required"),
+            Some("none") => json!("none"),
+            Some("tool") => json!({
+                "type": "function",
+                "function": {"name": tool_choice.get("name")},
+            }),
+            _ => json!("auto"),
+        };
+        chat.insert("tool_choice".to_string(), tool_choice);
+    }
+
+    Ok(Value::Object(chat))
+}
+
+/// Joins the text blocks of an Anthropic content array
+fn text_of(blocks: &[Value]) -> String {
+    blocks
+        .iter()
+        .filter_map(|block| block.get("text").and_then(Value::as_str))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn assistant_message(blocks: &[Value]) -> Value {
+    let mut message = json!({"role": "assistant", "content": text_of(blocks)});
+    let tool_calls: Vec<Value> = blocks
+        .iter()
+        .filter(|block| block.get("type").and_then(Value::as_str) == Some("tool_use"))
+        .map(|block| {
+            json!({
+                "id": block.get("id"),
+                "type": "function",
+                "function": {
+                    "name": block.get("name"),
+                    "arguments": block.get("input").unwrap_or(&json!({})).to_string(),
+                }
+            })
+        })
+        .collect();
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = Value::Array(tool_calls);
+    }
+    message
+}
+
+/// A user turn becomes one `tool` message per tool result, followed by the user content
+fn user_messages(blocks: &[Value]) -> Vec<Value> {
+    let mut messages = Vec::new();
+    let mut parts = Vec::new();
+
+    for block in blocks {
+        match block.get("type").and_then(Value::as_str) {
+            Some("text") => parts.push(json!({"type": "text", "text": block.get("text")})),
+            Some("image") => {
+                let source = block.get("source").cloned().unwrap_or_default();
+                let url = match source.get("type").and_then(Value::as_str) {
+                    Some("url") => source.get("url").cloned().unwrap_or_default(),
+                    _ => json!(format!(
+                        "data:{};base64,{}",
+                        source
+                            .get("media_type")
+                            .and_then(Value::as_str)
+                            .unwrap_or("image/png"),
+                        source.get("data").and_then(Value::as_str).unwrap_or("")
+                    )),
+                };
+                parts.push(json!({"type": "image_url", "image_url": {"url": url}}));
+            }
+            Some("tool_result") => {
+                let content = match block.get("content") {
+                    Some(Value::String(content)) => content.clone(),
+                    Some(Value::Array(blocks)) => text_of(blocks),
+                    _ => String::new(),
+                };
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": block.get("tool_use_id"),
+                    "content": content,
+                }));
+            }
+            _ => {}
+        }
+    }
+
+    if parts.iter().all(|part| part["type"] == "text") {
+        if !parts.is_empty() {
+            let text: Vec<&str> = parts
+                .iter()
+                .filter_map(|part| part["text"].as_str())
+                .collect();
+            messages.push(json!({"role": "user", "content": text.join("\n")}));
+        }
+    } else {
+        messages.push(json!({"role": "user", "content": parts}));
+    }
+    messages
+}
+
+fn stop_reason(finish_reason: Option<&str>) -> &'static str {
+    match finish_reason {
+        Some("length") => "max_tokens",
+        Some("tool_calls") | Some("function_call") => "tool_use",
+        _ => "end_turn",
+    }
+}
+
+fn message_id(id: Option<&str>) -> String {
+    match id {
+        Some(id) if id.starts_with("msg_") => id.to_string(),
+        Some(id) => format!("msg_{}", id),
+        None => format!("msg_{}", uuid::Uuid::new_v4().simple()),
+    }
+}
+
+/// Translates a chat completion into an Anthropic message
+pub fn to_anthropic_response(response: &Value) -> Value {
+    let choice = response.pointer("/choices/0").cloned().unwrap_or_default();
+    let message = choice.get("message").cloned().unwrap_or_default();
+
+    let mut content = Vec::new();
+    if let Some(text) = message
+        .get("content")
+        .and_then(Value::as_str)
+        .filter(|text| !text.is_empty())
+    {
+        content.push(json!({"type": "text", "text": text}));
+    }
+    for tool_call in message
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let arguments = tool_call
+            .pointer("/function/arguments")
+            .and_then(Value::as_str)
+            .unwrap_or("{}");
+        content.push(json!({
+            "type": "tool_use",
+            "id": tool_call.get("id"),
+            "name": tool_call.pointer("/function/name"),
+            "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+        }));
+    }
+
+    json!({
+        "id": message_id(response.get("id").and_then(Value::as_str)),
+        "type": "message",
+        "role": "assistant",
+        "model": response.get("model"),
+        "content": content,
+        "stop_reason": stop_reason(choice.get("finish_reason").and_then(Value::as_str)),
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": response.pointer("/usage/prompt_tokens").cloned().unwrap_or(json!(0)),
+            "output_tokens": response.pointer("/usage/completion_tokens").cloned().unwrap_or(json!(0)),
+        }
+    })
+}
+
+/// Translates an error body into Anthropic's `{"type": "error", ...}` format
+pub fn to_anthropic_error(status: StatusCode, body: &[u8]) -> Vec<u8> {
+    let json: Option<Value> = serde_json::from_slice(body).ok();
+    let message = json
+        .as_ref()
+        .and_then(|json| {
+            json.pointer("/error/message")
+                .or_else(|| json.get("message"))
+        })
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| String::from_utf8_lossy(body).trim().to_string());
+
+    let error_type = match status.as_u16() {
+        400 | 405 | 413 | 422 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        429 => "rate_limit_error",
+        503 | 529 => "overloaded_error",
+        _ => "api_error",
+    };
+    json!({
+        "type": "error",
+        "error": {"type": error_type, "message": message},
+    })
+    .to_string()
+    .into_bytes()
+}
+
+#[derive(Debug, PartialEq)]
+enum Block {
+    Text,
+    ToolUse,
+}
+
+/// Turns chat completion chunks into Anthropic stream events
+#[derive(Debug, Default)]
+pub struct StreamTranslator {
+    reader: SseReader,
+    started: bool,
+    finished: bool,
+    /// Kind of the open content block, if any
+    block: Option<Block>,
+    /// Index the next content block gets
+    next_index: usize,
+    stop_reason: Option<String>,
+    output_tokens: u64,
+}
+
+impl StreamTranslator {
+    /// Feeds raw SSE bytes from upstream, returns the events to send
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        let mut events = String::new();
+        for data in self.reader.push(bytes) {
+            if data == "[DONE]" {
+                events.push_str(&self.finish());
+            } else if let Ok(chunk) = serde_json::from_str::<Value>(&data) {
+                events.push_str(&self.chunk(&chunk));
+            }
+        }
+        events
+    }
+
+    fn chunk(&mut self, chunk: &Value) -> String {
+        let mut events = String::new();
+        if !self.started {
+            self.started = true;
+            events.push_str(&event(
+                "message_start",
+                json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": message_id(chunk.get("id").and_then(Value::as_str)),
+                        "type": "message",
+                        "role": "assistant",
+                        "model": chunk.get("model"),
+                        "content": [],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": {"input_tokens": 0, "output_tokens": 0},
+                    }
+                }),
+            ));
+        }
+        if let Some(tokens) = chunk
+            .pointer("/usage/completion_tokens")
+            .and_then(Value::as_u64)
+        {
+            self.output_tokens = tokens;
+        }
+
+        let Some(choice) = chunk.pointer("/choices/0") else {
+            return events;
+        };
+        if let Some(text) = choice
+            .pointer("/delta/content")
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+        {
+            if self.block != Some(Block::Text) {
+                events
+                    .push_str(&self.start_block(Block::Text, json!({"type": "text", "text": ""})));
+            }
+            events.push_str(&self.delta(json!({"type": "text_delta", "text": text})));
+        }
+        for tool_call in choice
+            .pointer("/delta/tool_calls")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(name) = tool_call.pointer("/function/name") {
+                events.push_str(&self.start_block(
+                    Block::ToolUse,
+                    json!({"type": "tool_use", "id": tool_call.get("id"), "name": name, "input": {}}),
+                ));
+            }
+            if let Some(arguments) = tool_call
+                .pointer("/function/arguments")
+                .and_then(Value::as_str)
+                .filter(|arguments| !arguments.is_empty())
+            {
+                events.push_str(
+                    &self.delta(json!({"type": "input_json_delta", "partial_json": arguments})),
+                );
+            }
+        }
+        if let Some(finish_reason) = choice.get("finish_reason").and_then(Value::as_str) {
+            self.stop_reason = Some(stop_reason(Some(finish_reason)).to_string());
+        }
+        events
+    }
+
+    fn start_block(&mut self, block: Block, content_block: Value) -> String {
+        let mut events = self.stop_block();
+        events.push_str(&event(
+            "content_block_start",
+            json!({"type": "content_block_start", "index": self.next_index, "content_block": content_block}),
+        ));
+        self.block = Some(block);
+        self.next_index += 1;
+        events
+    }
+
+    fn delta(&self, delta: Value) -> String {
+        event(
+            "content_block_delta",
+            json!({"type": "content_block_delta", "index": self.next_index.saturating_sub(1), "delta": delta}),
+        )
+    }
+
+    fn stop_block(&mut self) -> String {
+        match self.block.take() {
+            Some(_) => event(
+                "content_block_stop",
+                json!({"type": "content_block_stop", "index": self.next_index - 1}),
+            ),
+            None => String::new(),
+        }
+    }
+
+    /// Closes the message, also called when upstream ends without `[DONE]`
+    pub fn finish(&mut self) -> String {
+        if self.finished || !self.started {
+            return String::new();
+        }
+        self.finished = true;
+
+        let mut events = self.stop_block();
+        events.push_str(&event(
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": {
+                    "stop_reason": self.stop_reason.as_deref().unwrap_or("end_turn"),
+                    "stop_sequence": null,
+                },
+                "usage": {"output_tokens": self.output_tokens},
+            }),
+        ));
+        events.push_str(&event("message_stop", json!({"type": "message_stop"})));
+        events
+    }
+}
+
+fn event(name: &str, data: Value) -> String {
+    format!("event: {}\ndata: {}\n\n", name, data)
+}
+
+/// Wraps a chat completion SSE body into an Anthropic event stream
+pub fn translate_stream(body: Body) -> Body {
+    let events = stream::unfold(
+        (body, StreamTranslator::default(), false),
+        |(mut body, mut translator, done)| async move {
+            if done {
+                return None;
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => {
+                    let events = Bytes::from(translator.push(&chunk));
+                    Some((Ok(events), (body, translator, false)))
+                }
+                Some(Err(e)) => Some((Err(e), (body, translator, true))),
+                None => {
+                    let events = Bytes::from(translator.finish());
+                    Some((Ok(events), (body, translator, true)))
+                }
+            }
+        },
+    );
+    Body::wrap_stream(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_translation() {
+        let request = json!({
+            "model": "llama3.2-3b-instruct",
+            "max_tokens": 256,
+            "system": [{"type": "text", "text": "Be brief."}],
+            "stop_sequences": ["\n\n"],
+            "messages": [
+                {"role": "user", "content": "What's the weather?"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {"city": "Hanoi"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "toolu_1", "content": "Sunny"}
+                ]}
+            ],
+            "tools": [{"name": "weather", "description": "Weather", "input_schema": {"type": "object"}}],
+            "tool_choice": {"type": "any"}
+        });
+        let chat = to_openai_request(&request).unwrap();
+
+        assert_eq!(chat["messages"][0]["role"], "system");
+        assert_eq!(chat["messages"][0]["content"], "Be brief.");
+        assert_eq!(
+            chat["messages"][2]["tool_calls"][0]["function"]["arguments"],
+            r#"{"city":"Hanoi"}"#
+        );
+        assert_eq!(chat["messages"][3]["role"], "tool");
+        assert_eq!(chat["messages"][3]["tool_call_id"], "toolu_1");
+        assert_eq!(chat["stop"], json!(["\n\n"]));
+        assert_eq!(
+            chat["tools"][0]["function"]["parameters"],
+            json!({"type": "object"})
+        );
+        assert_eq!(chat["tool_choice"], "required");
+    }
+
+    #[test]
+    fn test_response_translation() {
+        let response = json!({
+            "id": "chatcmpl-1",
+            "model": "llama3.2-3b-instruct",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi!"}, "finish_reason": "length"}],
+            "usage": {"prompt_tokens": 5, "completion_tokens": 2}
+        });
+        let message = to_anthropic_response(&response);
+
+        assert_eq!(message["id"], "msg_chatcmpl-1");
+        assert_eq!(message["content"][0]["text"], "Hi!");
+        assert_eq!(message["stop_reason"], "max_tokens");
+        assert_eq!(message["usage"]["output_tokens"], 2);
+    }
+
+    #[test]
+    fn test_stream_translation() {
+        let mut translator = StreamTranslator::default();
+        let mut events = translator.push(
+            b"data: {\"id\":\"1\",\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel",
+        );
+        assert!(events.is_empty());
+        events.push_str(&translator.push(
+            b"lo\"},\"finish_reason\":null}]}\n\ndata: {\"id\":\"1\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+        ));
+        events.push_str(&translator.finish());
+
+        let names: Vec<&str> = events
+            .lines()
+            .filter_map(|line| line.strip_prefix("event: "))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+        assert!(events.contains("\"text\":\"Hello\""));
+        assert!(events.contains("\"stop_reason\":\"end_turn\""));
+    }
+}
@@ -0,0 +1,301 @@
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use super::routing::glob_match;
+
+/// File in the Jan data folder the key store is persisted to
+pub const API_KEYS_FILE: &str = "api_keys.json";
+
+/// Prefix of every generated key, makes leaked keys easy to recognise
+const KEY_PREFIX: &str = "jan-";
+
+/// A named API key for the local server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// SHA-256 of the secret, the secret itself is only returned once on creation
+    pub key_hash: String,
+    /// First characters of the secret, to tell keys apart in the UI
+    pub key_preview: String,
+    /// Allowed path prefixes, optionally preceded by a method (e.g. `POST /v1/chat`).
+    /// Empty allows every path.
+    #[serde(default)]
+    pub allowed_paths: Vec<String>,
+    /// Allowed model ids or glob patterns. Empty allows every model.
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// Unix timestamp (seconds) after which the key is rejected
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// Generations made with the key go ahead of other waiting requests
+    #[serde(default)]
+    pub interactive: bool,
+    #[serde(default)]
+    pub revoked: bool,
+    pub created_at: i64,
+}
+
+/// Returned once when a key is created, holds the only copy of the secret
+#[derive(Serialize, Debug, Clone)]
+pub struct CreatedApiKey {
+    pub key: ApiKey,
+    pub secret: String,
+}
+
+/// Why a bearer token was rejected
+#[derive(Debug, PartialEq)]
+pub enum ApiKeyError {
+    Unknown,
+    Revoked,
+    Expired,
+}
+
+impl std::fmt::Display for ApiKeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeyError::Unknown => write!(f, "Invalid or missing authorization token"),
+            ApiKeyError::Revoked => write!(f, "API key has been revoked"),
+            ApiKeyError::Expired => write!(f, "API key has expired"),
+        }
+    }
+}
+
+/// Whether the path has `.` or `..` segments, plain or percent-encoded,
+/// which upstream would resolve to a path outside the one checked
+fn has_dot_segments(path: &str) -> bool {
+    path.split('/').any(|segment| {
+        let segment = segment.to_ascii_lowercase().replace("%2e", ".");
+        segment == "." || segment == ".."
+    })
+}
+
+/// Whether the path is the prefix itself or lies below it
+fn is_under(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path == prefix
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+impl ApiKey {
+    /// Checks the method and destination path against the key's allowed paths
+    pub fn allows_path(&self, method: &str, path: &str) -> bool {
+        if self.allowed_paths.is_empty() {
+            return true;
+        }
+        if has_dot_segments(path) {
+            return false;
+        }
+
+        self.allowed_paths.iter().any(|allowed| {
+            let allowed = allowed.trim();
+            match allowed.split_once(' ') {
+                Some((allowed_method, prefix)) => {
+                    allowed_method.eq_ignore_ascii_case(method) && is_under(path, prefix.trim())
+                }
+                None => is_under(path, allowed),
+            }
+        })
+    }
+
+    /// Checks a requested model against the key's allowed models
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.is_empty()
+            || self
+                .allowed_models
+                .iter()
+                .any(|pattern| glob_match(pattern, model))
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+/// In-memory view of the persisted API keys
+#[derive(Debug, Default)]
+pub struct ApiKeyStore {
+    keys: Vec<ApiKey>,
+}
+
+impl ApiKeyStore {
+    /// Loads the store from the data folder, starting empty if the file is missing or invalid
+    pub fn load(data_folder: &Path) -> Self {
+        let path = data_folder.join(API_KEYS_FILE);
+        if !path.exists() {
+            return Self::default();
+        }
+
+        match fs::read_to_string(&path).map(|content| serde_json::from_str(&content)) {
+            Ok(Ok(keys)) => Self { keys },
+            Ok(Err(e)) => {
+                log::error!("Failed to parse {}: {}", API_KEYS_FILE, e);
+                Self::default()
+            }
+            Err(e) => {
+                log::error!("Failed to read {}: {}", API_KEYS_FILE, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, data_folder: &Path) -> Result<(), String> {
+        let data = serde_json::to_string_pretty(&self.keys).map_err(|e| e.to_string())?;
+        fs::write(data_folder.join(API_KEYS_FILE), data).map_err(|e| e.to_string())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn list(&self) -> Vec<ApiKey> {
+        self.keys.clone()
+    }
+
+    pub fn create(
+        &mut self,
+        name: String,
+        allowed_paths: Vec<String>,
+        allowed_models: Vec<String>,
+        expires_at: Option<i64>,
+        interactive: bool,
+    ) -> CreatedApiKey {
+        let secret: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect();
+        let secret = format!("{}{}", KEY_PREFIX, secret);
+
+        let key = ApiKey {
+            id: Uuid::new_v4().to_string(),
+            name,
+            key_hash: hash_key(&secret),
+            key_preview: secret[..KEY_PREFIX.len() + 4].to_string(),
+            allowed_paths,
+            allowed_models,
+            expires_at,
+            interactive,
+            revoked: false,
+            created_at: unix_now(),
+        };
+        self.keys.push(key.clone());
+
+        CreatedApiKey { key, secret }
+    }
+
+    pub fn revoke(&mut self, id: &str) -> Result<(), String> {
+        let key = self
+            .keys
+            .iter_mut()
+            .find(|key| key.id == id)
+            .ok_or_else(|| format!("API key {} not found", id))?;
+        key.revoked = true;
+        Ok(())
+    }
+
+    pub fn delete(&mut self, id: &str) -> Result<(), String> {
+        let len = self.keys.len();
+        self.keys.retain(|key| key.id != id);
+        if self.keys.len() == len {
+            return Err(format!("API key {} not found", id));
+        }
+        Ok(())
+    }
+
+    /// Resolves a bearer token to the key it belongs to
+    pub fn authenticate(&self, token: &str) -> Result<ApiKey, ApiKeyError> {
+        let hash = hash_key(token);
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.key_hash == hash)
+            .ok_or(ApiKeyError::Unknown)?;
+
+        if key.revoked {
+            Err(ApiKeyError::Revoked)
+        } else if key.is_expired(unix_now()) {
+            Err(ApiKeyError::Expired)
+        } else {
+            Ok(key.clone())
+        }
+    }
+}
+
+fn hash_key(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authenticate_created_key() {
+        let mut store = ApiKeyStore::default();
+        let created = store.create("ci".to_string(), vec![], vec![], None, false);
+
+        assert!(created.secret.starts_with(KEY_PREFIX));
+        assert_eq!(store.authenticate(&created.secret).unwrap().name, "ci");
+        assert_eq!(
+            store.authenticate("jan-unknown").unwrap_err(),
+            ApiKeyError::Unknown
+        );
+
+        store.revoke(&created.key.id).unwrap();
+        assert_eq!(
+            store.authenticate(&created.secret).unwrap_err(),
+            ApiKeyError::Revoked
+        );
+    }
+
+    #[test]
+    fn test_authenticate_expired_key() {
+        let mut store = ApiKeyStore::default();
+        let created = store.create("old".to_string(), vec![], vec![], Some(1), false);
+
+        assert_eq!(
+            store.authenticate(&created.secret).unwrap_err(),
+            ApiKeyError::Expired
+        );
+    }
+
+    #[test]
+    fn test_key_scopes() {
+        let mut store = ApiKeyStore::default();
+        let key = store
+            .create(
+                "chat-only".to_string(),
+                vec!["/v1/chat".to_string(), "GET /v1/models".to_string()],
+                vec!["llama3*".to_string()],
+                None,
+                false,
+            )
+            .key;
+
+        assert!(key.allows_path("POST", "/v1/chat/completions"));
+        assert!(key.allows_path("GET", "/v1/models"));
+        assert!(!key.allows_path("POST", "/v1/models/pull"));
+        assert!(!key.allows_path("POST", "/v1/embeddings"));
+        assert!(!key.allows_path("POST", "/v1/chatfoo"));
+        assert!(!key.allows_path("POST", "/v1/chat/../models/pull"));
+        assert!(!key.allows_path("POST", "/v1/chat/%2e%2E/models/pull"));
+
+        assert!(key.allows_model("llama3.1-8b-instruct"));
+        assert!(!key.allows_model("qwen2.5-7b"));
+    }
+}
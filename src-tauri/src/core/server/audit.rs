@@ -0,0 +1,394 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+/// Audit log file, written under the Jan logs folder
+pub const AUDIT_LOG_FILE: &str = "server-audit.jsonl";
+
+/// Position and hash of the last entry, kept next to the log so entries cut
+/// off its end are noticed
+pub const AUDIT_HEAD_FILE: &str = "server-audit.head";
+
+/// `prev_hash` of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Bytes read from the end of the log to find the entry to chain onto
+const TAIL_LENGTH: u64 = 64 * 1024;
+
+/// Who called which endpoint and whether they were let in
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AuditEntry {
+    /// Position in the chain, starting at 0
+    pub seq: u64,
+    /// Unix timestamp (milliseconds) the request was received at
+    pub timestamp: u64,
+    /// Client address, `unix` for clients of the Unix socket
+    pub client_ip: String,
+    pub origin: Option<String>,
+    pub key_name: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub model: Option<String>,
+    pub status: u16,
+    /// Error code when host validation or authorization turned the request away
+    pub denied: Option<String>,
+    /// Hash of the previous entry
+    pub prev_hash: String,
+}
+
+/// One line of the audit log
+#[derive(Serialize, Deserialize)]
+struct AuditLine {
+    #[serde(flatten)]
+    entry: AuditEntry,
+    hash: String,
+}
+
+/// Contents of the head file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct AuditHead {
+    seq: u64,
+    hash: String,
+}
+
+/// Result of checking the hash chain of the audit log
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AuditVerification {
+    pub entries: u64,
+    pub valid: bool,
+    /// 1-based line where the chain breaks
+    pub broken_at_line: Option<u64>,
+    pub error: Option<String>,
+    /// Hash of the last entry, a copy kept elsewhere also reveals a log
+    /// rewritten along with its head file
+    pub head_hash: Option<String>,
+}
+
+fn digest(entry: &AuditEntry) -> String {
+    let bytes = serde_json::to_vec(entry).unwrap_or_default();
+    format!("{:x}", Sha256::digest(&bytes))
+}
+
+/// Appends entries to the audit log, each chained to the previous one by its hash
+#[derive(Debug)]
+pub struct AuditLog {
+    sender: mpsc::UnboundedSender<AuditEntry>,
+}
+
+impl AuditLog {
+    /// Starts the writer task, which ends once the last handle is dropped
+    pub fn start(log_dir: &Path) -> Arc<Self> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_audit_log(
+            log_dir.join(AUDIT_LOG_FILE),
+            log_dir.join(AUDIT_HEAD_FILE),
+            receiver,
+        ));
+        Arc::new(Self { sender })
+    }
+
+    /// Queues an entry, its position and hashes are set by the writer
+    pub fn record(&self, entry: AuditEntry) {
+        if self.sender.send(entry).is_err() {
+            log::debug!("Audit log writer has stopped");
+        }
+    }
+}
+
+/// Builds an entry for a request received now
+pub fn audit_entry(
+    client_ip: String,
+    origin: &str,
+    method: &str,
+    path: &str,
+    status: u16,
+) -> AuditEntry {
+    AuditEntry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default(),
+        client_ip,
+        origin: (!origin.is_empty()).then(|| origin.to_string()),
+        method: method.to_string(),
+        path: path.to_string(),
+        status,
+        ..Default::default()
+    }
+}
+
+fn read_head(path: &Path) -> Option<AuditHead> {
+    let content = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Reads the last complete entry of an existing log, and whether the log
+/// ends mid-line as after a crash during a write
+async fn read_tail(file: &mut tokio::fs::File) -> std::io::Result<(Option<AuditLine>, bool)> {
+    let length = file.metadata().await?.len();
+    file.seek(SeekFrom::Start(length.saturating_sub(TAIL_LENGTH)))
+        .await?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).await?;
+    let torn = tail.last().is_some_and(|byte| *byte != b'\n');
+    let last = String::from_utf8_lossy(&tail)
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str(line).ok());
+    Ok((last, torn))
+}
+
+async fn write_audit_log(
+    path: PathBuf,
+    head_path: PathBuf,
+    mut receiver: mpsc::UnboundedReceiver<AuditEntry>,
+) {
+    let mut file = match tokio::fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(&path)
+        .await
+    {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to open audit log {:?}: {}", path, e);
+            return;
+        }
+    };
+    let (last, torn) = read_tail(&mut file).await.unwrap_or_else(|e| {
+        // Keep logging, verification will report where the chain breaks
+        log::error!("Failed to read the end of the audit log {:?}: {}", path, e);
+        (None, false)
+    });
+    let last = last.map(|line| AuditHead {
+        seq: line.entry.seq,
+        hash: line.hash,
+    });
+    // Entries cut off the end of the log are still in the head, chaining onto
+    // it leaves the gap for verification to find instead of forking the chain
+    let end = match (last, read_head(&head_path)) {
+        (Some(last), Some(head)) if head.seq > last.seq => Some(head),
+        (last, head) => last.or(head),
+    };
+    let (mut seq, mut prev_hash) = match end {
+        Some(end) => (end.seq + 1, end.hash),
+        None => (0, GENESIS_HASH.to_string()),
+    };
+    // A torn line is left for verification to report, the next entry starts on its own line
+    if torn {
+        if let Err(e) = file.write_all(b"\n").await {
+            log::error!("Failed to write audit log: {}", e);
+        }
+    }
+
+    while let Some(mut entry) = receiver.recv().await {
+        entry.seq = seq;
+        entry.prev_hash = prev_hash.clone();
+        let hash = digest(&entry);
+        let mut line = match serde_json::to_string(&AuditLine {
+            entry,
+            hash: hash.clone(),
+        }) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Failed to serialize audit log entry: {}", e);
+                continue;
+            }
+        };
+        line.push('\n');
+        // Flushed right away, the next start chains onto the last line on disk
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            log::error!("Failed to write audit log: {}", e);
+            continue;
+        }
+        if let Err(e) = file.flush().await {
+            log::error!("Failed to write audit log: {}", e);
+            continue;
+        }
+        let head = AuditHead {
+            seq,
+            hash: hash.clone(),
+        };
+        let head = serde_json::to_vec(&head).unwrap_or_default();
+        if let Err(e) = tokio::fs::write(&head_path, head).await {
+            log::error!("Failed to write audit log head {:?}: {}", head_path, e);
+        }
+        seq += 1;
+        prev_hash = hash;
+    }
+}
+
+/// Checks that every entry of the audit log is intact and follows the
+/// previous one, and that the log still ends at the recorded head
+pub fn verify_audit_log(log_dir: &Path) -> std::io::Result<AuditVerification> {
+    let lines = match std::fs::File::open(log_dir.join(AUDIT_LOG_FILE)) {
+        Ok(file) => Some(BufReader::new(file).lines()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    let mut entries = 0;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (index, line) in lines.into_iter().flatten().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let problem = match serde_json::from_str::<AuditLine>(&line) {
+            Err(e) => Some(format!("Unreadable entry: {}", e)),
+            Ok(line) if line.entry.seq != entries => Some(format!(
+                "Expected entry {} but found {}, entries are missing",
+                entries, line.entry.seq
+            )),
+            Ok(line) if line.entry.prev_hash != prev_hash => {
+                Some("Entry does not follow the previous one".to_string())
+            }
+            Ok(line) if digest(&line.entry) != line.hash => Some("Entry was modified".to_string()),
+            Ok(line) => {
+                prev_hash = line.hash;
+                None
+            }
+        };
+        if let Some(error) = problem {
+            return Ok(AuditVerification {
+                entries,
+                valid: false,
+                broken_at_line: Some(index as u64 + 1),
+                error: Some(error),
+                head_hash: None,
+            });
+        }
+        entries += 1;
+    }
+
+    let head_hash = (entries > 0).then_some(prev_hash);
+    let error = match read_head(&log_dir.join(AUDIT_HEAD_FILE)) {
+        Some(head) if head.seq >= entries => Some(format!(
+            "The log ends before entry {}, entries were removed from its end",
+            head.seq
+        )),
+        Some(head) if head.seq + 1 != entries || Some(&head.hash) != head_hash.as_ref() => {
+            Some("The last entry is not the one recorded in the head".to_string())
+        }
+        _ => None,
+    };
+    Ok(AuditVerification {
+        entries,
+        valid: error.is_none(),
+        broken_at_line: None,
+        error,
+        head_hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_audit_chain_detects_tampering() {
+        let dir = std::env::temp_dir().join(format!("jan-audit-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Two writers in turn, the second continues the chain of the first
+        for key in ["ci", "bot"] {
+            let audit = AuditLog::start(&dir);
+            let mut entry = audit_entry(
+                "127.0.0.1".to_string(),
+                "",
+                "POST",
+                "/v1/chat/completions",
+                200,
+            );
+            entry.key_name = Some(key.to_string());
+            audit.record(entry.clone());
+            entry.status = 401;
+            entry.denied = Some("invalid_api_key".to_string());
+            audit.record(entry);
+            drop(audit);
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        let verification = verify_audit_log(&dir).unwrap();
+        assert!(verification.valid, "{:?}", verification);
+        assert_eq!(verification.entries, 4);
+        assert!(verification.head_hash.is_some());
+
+        let path = dir.join(AUDIT_LOG_FILE);
+        let content = std::fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<_> = content.lines().collect();
+        lines.remove(1);
+        std::fs::write(&path, lines.join("\n")).unwrap();
+        let verification = verify_audit_log(&dir).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at_line, Some(2));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    async fn record(dir: &Path, count: usize) {
+        let audit = AuditLog::start(dir);
+        for _ in 0..count {
+            audit.record(audit_entry(
+                "127.0.0.1".to_string(),
+                "",
+                "GET",
+                "/v1/models",
+                200,
+            ));
+        }
+        drop(audit);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    #[tokio::test]
+    async fn test_audit_chain_detects_truncation() {
+        let dir = std::env::temp_dir().join(format!("jan-audit-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        record(&dir, 3).await;
+
+        let path = dir.join(AUDIT_LOG_FILE);
+        let content = std::fs::read_to_string(&path).unwrap();
+        let kept: Vec<_> = content.lines().take(2).collect();
+        std::fs::write(&path, kept.join("\n") + "\n").unwrap();
+        let verification = verify_audit_log(&dir).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.entries, 2);
+
+        // A restart continues from the head, the gap stays visible
+        record(&dir, 1).await;
+        let verification = verify_audit_log(&dir).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at_line, Some(3));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_audit_chain_continues_after_torn_line() {
+        let dir = std::env::temp_dir().join(format!("jan-audit-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        record(&dir, 2).await;
+
+        let path = dir.join(AUDIT_LOG_FILE);
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, &content[..content.len() - 10]).unwrap();
+        record(&dir, 1).await;
+
+        // Only the torn line is reported, the entries after it chain onto the head
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let last: AuditLine = serde_json::from_str(lines[2]).unwrap();
+        assert_eq!(last.entry.seq, 2);
+        let verification = verify_audit_log(&dir).unwrap();
+        assert_eq!(verification.broken_at_line, Some(2));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,430 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Folder in the Jan data folder cached responses are stored in
+pub const CACHE_DIR: &str = "cache/responses";
+
+/// Response header telling whether the response came from the cache
+pub const CACHE_HEADER: &str = "X-Jan-Cache";
+
+/// Request fields that do not change the generated content
+const IGNORED_FIELDS: [&str; 3] = ["stream", "stream_options", "user"];
+
+/// Settings of the on-disk response cache, disabled by default
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Least recently used responses are evicted beyond this size
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+}
+
+fn default_max_size_mb() -> u64 {
+    512
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_size_mb: default_max_size_mb(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    size: u64,
+    last_used: u128,
+}
+
+/// Caches deterministic completions and embeddings on disk, one file per response
+#[derive(Debug)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl ResponseCache {
+    /// Opens the cache folder, indexing the responses left by earlier runs
+    pub fn open(dir: &Path, config: &CacheConfig) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        let mut entries = HashMap::new();
+        for entry in fs::read_dir(dir)?.flatten() {
+            let path = entry.path();
+            let (Some(key), Ok(metadata)) = (
+                path.file_stem().and_then(|stem| stem.to_str()),
+                entry.metadata(),
+            ) else {
+                continue;
+            };
+            let last_used = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_millis())
+                .unwrap_or_default();
+            entries.insert(
+                key.to_string(),
+                CacheEntry {
+                    size: metadata.len(),
+                    last_used,
+                },
+            );
+        }
+        log::info!("Response cache opened with {} entries", entries.len());
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            max_bytes: config.max_size_mb * 1024 * 1024,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    /// Returns the cached response body for a key
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.get_mut(key)?.last_used = now_millis();
+        }
+
+        match tokio::fs::read(self.path(key)).await {
+            Ok(bytes) => {
+                // Keep the recency across restarts, the index is rebuilt from mtimes
+                if let Ok(file) = fs::File::options().write(true).open(self.path(key)) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                Some(bytes)
+            }
+            Err(e) => {
+                log::warn!("Failed to read cached response {}: {}", key, e);
+                self.entries.lock().unwrap().remove(key);
+                None
+            }
+        }
+    }
+
+    /// Stores a response body, evicting the least recently used ones when over budget
+    pub async fn put(&self, key: &str, bytes: &[u8]) {
+        if bytes.len() as u64 > self.max_bytes {
+            return;
+        }
+        if let Err(e) = tokio::fs::write(self.path(key), bytes).await {
+            log::warn!("Failed to write cached response {}: {}", key, e);
+            return;
+        }
+
+        let evicted = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.insert(
+                key.to_string(),
+                CacheEntry {
+                    size: bytes.len() as u64,
+                    last_used: now_millis(),
+                },
+            );
+            evict(&mut entries, self.max_bytes)
+        };
+        for key in evicted {
+            if let Err(e) = tokio::fs::remove_file(self.path(&key)).await {
+                log::warn!("Failed to evict cached response {}: {}", key, e);
+            }
+        }
+    }
+}
+
+/// Drops least recently used entries until the total size fits, returns their keys
+fn evict(entries: &mut HashMap<String, CacheEntry>, max_bytes: u64) -> Vec<String> {
+    let mut total: u64 = entries.values().map(|entry| entry.size).sum();
+    if total <= max_bytes {
+        return Vec::new();
+    }
+
+    let mut by_age: Vec<_> = entries
+        .iter()
+        .map(|(key, entry)| (entry.last_used, key.clone()))
+        .collect();
+    by_age.sort();
+
+    let mut evicted = Vec::new();
+    for (_, key) in by_age {
+        if total <= max_bytes {
+            break;
+        }
+        if let Some(entry) = entries.remove(&key) {
+            total -= entry.size;
+            evicted.push(key);
+        }
+    }
+    evicted
+}
+
+/// Cache key of a request, `None` when its response is not deterministic.
+/// Chat and text completions are only cached with `temperature` 0.
+pub fn cache_key(path: &str, request: &Value) -> Option<String> {
+    match path {
+        "/v1/embeddings" => {}
+        "/v1/chat/completions" | "/v1/completions" => {
+            if request.get("temperature").and_then(Value::as_f64) != Some(0.0) {
+                return None;
+            }
+        }
+        _ => return None,
+    }
+
+    let mut body = request.as_object()?.clone();
+    for field in IGNORED_FIELDS {
+        body.remove(field);
+    }
+    let model = body.get("model").and_then(Value::as_str)?.to_string();
+
+    let mut hasher = Sha256::new();
+    hasher.update(path.as_bytes());
+    hasher.update([0]);
+    hasher.update(model.as_bytes());
+    hasher.update([0]);
+    hasher.update(canonical_json(&Value::Object(body)).as_bytes());
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+/// Serializes JSON with sorted object keys, so formatting does not affect the hash
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(object) => {
+            let mut keys: Vec<_> = object.keys().collect();
+            keys.sort();
+            let fields: Vec<String> = keys
+                .into_iter()
+                .map(|key| {
+                    format!(
+                        "{}:{}",
+                        Value::from(key.as_str()),
+                        canonical_json(&object[key])
+                    )
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        // `0` and `0.0` are the same parameter value
+        Value::Number(number) => number
+            .as_f64()
+            .map(|number| number.to_string())
+            .unwrap_or_else(|| number.to_string()),
+        other => other.to_string(),
+    }
+}
+
+/// Rebuilds a complete response from the SSE chunks of a streamed completion.
+/// Returns `None` for streams that cannot be represented, e.g. tool calls.
+pub fn assemble_stream(sse: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(sse).ok()?;
+    let mut response: Option<Map<String, Value>> = None;
+    let mut choices: Vec<Map<String, Value>> = Vec::new();
+    let mut completed = false;
+
+    for line in text.lines() {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if data == "[DONE]" {
+            completed = true;
+            break;
+        }
+        let chunk: Value = serde_json::from_str(data).ok()?;
+        let chunk = chunk.as_object()?;
+        let response = response.get_or_insert_with(|| chunk.clone());
+        if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+            response.insert("usage".to_string(), usage.clone());
+        }
+
+        for choice in chunk.get("choices")?.as_array()? {
+            let index = choice.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+            while choices.len() <= index {
+                choices.push(Map::new());
+            }
+            let assembled = &mut choices[index];
+            assembled.insert("index".to_string(), json!(index));
+
+            if let Some(delta) = choice.get("delta") {
+                if delta.get("tool_calls").is_some() || delta.get("function_call").is_some() {
+                    return None;
+                }
+                let message = assembled
+                    .entry("message")
+                    .or_insert_with(|| json!({"role": "assistant", "content": ""}));
+                if let Some(role) = delta.get("role").filter(|role| role.is_string()) {
+                    message["role"] = role.clone();
+                }
+                if let Some(content) = delta.get("content").and_then(Value::as_str) {
+                    let existing = message["content"].as_str().unwrap_or_default();
+                    message["content"] = json!(format!("{}{}", existing, content));
+                }
+            } else if let Some(text) = choice.get("text").and_then(Value::as_str) {
+                let existing = assembled
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                assembled.insert("text".to_string(), json!(format!("{}{}", existing, text)));
+            }
+            if let Some(finish_reason) = choice.get("finish_reason").filter(|r| !r.is_null()) {
+                assembled.insert("finish_reason".to_string(), finish_reason.clone());
+            }
+        }
+    }
+
+    let mut response = response.filter(|_| completed)?;
+    if response.get("object").and_then(Value::as_str) == Some("chat.completion.chunk") {
+        response.insert("object".to_string(), json!("chat.completion"));
+    }
+    response.insert(
+        "choices".to_string(),
+        Value::Array(choices.into_iter().map(Value::Object).collect()),
+    );
+    serde_json::to_vec(&response).ok()
+}
+
+/// Replays a cached completion as the SSE stream a streaming request expects
+pub fn replay_as_stream(cached: &[u8]) -> Option<String> {
+    let response: Value = serde_json::from_slice(cached).ok()?;
+    let is_chat = response.get("object").and_then(Value::as_str) == Some("chat.completion");
+    let mut chunk = response.as_object()?.clone();
+    chunk.remove("usage");
+    if is_chat {
+        chunk.insert("object".to_string(), json!("chat.completion.chunk"));
+    }
+
+    let mut events = Vec::new();
+    for choice in response.get("choices")?.as_array()? {
+        let index = choice.get("index").cloned().unwrap_or(json!(0));
+        let finish_reason = choice
+            .get("finish_reason")
+            .cloned()
+            .unwrap_or(json!("stop"));
+        let (content, done) = if is_chat {
+            let message = choice.get("message")?;
+            let mut delta = json!({
+                "role": message.get("role").cloned().unwrap_or(json!("assistant")),
+                "content": message.get("content").cloned().unwrap_or(json!("")),
+            });
+            if let Some(tool_calls) = message.get("tool_calls").and_then(Value::as_array) {
+                // Stream chunks identify tool calls by index
+                delta["tool_calls"] = tool_calls
+                    .iter()
+                    .enumerate()
+                    .map(|(index, tool_call)| {
+                        let mut tool_call = tool_call.clone();
+                        tool_call["index"] = json!(index);
+                        tool_call
+                    })
+                    .collect();
+            }
+            (
+                json!({"index": index, "delta": delta, "finish_reason": null}),
+                json!({"index": index, "delta": {}, "finish_reason": finish_reason}),
+            )
+        } else {
+            (
+                json!({"index": index, "text": choice.get("text").cloned().unwrap_or(json!("")), "finish_reason": null}),
+                json!({"index": index, "text": "", "finish_reason": finish_reason}),
+            )
+        };
+        for choice in [content, done] {
+            chunk.insert("choices".to_string(), json!([choice]));
+            events.push(format!("data: {}\n\n", Value::Object(chunk.clone())));
+        }
+    }
+    if let Some(usage) = response.get("usage") {
+        chunk.insert("choices".to_string(), json!([]));
+        chunk.insert("usage".to_string(), usage.clone());
+        events.push(format!("data: {}\n\n", Value::Object(chunk)));
+    }
+    events.push("data: [DONE]\n\n".to_string());
+    Some(events.concat())
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_key() {
+        let request = json!({"model": "llama3", "temperature": 0, "messages": [{"role": "user", "content": "Hi"}]});
+        let reordered = json!({"messages": [{"content": "Hi", "role": "user"}], "stream": true, "temperature": 0.0, "model": "llama3"});
+        let key = cache_key("/v1/chat/completions", &request).unwrap();
+        assert_eq!(cache_key("/v1/chat/completions", &reordered), Some(key));
+
+        let sampled = json!({"model": "llama3", "temperature": 0.7, "messages": []});
+        assert!(cache_key("/v1/chat/completions", &sampled).is_none());
+        assert!(cache_key("/v1/embeddings", &json!({"model": "nomic", "input": "a"})).is_some());
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        let sse = concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"model\":\"llama3\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"Hel\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"model\":\"llama3\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let assembled = assemble_stream(sse.as_bytes()).unwrap();
+        let response: Value = serde_json::from_slice(&assembled).unwrap();
+        assert_eq!(response["object"], "chat.completion");
+        assert_eq!(response["choices"][0]["message"]["content"], "Hello");
+        assert_eq!(response["choices"][0]["finish_reason"], "stop");
+
+        let replayed = replay_as_stream(&assembled).unwrap();
+        assert_eq!(
+            assemble_stream(replayed.as_bytes())
+                .map(|bytes| serde_json::from_slice::<Value>(&bytes).unwrap()),
+            Some(response)
+        );
+
+        // Interrupted streams are not cached
+        assert!(assemble_stream(&sse.as_bytes()[..sse.len() - 14]).is_none());
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let mut entries = HashMap::from([
+            (
+                "old".to_string(),
+                CacheEntry {
+                    size: 60,
+                    last_used: 1,
+                },
+            ),
+            (
+                "new".to_string(),
+                CacheEntry {
+                    size: 60,
+                    last_used: 2,
+                },
+            ),
+        ]);
+        assert_eq!(evict(&mut entries, 100), vec!["old".to_string()]);
+        assert!(entries.contains_key("new"));
+    }
+}
@@ -0,0 +1,510 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::future::BoxFuture;
+use futures_util::{stream, StreamExt};
+use hyper::{Body, HeaderMap};
+use serde_json::{json, Value};
+
+/// Longest thread title derived from the first user message
+const TITLE_LENGTH: usize = 50;
+
+/// Request header naming the thread a chat completion is recorded in
+pub const THREAD_ID_HEADER: &str = "x-jan-thread-id";
+
+/// Jan's thread and message persistence, provided by the app so the server
+/// can keep API conversations where the UI shows them
+pub trait ConversationStore: Send + Sync {
+    /// Creates a thread, the returned thread carries the assigned id
+    fn create_thread(&self, thread: Value) -> BoxFuture<'_, Result<Value, String>>;
+
+    /// The thread with the given id, `None` when it does not exist
+    fn get_thread(&self, thread_id: String) -> BoxFuture<'_, Result<Option<Value>, String>>;
+
+    fn modify_thread(&self, thread: Value) -> BoxFuture<'_, Result<(), String>>;
+
+    fn list_messages(&self, thread_id: String) -> BoxFuture<'_, Result<Vec<Value>, String>>;
+
+    /// Appends a message to its thread, assigning an id when it has none
+    fn create_message(&self, message: Value) -> BoxFuture<'_, Result<Value, String>>;
+}
+
+/// Appends chat completion messages to a Jan thread
+#[derive(Clone)]
+pub struct ThreadWriter {
+    store: Arc<dyn ConversationStore>,
+    thread_id: String,
+}
+
+impl ThreadWriter {
+    /// Creates a new thread for a conversation with `model`
+    pub async fn create(
+        store: Arc<dyn ConversationStore>,
+        model: &str,
+        title: &str,
+        instructions: Option<&str>,
+    ) -> Result<Self, String> {
+        let thread = store
+            .create_thread(new_thread(model, title, instructions))
+            .await?;
+        let thread_id = thread
+            .get("id")
+            .and_then(Value::as_str)
+            .ok_or("Created thread has no id")?
+            .to_string();
+        Ok(Self { store, thread_id })
+    }
+
+    /// Writes to an existing thread, `None` when it does not exist
+    pub async fn open(
+        store: Arc<dyn ConversationStore>,
+        thread_id: String,
+    ) -> Result<Option<Self>, String> {
+        // Thread ids become part of a path, only accept the ids Jan assigns
+        if uuid::Uuid::parse_str(&thread_id).is_err() {
+            return Ok(None);
+        }
+        let exists = store.get_thread(thread_id.clone()).await?.is_some();
+        Ok(exists.then_some(Self { store, thread_id }))
+    }
+
+    /// Moves the thread to the top of the thread list
+    pub async fn touch(&self) -> Result<(), String> {
+        let Some(mut thread) = self.store.get_thread(self.thread_id.clone()).await? else {
+            return Err(format!("Thread {} not found", self.thread_id));
+        };
+        thread["updated"] = json!(now());
+        self.store.modify_thread(thread).await
+    }
+
+    pub fn thread_id(&self) -> &str {
+        &self.thread_id
+    }
+
+    /// Stores a chat message, under `id` when given
+    pub async fn append(&self, message: &Value, id: Option<String>) -> Result<(), String> {
+        let id = id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        self.store
+            .create_message(to_thread_message(&self.thread_id, &id, message))
+            .await
+            .map(|_| ())
+    }
+
+    /// Stores a message taken from another thread
+    pub async fn copy(&self, mut message: Value) -> Result<(), String> {
+        message["thread_id"] = json!(self.thread_id);
+        self.store.create_message(message).await.map(|_| ())
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// A thread in the shape the UI creates them
+fn new_thread(model: &str, title: &str, instructions: Option<&str>) -> Value {
+    let now = now();
+    json!({
+        "object": "thread",
+        "title": title,
+        "assistants": [{
+            "id": "jan",
+            "name": "Jan",
+            "instructions": instructions,
+            "model": {"id": model, "name": model, "engine": "llama.cpp", "settings": {}},
+        }],
+        "created": now,
+        "updated": now,
+        "metadata": {"source": "api"},
+    })
+}
+
+/// Thread a chat completion asked to be recorded in
+#[derive(Debug, PartialEq)]
+pub enum ThreadTarget {
+    New,
+    Existing(String),
+}
+
+impl ThreadTarget {
+    /// Reads the thread header, or `"store": true` for a new thread. The
+    /// `store` field is removed as it is not meant for the model server.
+    pub fn from_request(headers: &HeaderMap, request: &mut Value) -> Option<Self> {
+        let store = request
+            .as_object_mut()
+            .and_then(|request| request.remove("store"))
+            .and_then(|store| store.as_bool())
+            .unwrap_or(false);
+        match headers
+            .get(THREAD_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|thread_id| !thread_id.is_empty())
+        {
+            Some(thread_id) => Some(Self::Existing(thread_id.to_string())),
+            None if store => Some(Self::New),
+            None => None,
+        }
+    }
+}
+
+/// Writes the messages of a chat request that the thread does not have yet.
+/// Clients resend the whole conversation, so the messages beyond those already
+/// stored are new; a shorter conversation only adds its last message.
+pub async fn record_request(writer: &ThreadWriter, request: &Value) -> Result<(), String> {
+    writer.touch().await?;
+    let messages: Vec<&Value> = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|message| !is_instruction(message))
+        .collect();
+    let stored = writer
+        .store
+        .list_messages(writer.thread_id.clone())
+        .await?
+        .iter()
+        .filter_map(to_chat_message)
+        .count();
+
+    let skip = stored.min(messages.len().saturating_sub(1));
+    for message in &messages[skip..] {
+        writer.append(message, None).await?;
+    }
+    Ok(())
+}
+
+fn is_instruction(message: &Value) -> bool {
+    matches!(
+        message.get("role").and_then(Value::as_str),
+        Some("system") | Some("developer")
+    )
+}
+
+/// System prompt of a chat request, kept as the thread's assistant instructions
+pub fn instructions(request: &Value) -> Option<String> {
+    let instructions: Vec<String> = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|message| is_instruction(message))
+        .map(|message| text_of(message.get("content")))
+        .collect();
+    (!instructions.is_empty()).then(|| instructions.join("\n"))
+}
+
+/// Passes a chat completion SSE body through, storing the assembled reply
+/// once upstream finishes it
+pub fn record_stream(writer: ThreadWriter, body: Body) -> Body {
+    let chunks = stream::unfold(
+        (body, Some(writer), Vec::new()),
+        |(mut body, mut writer, mut received)| async move {
+            match body.next().await {
+                Some(Ok(chunk)) => {
+                    received.extend_from_slice(&chunk);
+                    Some((Ok(chunk), (body, writer, received)))
+                }
+                Some(Err(e)) => Some((Err(e), (body, None, received))),
+                None => {
+                    if let Some(writer) = writer.take() {
+                        match assemble_reply(&received) {
+                            Some(reply) => {
+                                if let Err(e) = writer.append(&reply, None).await {
+                                    log::error!("Failed to store reply: {}", e);
+                                }
+                            }
+                            None => log::warn!("Streamed reply could not be stored"),
+                        }
+                    }
+                    None
+                }
+            }
+        },
+    );
+    Body::wrap_stream(chunks)
+}
+
+/// Rebuilds the assistant message of a streamed chat completion, `None` when
+/// the stream did not finish
+fn assemble_reply(sse: &[u8]) -> Option<Value> {
+    let text = String::from_utf8_lossy(sse);
+    let mut content = String::new();
+    let mut tool_calls: Vec<Value> = Vec::new();
+    let mut completed = false;
+
+    for line in text.lines() {
+        let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+            continue;
+        };
+        if data == "[DONE]" {
+            completed = true;
+            break;
+        }
+        let Ok(chunk) = serde_json::from_str::<Value>(data) else {
+            continue;
+        };
+        let Some(delta) = chunk.pointer("/choices/0/delta") else {
+            continue;
+        };
+        if let Some(text) = delta.get("content").and_then(Value::as_str) {
+            content.push_str(text);
+        }
+        for tool_call in delta
+            .get("tool_calls")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let index = tool_call.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+            while tool_calls.len() <= index {
+                tool_calls.push(json!({
+                    "id": null,
+                    "type": "function",
+                    "function": {"name": "", "arguments": ""},
+                }));
+            }
+            let assembled = &mut tool_calls[index];
+            if let Some(id) = tool_call.get("id").filter(|id| id.is_string()) {
+                assembled["id"] = id.clone();
+            }
+            for field in ["name", "arguments"] {
+                if let Some(part) = tool_call
+                    .get("function")
+                    .and_then(|function| function.get(field))
+                    .and_then(Value::as_str)
+                {
+                    let existing = assembled["function"][field].as_str().unwrap_or_default();
+                    assembled["function"][field] = json!(format!("{}{}", existing, part));
+                }
+            }
+        }
+    }
+
+    if !completed {
+        return None;
+    }
+    let mut reply = json!({"role": "assistant", "content": content});
+    if !tool_calls.is_empty() {
+        reply["tool_calls"] = json!(tool_calls);
+    }
+    Some(reply)
+}
+
+/// Title for a thread, taken from the first user message
+pub fn thread_title(messages: &[Value]) -> String {
+    let text = messages
+        .iter()
+        .find(|message| message.get("role").and_then(Value::as_str) == Some("user"))
+        .map(|message| text_of(message.get("content")))
+        .unwrap_or_default();
+    let title: String = text
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .chars()
+        .take(TITLE_LENGTH)
+        .collect();
+    if title.is_empty() {
+        "New Thread".to_string()
+    } else {
+        title
+    }
+}
+
+/// Text of a chat message content, either a string or a list of parts
+fn text_of(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Converts a chat completion message into a Jan thread message. Tool calls
+/// are kept in the metadata, as the UI does.
+pub fn to_thread_message(thread_id: &str, id: &str, message: &Value) -> Value {
+    let now = now();
+    let mut content = vec![json!({
+        "type": "text",
+        "text": {"value": text_of(message.get("content")), "annotations": []},
+    })];
+    for part in message
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter(|part| part.get("type").and_then(Value::as_str) == Some("image_url"))
+    {
+        content.push(json!({
+            "type": "image_url",
+            "image_url": {
+                "url": part.pointer("/image_url/url"),
+                "detail": part.pointer("/image_url/detail"),
+            },
+        }));
+    }
+
+    let mut thread_message = json!({
+        "id": id,
+        "object": "thread.message",
+        "thread_id": thread_id,
+        "type": "text",
+        "role": message.get("role").and_then(Value::as_str).unwrap_or("user"),
+        "content": content,
+        "status": "ready",
+        "created_at": now,
+        "completed_at": now,
+        "metadata": {},
+    });
+    if let Some(tool_calls) = message
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .filter(|tool_calls| !tool_calls.is_empty())
+    {
+        thread_message["metadata"]["tool_calls"] = tool_calls
+            .iter()
+            .map(|tool| json!({"tool": tool, "state": "ready"}))
+            .collect();
+    }
+    if let Some(tool_call_id) = message.get("tool_call_id") {
+        thread_message["tool_call_id"] = tool_call_id.clone();
+    }
+    thread_message
+}
+
+/// Converts a Jan thread message back into a chat completion message,
+/// skipping messages the UI marked as failed
+pub fn to_chat_message(message: &Value) -> Option<Value> {
+    if message
+        .pointer("/metadata/error")
+        .is_some_and(|error| !error.is_null() && *error != Value::Bool(false))
+    {
+        return None;
+    }
+    let role = message.get("role").and_then(Value::as_str)?;
+    let parts = message
+        .get("content")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+
+    let text = parts
+        .iter()
+        .filter_map(|part| part.pointer("/text/value").and_then(Value::as_str))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let images: Vec<Value> = parts
+        .iter()
+        .filter_map(|part| part.pointer("/image_url/url"))
+        .filter(|url| url.is_string())
+        .map(|url| json!({"type": "image_url", "image_url": {"url": url}}))
+        .collect();
+    let content = if images.is_empty() {
+        json!(text)
+    } else {
+        let mut content = vec![json!({"type": "text", "text": text})];
+        content.extend(images);
+        json!(content)
+    };
+
+    let mut chat_message = json!({"role": role, "content": content});
+    let tool_calls: Vec<Value> = message
+        .pointer("/metadata/tool_calls")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|call| call.get("tool").cloned())
+        .collect();
+    if !tool_calls.is_empty() {
+        chat_message["tool_calls"] = json!(tool_calls);
+    }
+    if let Some(tool_call_id) = message.get("tool_call_id").filter(|id| id.is_string()) {
+        chat_message["tool_call_id"] = tool_call_id.clone();
+    }
+    Some(chat_message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_message_round_trip() {
+        let message = json!({
+            "role": "assistant",
+            "content": "Let me check.",
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": {"name": "weather", "arguments": "{}"},
+            }],
+        });
+        let stored = to_thread_message("thread-1", "message-1", &message);
+        assert_eq!(stored["content"][0]["text"]["value"], "Let me check.");
+        assert_eq!(stored["metadata"]["tool_calls"][0]["tool"]["id"], "call_1");
+        assert_eq!(to_chat_message(&stored), Some(message));
+    }
+
+    #[test]
+    fn test_failed_messages_are_not_replayed() {
+        let failed = json!({"role": "assistant", "content": [], "metadata": {"error": true}});
+        assert_eq!(to_chat_message(&failed), None);
+    }
+
+    #[test]
+    fn test_thread_title_is_the_first_line() {
+        assert_eq!(
+            thread_title(&[json!({"role": "user", "content": "Plan a trip\nto Hanoi"})]),
+            "Plan a trip"
+        );
+    }
+
+    #[test]
+    fn test_assemble_reply() {
+        let sse = concat!(
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\",\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"lo\",\"tool_calls\":[{\"index\":0,\"id\":\"call_1\",\"function\":{\"name\":\"weather\",\"arguments\":\"{\\\"city\"}}]}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"\\\":1}\"}}]}}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let reply = assemble_reply(sse.as_bytes()).unwrap();
+        assert_eq!(reply["content"], "Hello");
+        assert_eq!(reply["tool_calls"][0]["id"], "call_1");
+        assert_eq!(
+            reply["tool_calls"][0]["function"]["arguments"],
+            "{\"city\":1}"
+        );
+    }
+
+    #[test]
+    fn test_unfinished_stream_is_not_assembled() {
+        assert_eq!(assemble_reply(b"data: {\"choices\":[]}\n\n"), None);
+    }
+
+    #[test]
+    fn test_store_starts_a_new_thread() {
+        let mut request = json!({"model": "m", "messages": [], "store": true});
+        let target = ThreadTarget::from_request(&HeaderMap::new(), &mut request);
+        assert_eq!(target, Some(ThreadTarget::New));
+        assert!(request.get("store").is_none());
+    }
+
+    #[test]
+    fn test_thread_header_continues_a_thread() {
+        let mut headers = HeaderMap::new();
+        headers.insert(THREAD_ID_HEADER, "thread-1".parse().unwrap());
+        let mut request = json!({"model": "m", "messages": []});
+        let target = ThreadTarget::from_request(&headers, &mut request);
+        assert_eq!(target, Some(ThreadTarget::Existing("thread-1".to_string())));
+    }
+}
@@ -0,0 +1,337 @@
+use hyper::http::response::Builder;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use super::cache::CACHE_HEADER;
+use super::conversations::THREAD_ID_HEADER;
+use super::errors::ApiError;
+use super::queue::QUEUE_POSITION_HEADER;
+use super::routing::glob_match;
+
+/// Methods browsers may use to call the API
+const ALLOWED_METHODS: [&str; 6] = ["GET", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"];
+
+/// Request headers always allowed, the OpenAI and Anthropic SDKs and the Jan app send them
+const DEFAULT_ALLOWED_HEADERS: [&str; 30] = [
+    "accept",
+    "accept-language",
+    "anthropic-beta",
+    "anthropic-dangerous-direct-browser-access",
+    "anthropic-version",
+    "authorization",
+    "cache-control",
+    "connection",
+    "content-type",
+    "dnt",
+    "host",
+    "if-modified-since",
+    "keep-alive",
+    "origin",
+    "user-agent",
+    "x-api-key",
+    "x-csrf-token",
+    "x-forwarded-for",
+    "x-forwarded-host",
+    "x-forwarded-proto",
+    THREAD_ID_HEADER,
+    "x-requested-with",
+    "x-stainless-arch",
+    "x-stainless-lang",
+    "x-stainless-os",
+    "x-stainless-package-version",
+    "x-stainless-retry-count",
+    "x-stainless-runtime",
+    "x-stainless-runtime-version",
+    "x-stainless-timeout",
+];
+
+/// Response headers scripts can always read
+const DEFAULT_EXPOSED_HEADERS: [&str; 3] = [CACHE_HEADER, QUEUE_POSITION_HEADER, THREAD_ID_HEADER];
+
+/// Which web pages may call the API from a browser, and with what
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CorsConfig {
+    /// Exact origins or patterns such as `https://*.example.com`, any origin when empty
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Request headers allowed on top of the defaults, `*` allows any header
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+    /// Response headers scripts may read, on top of Jan's own
+    #[serde(default)]
+    pub exposed_headers: Vec<String>,
+    /// Let browsers send credentials to trusted hosts. With `allowed_origins`
+    /// set, only the listed origins get them.
+    #[serde(default = "default_allow_credentials")]
+    pub allow_credentials: bool,
+    /// Seconds browsers may reuse a preflight answer
+    #[serde(default = "default_max_age")]
+    pub max_age: u64,
+}
+
+fn default_allow_credentials() -> bool {
+    true
+}
+
+fn default_max_age() -> u64 {
+    86400
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_headers: Vec::new(),
+            exposed_headers: Vec::new(),
+            allow_credentials: default_allow_credentials(),
+            max_age: default_max_age(),
+        }
+    }
+}
+
+impl CorsConfig {
+    fn allows_origin(&self, origin: &str) -> bool {
+        let origin = origin.to_lowercase();
+        self.allowed_origins.is_empty()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|pattern| glob_match(&pattern.to_lowercase(), &origin))
+    }
+
+    /// Credentials are only shared through trusted hosts, the origin has
+    /// already been checked against the policy
+    fn allows_credentials(&self, host_trusted: bool) -> bool {
+        self.allow_credentials && host_trusted
+    }
+
+    fn allows_header(&self, header: &str) -> bool {
+        DEFAULT_ALLOWED_HEADERS
+            .into_iter()
+            .chain(self.allowed_headers.iter().map(String::as_str))
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(header))
+    }
+
+    fn allowed_headers(&self) -> String {
+        DEFAULT_ALLOWED_HEADERS
+            .into_iter()
+            .chain(self.allowed_headers.iter().map(String::as_str))
+            .filter(|header| *header != "*")
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn exposed_headers(&self) -> String {
+        DEFAULT_EXPOSED_HEADERS
+            .into_iter()
+            .chain(self.exposed_headers.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Adds the CORS headers of a response. Origins outside the policy get
+    /// none, so browsers keep the response from them.
+    pub fn apply(&self, builder: Builder, origin: &str, host_trusted: bool) -> Builder {
+        let mut builder = builder;
+        if origin.is_empty() {
+            builder = builder.header("Access-Control-Allow-Origin", "*");
+        } else if self.allows_origin(origin) {
+            builder = builder.header("Access-Control-Allow-Origin", origin);
+            if self.allows_credentials(host_trusted) {
+                builder = builder.header("Access-Control-Allow-Credentials", "true");
+            }
+        }
+
+        builder
+            .header("Access-Control-Allow-Methods", ALLOWED_METHODS.join(", "))
+            .header("Access-Control-Allow-Headers", self.allowed_headers())
+            .header("Access-Control-Expose-Headers", self.exposed_headers())
+            .header("Vary", "Origin")
+    }
+
+    /// Checks the method of a preflight request
+    pub fn check_preflight_method(&self, requested_method: &str) -> Result<(), ApiError> {
+        let allowed = requested_method.is_empty()
+            || ALLOWED_METHODS
+                .iter()
+                .any(|method| method.eq_ignore_ascii_case(requested_method));
+        if allowed {
+            Ok(())
+        } else {
+            log::warn!("CORS preflight: Method '{}' not allowed", requested_method);
+            Err(ApiError::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "Method not allowed",
+                "invalid_request_error",
+                "method_not_allowed",
+            ))
+        }
+    }
+
+    /// Answers a preflight request whose host was already validated
+    pub fn preflight(
+        &self,
+        origin: &str,
+        requested_headers: &str,
+        host_trusted: bool,
+    ) -> Result<Builder, ApiError> {
+        if !origin.is_empty() && !self.allows_origin(origin) {
+            log::warn!("CORS preflight: Origin '{}' not allowed", origin);
+            return Err(ApiError::forbidden(
+                "Origin not allowed",
+                "origin_not_allowed",
+            ));
+        }
+
+        let headers_valid = requested_headers
+            .split(',')
+            .map(|header| header.trim())
+            .filter(|header| !header.is_empty())
+            .all(|header| self.allows_header(header));
+        if !headers_valid {
+            log::warn!(
+                "CORS preflight: Some requested headers not allowed: {}",
+                requested_headers
+            );
+            return Err(ApiError::forbidden(
+                "Headers not allowed",
+                "headers_not_allowed",
+            ));
+        }
+
+        // Headers allowed by a wildcard have to be named in the answer
+        let allowed_headers =
+            if self.allowed_headers.iter().any(|h| h == "*") && !requested_headers.is_empty() {
+                requested_headers.to_string()
+            } else {
+                self.allowed_headers()
+            };
+        let mut response = hyper::Response::builder()
+            .status(StatusCode::OK)
+            .header("Access-Control-Allow-Methods", ALLOWED_METHODS.join(", "))
+            .header("Access-Control-Allow-Headers", allowed_headers)
+            .header("Access-Control-Max-Age", self.max_age.to_string())
+            .header(
+                "Vary",
+                "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+            );
+
+        if origin.is_empty() {
+            // No origin header - allow all origins (useful for non-browser clients)
+            response = response.header("Access-Control-Allow-Origin", "*");
+        } else {
+            response = response.header("Access-Control-Allow-Origin", origin);
+            if self.allows_credentials(host_trusted) {
+                response = response.header("Access-Control-Allow-Credentials", "true");
+            }
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header<'a>(builder: &'a Builder, name: &str) -> Option<&'a str> {
+        builder.headers_ref()?.get(name)?.to_str().ok()
+    }
+
+    fn restricted() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec![
+                "https://*.example.com".to_string(),
+                "http://localhost:3000".to_string(),
+            ],
+            allowed_headers: vec!["X-Tenant-Id".to_string()],
+            exposed_headers: vec!["X-Request-Id".to_string()],
+            max_age: 600,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_preflight_allows_listed_origins() {
+        let preflight = restricted()
+            .preflight(
+                "https://chat.example.com",
+                "content-type, x-tenant-id",
+                true,
+            )
+            .unwrap();
+        assert_eq!(
+            header(&preflight, "access-control-allow-origin"),
+            Some("https://chat.example.com")
+        );
+        assert_eq!(header(&preflight, "access-control-max-age"), Some("600"));
+        assert!(header(&preflight, "access-control-allow-headers")
+            .unwrap()
+            .contains("X-Tenant-Id"));
+    }
+
+    #[test]
+    fn test_preflight_rejects_unlisted_origins() {
+        let denied = restricted()
+            .preflight("https://example.org", "", true)
+            .unwrap_err();
+        assert_eq!(denied.code, "origin_not_allowed");
+    }
+
+    #[test]
+    fn test_preflight_rejects_unlisted_headers() {
+        let denied = restricted()
+            .preflight("http://localhost:3000", "x-other", true)
+            .unwrap_err();
+        assert_eq!(denied.code, "headers_not_allowed");
+    }
+
+    #[test]
+    fn test_response_exposes_jan_headers() {
+        let response =
+            restricted().apply(hyper::Response::builder(), "https://app.example.com", true);
+        assert_eq!(
+            header(&response, "access-control-expose-headers"),
+            Some("X-Jan-Cache, X-Jan-Queue-Position, x-jan-thread-id, X-Request-Id")
+        );
+    }
+
+    #[test]
+    fn test_response_to_unlisted_origin_has_no_cors_headers() {
+        let response = restricted().apply(hyper::Response::builder(), "https://evil.test", true);
+        assert_eq!(header(&response, "access-control-allow-origin"), None);
+    }
+
+    #[test]
+    fn test_credentials_need_trusted_host() {
+        let cors = restricted();
+        let origin = "https://app.example.com";
+        let response = cors.apply(hyper::Response::builder(), origin, true);
+        assert_eq!(
+            header(&response, "access-control-allow-credentials"),
+            Some("true")
+        );
+        let preflight = cors.preflight(origin, "", true).unwrap();
+        assert_eq!(
+            header(&preflight, "access-control-allow-credentials"),
+            Some("true")
+        );
+
+        // Preflights follow the same rule as responses
+        let preflight = cors.preflight(origin, "", false).unwrap();
+        assert_eq!(header(&preflight, "access-control-allow-credentials"), None);
+
+        // By default any origin is allowed, with credentials through trusted hosts
+        let open = CorsConfig::default();
+        let response = open.apply(hyper::Response::builder(), origin, true);
+        assert_eq!(
+            header(&response, "access-control-allow-origin"),
+            Some(origin)
+        );
+        assert_eq!(
+            header(&response, "access-control-allow-credentials"),
+            Some("true")
+        );
+        let preflight = open.preflight(origin, "", false).unwrap();
+        assert_eq!(header(&preflight, "access-control-allow-credentials"), None);
+    }
+}
@@ -0,0 +1,225 @@
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Service type other Jan instances browse for
+pub const JAN_SERVICE_TYPE: &str = "_jan._tcp.local.";
+
+/// Service type of OpenAI-compatible servers, for generic clients
+pub const OPENAI_SERVICE_TYPE: &str = "_openai._tcp.local.";
+
+/// How often the advertised details are brought up to date
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Details published in the TXT record of the advertised services
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceDetails {
+    pub prefix: String,
+    pub models: usize,
+    pub key_required: bool,
+}
+
+/// Announces the server on the local network for as long as it is alive
+pub struct Advertiser {
+    daemon: ServiceDaemon,
+    instance: String,
+    host_name: String,
+    addr: SocketAddr,
+    scheme: &'static str,
+    published: Option<ServiceDetails>,
+}
+
+impl Advertiser {
+    /// Sets up the announcement, nothing is published until the first update
+    pub fn new(host: &str, addr: SocketAddr, scheme: &'static str) -> Result<Self, String> {
+        let daemon = ServiceDaemon::new().map_err(|e| e.to_string())?;
+        let label: String = host
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        Ok(Self {
+            daemon,
+            instance: format!("Jan on {}", host),
+            host_name: format!("{}.local.", label.trim_matches('-')),
+            addr,
+            scheme,
+            published: None,
+        })
+    }
+
+    /// Publishes the details, re-announcing only when they changed
+    pub fn update(&mut self, details: ServiceDetails) -> Result<(), String> {
+        if self.published.as_ref() == Some(&details) {
+            return Ok(());
+        }
+        let properties = HashMap::from([
+            ("prefix".to_string(), details.prefix.clone()),
+            ("models".to_string(), details.models.to_string()),
+            ("key_required".to_string(), details.key_required.to_string()),
+            ("scheme".to_string(), self.scheme.to_string()),
+        ]);
+        for service_type in [JAN_SERVICE_TYPE, OPENAI_SERVICE_TYPE] {
+            let info = if self.addr.ip().is_unspecified() {
+                ServiceInfo::new(
+                    service_type,
+                    &self.instance,
+                    &self.host_name,
+                    "",
+                    self.addr.port(),
+                    properties.clone(),
+                )
+                .map(ServiceInfo::enable_addr_auto)
+            } else {
+                ServiceInfo::new(
+                    service_type,
+                    &self.instance,
+                    &self.host_name,
+                    self.addr.ip(),
+                    self.addr.port(),
+                    properties.clone(),
+                )
+            }
+            .map_err(|e| e.to_string())?;
+            self.daemon.register(info).map_err(|e| e.to_string())?;
+        }
+        log::info!(
+            "Advertising the server on the local network as '{}' ({} models)",
+            self.instance,
+            details.models
+        );
+        self.published = Some(details);
+        Ok(())
+    }
+}
+
+impl Drop for Advertiser {
+    fn drop(&mut self) {
+        if self.published.is_some() {
+            for service_type in [JAN_SERVICE_TYPE, OPENAI_SERVICE_TYPE] {
+                let fullname = format!("{}.{}", self.instance, service_type);
+                if let Err(e) = self.daemon.unregister(&fullname) {
+                    log::warn!("Failed to withdraw mDNS service {}: {}", fullname, e);
+                }
+            }
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// A Jan server found on the local network
+#[derive(Serialize, Debug, Clone)]
+pub struct DiscoveredServer {
+    pub name: String,
+    pub host: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    /// Base URL of the API, built from the first address
+    pub url: Option<String>,
+    pub prefix: String,
+    pub models: Option<usize>,
+    pub key_required: bool,
+}
+
+impl DiscoveredServer {
+    fn from_info(info: &ServiceInfo) -> Self {
+        let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+        // IPv4 first, it is what most clients reach first
+        addresses.sort_by_key(|addr| (addr.is_ipv6(), *addr));
+        let prefix = info
+            .get_property_val_str("prefix")
+            .unwrap_or("")
+            .to_string();
+        let scheme = info.get_property_val_str("scheme").unwrap_or("http");
+        let url = addresses.first().map(|addr| {
+            format!(
+                "{}://{}{}",
+                scheme,
+                SocketAddr::new(*addr, info.get_port()),
+                prefix
+            )
+        });
+        let name = info
+            .get_fullname()
+            .strip_suffix(&format!(".{}", JAN_SERVICE_TYPE))
+            .unwrap_or(info.get_fullname())
+            .to_string();
+        Self {
+            name,
+            host: info.get_hostname().to_string(),
+            addresses,
+            port: info.get_port(),
+            url,
+            prefix,
+            models: info
+                .get_property_val_str("models")
+                .and_then(|models| models.parse().ok()),
+            key_required: info.get_property_val_str("key_required") == Some("true"),
+        }
+    }
+}
+
+/// Looks for Jan servers on the local network for the given time
+pub async fn browse_servers(timeout: Duration) -> Result<Vec<DiscoveredServer>, String> {
+    let daemon = ServiceDaemon::new().map_err(|e| e.to_string())?;
+    let events = daemon.browse(JAN_SERVICE_TYPE).map_err(|e| e.to_string())?;
+
+    let mut servers: HashMap<String, DiscoveredServer> = HashMap::new();
+    let deadline = tokio::time::Instant::now() + timeout;
+    while let Ok(Ok(event)) = tokio::time::timeout_at(deadline, events.recv_async()).await {
+        match event {
+            ServiceEvent::ServiceResolved(info) => {
+                servers.insert(
+                    info.get_fullname().to_string(),
+                    DiscoveredServer::from_info(&info),
+                );
+            }
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                servers.remove(&fullname);
+            }
+            _ => {}
+        }
+    }
+
+    let _ = daemon.stop_browse(JAN_SERVICE_TYPE);
+    let _ = daemon.shutdown();
+    let mut servers: Vec<_> = servers.into_values().collect();
+    servers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(servers)
+}
+
+/// Whether the server is reachable from other machines and should be announced
+pub fn is_advertised(addr: &SocketAddr) -> bool {
+    !addr.ip().is_loopback()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discovered_server_from_txt_record() {
+        let properties = HashMap::from([
+            ("prefix".to_string(), "/v1".to_string()),
+            ("models".to_string(), "3".to_string()),
+            ("key_required".to_string(), "true".to_string()),
+            ("scheme".to_string(), "https".to_string()),
+        ]);
+        let info = ServiceInfo::new(
+            JAN_SERVICE_TYPE,
+            "Jan on studio",
+            "studio.local.",
+            "192.168.1.20",
+            1337,
+            properties,
+        )
+        .unwrap();
+
+        let server = DiscoveredServer::from_info(&info);
+        assert_eq!(server.name, "Jan on studio");
+        assert_eq!(server.url.as_deref(), Some("https://192.168.1.20:1337/v1"));
+        assert_eq!(server.models, Some(3));
+        assert!(server.key_required);
+    }
+}
@@ -0,0 +1,148 @@
+use hyper::{Body, StatusCode};
+use serde_json::{json, Value};
+
+/// An error returned to API clients in OpenAI's `{"error": {...}}` format
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+    pub error_type: &'static str,
+    pub code: &'static str,
+    /// Request parameter the error is about, if any
+    pub param: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(
+        status: StatusCode,
+        message: impl Into<String>,
+        error_type: &'static str,
+        code: &'static str,
+    ) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            error_type,
+            code,
+            param: None,
+        }
+    }
+
+    pub fn invalid_request(message: impl Into<String>, code: &'static str) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            message,
+            "invalid_request_error",
+            code,
+        )
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::UNAUTHORIZED,
+            message,
+            "invalid_request_error",
+            "invalid_api_key",
+        )
+    }
+
+    pub fn forbidden(message: impl Into<String>, code: &'static str) -> Self {
+        Self::new(
+            StatusCode::FORBIDDEN,
+            message,
+            "invalid_request_error",
+            code,
+        )
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "Not Found",
+            "invalid_request_error",
+            "not_found",
+        )
+    }
+
+    pub fn upstream(status: StatusCode, message: impl Into<String>) -> Self {
+        Self::new(status, message, "server_error", "upstream_error")
+    }
+
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.param = Some(param.into());
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "error": {
+                "message": self.message,
+                "type": self.error_type,
+                "param": self.param,
+                "code": self.code,
+            }
+        })
+    }
+
+    pub fn body(&self) -> Body {
+        Body::from(self.to_json().to_string())
+    }
+}
+
+/// Rewrites an upstream error body into the OpenAI format, unless it already is.
+/// Returns `None` when the body can be forwarded as is.
+pub fn normalize_upstream_error(status: StatusCode, bytes: &[u8]) -> Option<Vec<u8>> {
+    let json: Option<Value> = serde_json::from_slice(bytes).ok();
+    if json
+        .as_ref()
+        .and_then(|json| json.get("error"))
+        .is_some_and(|error| error.get("message").is_some())
+    {
+        return None;
+    }
+
+    // cortex reports errors as `{"message": "..."}`, anything else is forwarded as text
+    let message = match json.as_ref().and_then(|json| json.get("message")) {
+        Some(Value::String(message)) => message.clone(),
+        _ => String::from_utf8_lossy(bytes).trim().to_string(),
+    };
+    let message = if message.is_empty() {
+        status
+            .canonical_reason()
+            .unwrap_or("Upstream error")
+            .to_string()
+    } else {
+        message
+    };
+
+    let error = if status.is_server_error() {
+        ApiError::upstream(status, message)
+    } else {
+        ApiError::new(status, message, "invalid_request_error", "upstream_error")
+    };
+    Some(error.to_json().to_string().into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_upstream_error() {
+        let openai = br#"{"error":{"message":"model not found","type":"invalid_request_error"}}"#;
+        assert!(normalize_upstream_error(StatusCode::NOT_FOUND, openai).is_none());
+
+        let cortex = br#"{"message":"Model has not been loaded"}"#;
+        let normalized = normalize_upstream_error(StatusCode::BAD_REQUEST, cortex).unwrap();
+        let json: Value = serde_json::from_slice(&normalized).unwrap();
+        assert_eq!(json["error"]["message"], "Model has not been loaded");
+        assert_eq!(json["error"]["type"], "invalid_request_error");
+
+        let text = b"Internal Server Error";
+        let normalized = normalize_upstream_error(StatusCode::INTERNAL_SERVER_ERROR, text).unwrap();
+        let json: Value = serde_json::from_slice(&normalized).unwrap();
+        assert_eq!(json["error"]["message"], "Internal Server Error");
+        assert_eq!(json["error"]["type"], "server_error");
+        assert_eq!(json["error"]["code"], "upstream_error");
+    }
+}
@@ -0,0 +1,221 @@
+use hyper::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use super::routing::glob_match;
+
+/// A rewrite applied to the JSON body of matching requests before they are
+/// validated and sent upstream
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hook {
+    /// Destination path or glob pattern, e.g. `/v1/chat/completions`
+    pub path: String,
+    /// HTTP method to match, any method when unset
+    #[serde(default)]
+    pub method: Option<String>,
+    #[serde(flatten)]
+    pub action: HookAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum HookAction {
+    /// Adds a system message to requests that have none
+    InjectSystemPrompt { prompt: String },
+    /// Sets a parameter, replacing what the client sent
+    ForceParam { param: String, value: Value },
+    /// Keeps a numeric parameter within bounds, `default` is used when it is unset
+    ClampParam {
+        param: String,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+        #[serde(default)]
+        default: Option<Value>,
+    },
+    /// Removes fields from the request
+    StripFields { fields: Vec<String> },
+    /// Replaces the requested model, `from` may be a glob pattern
+    RewriteModel { from: String, to: String },
+}
+
+impl Hook {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        self.method
+            .as_deref()
+            .map_or(true, |m| m.eq_ignore_ascii_case(method.as_str()))
+            && glob_match(&self.path, path)
+    }
+}
+
+/// Hooks in configuration order, every matching hook runs
+#[derive(Debug, Default)]
+pub struct HookPipeline {
+    hooks: Vec<Hook>,
+}
+
+impl HookPipeline {
+    pub fn new(hooks: Vec<Hook>) -> Self {
+        Self { hooks }
+    }
+
+    /// Runs the matching hooks over a JSON object body, returning the rewritten
+    /// body when any of them changed it
+    pub fn apply(&self, method: &Method, path: &str, body: &[u8]) -> Option<Vec<u8>> {
+        let mut hooks = self
+            .hooks
+            .iter()
+            .filter(|hook| hook.matches(method, path))
+            .peekable();
+        hooks.peek()?;
+
+        let mut request: Value = serde_json::from_slice(body).ok()?;
+        let object = request.as_object_mut()?;
+        let mut changed = false;
+        for hook in hooks {
+            if apply_action(&hook.action, object) {
+                log::debug!("Hook {:?} rewrote request to {}", hook.action, path);
+                changed = true;
+            }
+        }
+        changed.then(|| serde_json::to_vec(&request).unwrap_or_default())
+    }
+}
+
+/// Applies one action, returns whether the request changed
+fn apply_action(action: &HookAction, request: &mut Map<String, Value>) -> bool {
+    match action {
+        HookAction::InjectSystemPrompt { prompt } => {
+            let Some(Value::Array(messages)) = request.get_mut("messages") else {
+                return false;
+            };
+            let has_system = messages.iter().any(|message| {
+                matches!(
+                    message.get("role").and_then(Value::as_str),
+                    Some("system") | Some("developer")
+                )
+            });
+            if has_system {
+                return false;
+            }
+            messages.insert(0, json!({"role": "system", "content": prompt}));
+            true
+        }
+        HookAction::ForceParam { param, value } => {
+            request.insert(param.clone(), value.clone()) != Some(value.clone())
+        }
+        HookAction::ClampParam {
+            param,
+            min,
+            max,
+            default,
+        } => {
+            let current = match request.get(param) {
+                None | Some(Value::Null) => {
+                    return match default {
+                        Some(default) => {
+                            request.insert(param.clone(), default.clone());
+                            true
+                        }
+                        None => false,
+                    };
+                }
+                Some(value) => value,
+            };
+            let Some(number) = current.as_f64() else {
+                return false;
+            };
+            let clamped = number
+                .max(min.unwrap_or(f64::MIN))
+                .min(max.unwrap_or(f64::MAX));
+            if clamped == number {
+                return false;
+            }
+            // Integer parameters such as `max_tokens` stay integers
+            let clamped = if current.is_i64() || current.is_u64() {
+                json!(clamped as i64)
+            } else {
+                json!(clamped)
+            };
+            request.insert(param.clone(), clamped);
+            true
+        }
+        HookAction::StripFields { fields } => {
+            let removed = fields
+                .iter()
+                .filter(|field| request.remove(field.as_str()).is_some())
+                .count();
+            removed > 0
+        }
+        HookAction::RewriteModel { from, to } => {
+            let matches = request
+                .get("model")
+                .and_then(Value::as_str)
+                .is_some_and(|model| model != to && glob_match(from, model));
+            if matches {
+                request.insert("model".to_string(), json!(to));
+            }
+            matches
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_pipeline() {
+        let hooks: Vec<Hook> = serde_json::from_value(json!([
+            {"path": "/v1/chat/completions", "method": "POST", "action": "inject_system_prompt", "prompt": "Answer in English."},
+            {"path": "/v1/*completions", "action": "clamp_param", "param": "max_tokens", "max": 1024, "default": 512},
+            {"path": "/v1/*completions", "action": "force_param", "param": "temperature", "value": 0.2},
+            {"path": "/v1/*completions", "action": "strip_fields", "fields": ["logit_bias"]},
+            {"path": "/v1/*", "action": "rewrite_model", "from": "gpt-4*", "to": "llama3.1-8b-instruct"}
+        ]))
+        .unwrap();
+        let pipeline = HookPipeline::new(hooks);
+
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "max_tokens": 4096,
+            "logit_bias": {"50256": -100}
+        });
+        let rewritten = pipeline
+            .apply(
+                &Method::POST,
+                "/v1/chat/completions",
+                body.to_string().as_bytes(),
+            )
+            .unwrap();
+        let rewritten: Value = serde_json::from_slice(&rewritten).unwrap();
+        assert_eq!(rewritten["messages"][0]["role"], "system");
+        assert_eq!(rewritten["max_tokens"], 1024);
+        assert_eq!(rewritten["temperature"], 0.2);
+        assert_eq!(rewritten["model"], "llama3.1-8b-instruct");
+        assert!(rewritten.get("logit_bias").is_none());
+
+        let completion = json!({"model": "llama3.2-3b-instruct", "prompt": "Once"});
+        let rewritten = pipeline
+            .apply(
+                &Method::POST,
+                "/v1/completions",
+                completion.to_string().as_bytes(),
+            )
+            .unwrap();
+        let rewritten: Value = serde_json::from_slice(&rewritten).unwrap();
+        assert_eq!(rewritten["max_tokens"], 512);
+
+        let embedding = json!({"model": "nomic-embed", "input": "Hi"});
+        assert!(pipeline
+            .apply(
+                &Method::POST,
+                "/v1/embeddings",
+                embedding.to_string().as_bytes()
+            )
+            .is_none());
+        assert!(pipeline.apply(&Method::GET, "/healthz", b"").is_none());
+    }
+}
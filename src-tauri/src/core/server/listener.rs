@@ -0,0 +1,272 @@
+use futures_util::stream::{self, FuturesUnordered, Stream, StreamExt};
+use std::io;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Unix socket the server listens on when enabled, created in the Jan data folder
+pub const UNIX_SOCKET_FILE: &str = "jan-server.sock";
+
+/// Time a client gets to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause after a failed accept, e.g. when running out of file descriptors
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// An accepted client connection, plain, TLS or over the Unix socket
+pub struct ClientConnection {
+    stream: ClientStream,
+    remote_addr: SocketAddr,
+}
+
+impl ClientConnection {
+    /// Address of the client, loopback for Unix socket clients, which
+    /// `is_local_socket` tells apart
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Whether the client connected through the Unix socket
+    pub fn is_local_socket(&self) -> bool {
+        #[cfg(unix)]
+        return matches!(self.stream, ClientStream::Unix(_));
+        #[cfg(not(unix))]
+        false
+    }
+}
+
+impl AsyncRead for ClientConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().stream {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match &mut self.get_mut().stream {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().stream {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.get_mut().stream {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            ClientStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Accepts the next TCP connection, retrying on transient errors
+async fn accept(listener: &TcpListener) -> (TcpStream, SocketAddr) {
+    loop {
+        match listener.accept().await {
+            Ok(accepted) => return accepted,
+            Err(e) => {
+                log::warn!("Failed to accept connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+async fn tls_handshake(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+) -> Option<ClientConnection> {
+    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => Some(ClientConnection {
+            stream: ClientStream::Tls(Box::new(stream)),
+            remote_addr,
+        }),
+        Ok(Err(e)) => {
+            log::debug!("TLS handshake with {} failed: {}", remote_addr, e);
+            None
+        }
+        Err(_) => {
+            log::debug!("TLS handshake with {} timed out", remote_addr);
+            None
+        }
+    }
+}
+
+pub type Incoming = Pin<Box<dyn Stream<Item = io::Result<ClientConnection>> + Send>>;
+
+/// Turns a listener into a stream of client connections for `hyper::Server`.
+/// TLS handshakes run concurrently so one slow client cannot block the others.
+/// The listener is closed as soon as the stream is dropped.
+pub fn incoming(listener: TcpListener, tls: Option<TlsAcceptor>) -> Incoming {
+    match tls {
+        None => Box::pin(stream::unfold(listener, |listener| async move {
+            let (stream, remote_addr) = accept(&listener).await;
+            let connection = ClientConnection {
+                stream: ClientStream::Plain(stream),
+                remote_addr,
+            };
+            Some((Ok(connection), listener))
+        })),
+        Some(acceptor) => {
+            let handshakes = FuturesUnordered::new();
+            Box::pin(stream::unfold(
+                (listener, acceptor, handshakes),
+                |(listener, acceptor, mut handshakes)| async move {
+                    loop {
+                        tokio::select! {
+                            (stream, remote_addr) = accept(&listener) => {
+                                handshakes.push(tls_handshake(acceptor.clone(), stream, remote_addr));
+                            }
+                            Some(handshake) = handshakes.next(), if !handshakes.is_empty() => {
+                                if let Some(connection) = handshake {
+                                    return Some((Ok(connection), (listener, acceptor, handshakes)));
+                                }
+                            }
+                        }
+                    }
+                },
+            ))
+        }
+    }
+}
+
+/// Binds a Unix socket that only the current user can connect to. A socket
+/// left behind by a server that is gone is replaced.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a file that is not a socket is in the way",
+            ));
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another server is listening on this socket",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Removes the socket file once the listener is closed
+#[cfg(unix)]
+struct SocketFile(std::path::PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Listens on a Unix socket and turns it into a stream of client connections.
+/// The socket file is removed as soon as the stream is dropped.
+#[cfg(unix)]
+pub fn incoming_unix(path: &Path) -> io::Result<Incoming> {
+    let listener = bind_unix(path)?;
+    let socket_file = SocketFile(path.to_path_buf());
+    Ok(Box::pin(stream::unfold(
+        (listener, socket_file),
+        |(listener, socket_file)| async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let connection = ClientConnection {
+                            stream: ClientStream::Unix(stream),
+                            remote_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+                        };
+                        return Some((Ok(connection), (listener, socket_file)));
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to accept Unix socket connection: {}", e);
+                        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    }
+                }
+            }
+        },
+    )))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_unix_socket_is_private_and_replaced_when_stale() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("jan-sock-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(UNIX_SOCKET_FILE);
+
+        // A socket nobody listens on anymore
+        drop(bind_unix(&path).unwrap());
+        let mut connections = incoming_unix(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        let mut connection = connections.next().await.unwrap().unwrap();
+        assert!(connection.is_local_socket());
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        connection.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert!(bind_unix(&path).is_err());
+
+        drop(connections);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
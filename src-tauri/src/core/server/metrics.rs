@@ -0,0 +1,625 @@
+use futures_util::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, Response};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+
+/// Access log file, written under the Jan logs folder
+pub const ACCESS_LOG_FILE: &str = "server-access.jsonl";
+
+/// Previous access log, replaced each time the current one is rotated
+const ROTATED_ACCESS_LOG_FILE: &str = "server-access.1.jsonl";
+
+/// Size at which the access log is rotated
+const MAX_ACCESS_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Content type of the Prometheus text exposition format
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Number of latency samples kept per model for percentile computation
+const LATENCY_SAMPLES: usize = 1000;
+
+/// Routes reported under their own label, requests to any other path count as `other`
+const ROUTE_LABELS: [&str; 14] = [
+    "/v1/chat/completions",
+    "/v1/completions",
+    "/v1/embeddings",
+    "/v1/models",
+    "/v1/responses",
+    "/v1/messages",
+    "/v1/realtime",
+    "/api/chat",
+    "/api/generate",
+    "/api/tags",
+    "/api/embeddings",
+    "/api/embed",
+    "/healthz",
+    "/metrics",
+];
+
+/// Label of requests to unknown routes and models
+const OTHER_LABEL: &str = "other";
+
+/// Distinct models tracked, further ones are counted as `other`
+const MAX_MODEL_LABELS: usize = 100;
+
+/// Label for the destination path of a request
+pub fn route_label(destination: &str) -> &'static str {
+    ROUTE_LABELS
+        .into_iter()
+        .find(|route| *route == destination.trim_end_matches('/'))
+        .unwrap_or(OTHER_LABEL)
+}
+
+/// Details learned while handling a request, filled in by the proxy as it goes
+#[derive(Debug, Default, Clone)]
+pub struct RequestInfo {
+    pub model: Option<String>,
+    pub key_name: Option<String>,
+    /// Error code when host validation or authorization turned the request away
+    pub denied: Option<&'static str>,
+    /// Set once the request was authorized and charged to the rate limit and
+    /// queue, upstream calls made again for it are not charged twice
+    pub admitted: bool,
+}
+
+/// One line of the access log
+#[derive(Serialize, Debug, Clone)]
+pub struct AccessLogEntry {
+    /// Unix timestamp (milliseconds) the request was received at
+    pub timestamp: u64,
+    /// Client address, `unix` for clients of the Unix socket
+    pub client_ip: String,
+    pub method: String,
+    pub path: String,
+    /// Label the request is counted under in the metrics
+    #[serde(skip)]
+    pub route: &'static str,
+    pub model: Option<String>,
+    pub key_name: Option<String>,
+    pub status: u16,
+    /// Time until the last byte was sent or the client went away
+    pub latency_ms: u64,
+    /// Time until the first body chunk was sent, if any
+    pub ttfb_ms: Option<u64>,
+    pub bytes: u64,
+}
+
+/// Aggregated statistics for one model
+#[derive(Serialize, Debug, Clone)]
+pub struct ModelStats {
+    pub model: String,
+    pub requests: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+    pub p50_latency_ms: u64,
+    pub p95_latency_ms: u64,
+}
+
+/// Aggregated statistics since the app started
+#[derive(Serialize, Debug, Clone)]
+pub struct ServerStats {
+    pub requests: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+    pub p50_latency_ms: u64,
+    pub p95_latency_ms: u64,
+    pub models: Vec<ModelStats>,
+}
+
+#[derive(Debug, Default)]
+struct Aggregate {
+    requests: u64,
+    errors: u64,
+    latencies: VecDeque<u64>,
+}
+
+impl Aggregate {
+    fn record(&mut self, entry: &AccessLogEntry) {
+        self.requests += 1;
+        if entry.status >= 400 {
+            self.errors += 1;
+        }
+        if self.latencies.len() == LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(entry.latency_ms);
+    }
+
+    fn error_rate(&self) -> f64 {
+        if self.requests == 0 {
+            0.0
+        } else {
+            self.errors as f64 / self.requests as f64
+        }
+    }
+
+    fn percentile(&self, percentile: f64) -> u64 {
+        if self.latencies.is_empty() {
+            return 0;
+        }
+        let mut sorted: Vec<u64> = self.latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (percentile * sorted.len() as f64).ceil() as usize;
+        sorted[rank.clamp(1, sorted.len()) - 1]
+    }
+}
+
+/// Request statistics of the local server, kept across server restarts
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    total: Mutex<Aggregate>,
+    models: Mutex<HashMap<String, Aggregate>>,
+    /// Request counts keyed by route, model and status
+    requests: Mutex<HashMap<(&'static str, String, u16), u64>>,
+    /// Models that answered successfully, the only ones given their own label
+    known_models: Mutex<HashSet<String>>,
+    /// Failed attempts keyed by upstream URL
+    upstream_errors: Mutex<HashMap<String, u64>>,
+}
+
+impl ServerMetrics {
+    pub fn record(&self, entry: &AccessLogEntry) {
+        let model = entry
+            .model
+            .as_deref()
+            .map(|model| self.model_label(model, entry.status));
+        let labels = (entry.route, model.clone().unwrap_or_default(), entry.status);
+        *self.requests.lock().unwrap().entry(labels).or_insert(0) += 1;
+        self.total.lock().unwrap().record(entry);
+        if let Some(model) = model {
+            self.models
+                .lock()
+                .unwrap()
+                .entry(model)
+                .or_default()
+                .record(entry);
+        }
+    }
+
+    /// A model gets its own label once it has answered a request, any name a
+    /// client makes up is counted as `other`
+    fn model_label(&self, model: &str, status: u16) -> String {
+        let mut known_models = self.known_models.lock().unwrap();
+        let known = known_models.contains(model)
+            || ((200..300).contains(&status)
+                && known_models.len() < MAX_MODEL_LABELS
+                && known_models.insert(model.to_string()));
+        if known {
+            model.to_string()
+        } else {
+            OTHER_LABEL.to_string()
+        }
+    }
+
+    pub fn record_upstream_error(&self, upstream: &str) {
+        *self
+            .upstream_errors
+            .lock()
+            .unwrap()
+            .entry(upstream.to_string())
+            .or_insert(0) += 1;
+    }
+
+    /// Renders the counters in the Prometheus text exposition format
+    pub fn render_prometheus(
+        &self,
+        active_streams: u32,
+        queued_requests: usize,
+        sidecar_restarts: u32,
+        mcp_connected: &HashMap<String, bool>,
+    ) -> String {
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP jan_server_requests_total Requests handled by the local API server.\n",
+        );
+        out.push_str("# TYPE jan_server_requests_total counter\n");
+        let requests = self.requests.lock().unwrap();
+        let mut request_counts: Vec<_> = requests.iter().collect();
+        request_counts.sort();
+        for ((route, model, status), count) in request_counts {
+            out.push_str(&format!(
+                "jan_server_requests_total{{path=\"{}\",model=\"{}\",status=\"{}\"}} {}\n",
+                route,
+                escape_label(model),
+                status,
+                count
+            ));
+        }
+
+        out.push_str(
+            "# HELP jan_server_streams_in_flight Streaming responses currently being sent.\n",
+        );
+        out.push_str("# TYPE jan_server_streams_in_flight gauge\n");
+        out.push_str(&format!(
+            "jan_server_streams_in_flight {}\n",
+            active_streams
+        ));
+
+        out.push_str(
+            "# HELP jan_server_requests_queued Generation requests waiting for the model.\n",
+        );
+        out.push_str("# TYPE jan_server_requests_queued gauge\n");
+        out.push_str(&format!("jan_server_requests_queued {}\n", queued_requests));
+
+        out.push_str(
+            "# HELP jan_server_upstream_errors_total Failed requests to upstream servers.\n",
+        );
+        out.push_str("# TYPE jan_server_upstream_errors_total counter\n");
+        let upstream_errors = self.upstream_errors.lock().unwrap();
+        let mut upstream_counts: Vec<_> = upstream_errors.iter().collect();
+        upstream_counts.sort();
+        for (upstream, count) in upstream_counts {
+            out.push_str(&format!(
+                "jan_server_upstream_errors_total{{upstream=\"{}\"}} {}\n",
+                escape_label(upstream),
+                count
+            ));
+        }
+
+        out.push_str(
+            "# HELP jan_cortex_restarts Restarts of the cortex sidecar since the last reset.\n",
+        );
+        out.push_str("# TYPE jan_cortex_restarts gauge\n");
+        out.push_str(&format!("jan_cortex_restarts {}\n", sidecar_restarts));
+
+        out.push_str(
+            "# HELP jan_mcp_server_connected Whether an MCP server is connected (1) or not (0).\n",
+        );
+        out.push_str("# TYPE jan_mcp_server_connected gauge\n");
+        let mut servers: Vec<_> = mcp_connected.iter().collect();
+        servers.sort();
+        for (server, connected) in servers {
+            out.push_str(&format!(
+                "jan_mcp_server_connected{{server=\"{}\"}} {}\n",
+                escape_label(server),
+                u8::from(*connected)
+            ));
+        }
+
+        out
+    }
+
+    pub fn stats(&self) -> ServerStats {
+        let total = self.total.lock().unwrap();
+        let mut models: Vec<ModelStats> = self
+            .models
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(model, aggregate)| ModelStats {
+                model: model.clone(),
+                requests: aggregate.requests,
+                errors: aggregate.errors,
+                error_rate: aggregate.error_rate(),
+                p50_latency_ms: aggregate.percentile(0.5),
+                p95_latency_ms: aggregate.percentile(0.95),
+            })
+            .collect();
+        models.sort_by_key(|stats| Reverse(stats.requests));
+
+        ServerStats {
+            requests: total.requests,
+            errors: total.errors,
+            error_rate: total.error_rate(),
+            p50_latency_ms: total.percentile(0.5),
+            p95_latency_ms: total.percentile(0.95),
+            models,
+        }
+    }
+}
+
+/// Escapes a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes access log entries as JSON lines and feeds them into the metrics
+#[derive(Debug)]
+pub struct AccessLog {
+    sender: mpsc::UnboundedSender<AccessLogEntry>,
+    metrics: Arc<ServerMetrics>,
+}
+
+impl AccessLog {
+    /// Starts the writer task, which ends once the last handle is dropped
+    pub fn start(log_dir: &Path, metrics: Arc<ServerMetrics>) -> Arc<Self> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_access_log(
+            log_dir.to_path_buf(),
+            receiver,
+            MAX_ACCESS_LOG_SIZE,
+        ));
+        Arc::new(Self { sender, metrics })
+    }
+
+    /// Wraps the response body so the request is logged once the body has been sent
+    pub fn track(
+        self: &Arc<Self>,
+        response: Response<Body>,
+        started: Instant,
+        entry: AccessLogEntry,
+    ) -> Response<Body> {
+        let (parts, body) = response.into_parts();
+        let tracked = TrackedBody {
+            inner: body,
+            access_log: self.clone(),
+            started,
+            first_byte: None,
+            bytes: 0,
+            entry: Some(entry),
+        };
+        Response::from_parts(parts, Body::wrap_stream(tracked))
+    }
+
+    fn finish(&self, entry: AccessLogEntry) {
+        self.metrics.record(&entry);
+        if self.sender.send(entry).is_err() {
+            log::debug!("Access log writer has stopped");
+        }
+    }
+}
+
+async fn open_access_log(path: &Path) -> std::io::Result<(tokio::fs::File, u64)> {
+    let file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    let size = file.metadata().await?.len();
+    Ok((file, size))
+}
+
+/// Appends entries to the access log, moving it aside once it reaches `max_size`
+async fn write_access_log(
+    log_dir: PathBuf,
+    mut receiver: mpsc::UnboundedReceiver<AccessLogEntry>,
+    max_size: u64,
+) {
+    let path = log_dir.join(ACCESS_LOG_FILE);
+    let (mut file, mut size) = match open_access_log(&path).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            log::error!("Failed to open access log {:?}: {}", path, e);
+            return;
+        }
+    };
+
+    while let Some(entry) = receiver.recv().await {
+        let mut line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Failed to serialize access log entry: {}", e);
+                continue;
+            }
+        };
+        line.push('\n');
+        // Flushed right away so nothing is lost when the file is rotated
+        if let Err(e) = file.write_all(line.as_bytes()).await {
+            log::error!("Failed to write access log: {}", e);
+            continue;
+        }
+        if let Err(e) = file.flush().await {
+            log::error!("Failed to write access log: {}", e);
+        }
+        size += line.len() as u64;
+
+        if size >= max_size {
+            drop(file);
+            if let Err(e) = tokio::fs::rename(&path, log_dir.join(ROTATED_ACCESS_LOG_FILE)).await {
+                log::error!("Failed to rotate access log {:?}: {}", path, e);
+            }
+            (file, size) = match open_access_log(&path).await {
+                Ok(opened) => opened,
+                Err(e) => {
+                    log::error!("Failed to open access log {:?}: {}", path, e);
+                    return;
+                }
+            };
+        }
+    }
+}
+
+/// Response body that records timing and size of what was sent to the client
+struct TrackedBody {
+    inner: Body,
+    access_log: Arc<AccessLog>,
+    started: Instant,
+    first_byte: Option<Duration>,
+    bytes: u64,
+    entry: Option<AccessLogEntry>,
+}
+
+impl TrackedBody {
+    fn finish(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.latency_ms = self.started.elapsed().as_millis() as u64;
+            entry.ttfb_ms = self.first_byte.map(|ttfb| ttfb.as_millis() as u64);
+            entry.bytes = self.bytes;
+            self.access_log.finish(entry);
+        }
+    }
+}
+
+impl Stream for TrackedBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_data(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if this.first_byte.is_none() {
+                    this.first_byte = Some(this.started.elapsed());
+                }
+                this.bytes += chunk.len() as u64;
+            }
+            Poll::Ready(None) | Poll::Ready(Some(Err(_))) => this.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for TrackedBody {
+    fn drop(&mut self) {
+        // The client went away before the body was complete
+        self.finish();
+    }
+}
+
+/// Builds the log entry for a request, timing fields are filled in once the body is sent
+pub fn access_log_entry(
+    client: String,
+    method: &str,
+    path: &str,
+    status: u16,
+    info: RequestInfo,
+) -> AccessLogEntry {
+    AccessLogEntry {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default(),
+        client_ip: client,
+        method: method.to_string(),
+        path: path.to_string(),
+        route: OTHER_LABEL,
+        model: info.model,
+        key_name: info.key_name,
+        status,
+        latency_ms: 0,
+        ttfb_ms: None,
+        bytes: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(model: &str, status: u16, latency_ms: u64) -> AccessLogEntry {
+        let info = RequestInfo {
+            model: Some(model.to_string()),
+            key_name: None,
+            denied: None,
+            admitted: false,
+        };
+        let mut entry = access_log_entry(
+            "127.0.0.1".to_string(),
+            "POST",
+            "/v1/chat/completions",
+            status,
+            info,
+        );
+        entry.route = route_label("/v1/chat/completions");
+        entry.latency_ms = latency_ms;
+        entry
+    }
+
+    #[test]
+    fn test_stats_aggregate_per_model() {
+        let metrics = ServerMetrics::default();
+        for latency in 1..=100 {
+            metrics.record(&entry("llama3", 200, latency));
+        }
+        metrics.record(&entry("qwen", 200, 10));
+        metrics.record(&entry("qwen", 502, 10));
+
+        let stats = metrics.stats();
+        assert_eq!(stats.requests, 102);
+        assert_eq!(stats.errors, 1);
+
+        let llama = &stats.models[0];
+        assert_eq!(llama.model, "llama3");
+        assert_eq!(llama.p50_latency_ms, 50);
+        assert_eq!(llama.p95_latency_ms, 95);
+        assert_eq!(llama.error_rate, 0.0);
+
+        let qwen = &stats.models[1];
+        assert_eq!(qwen.requests, 2);
+        assert_eq!(qwen.error_rate, 0.5);
+    }
+
+    #[test]
+    fn test_labels_are_bounded() {
+        let metrics = ServerMetrics::default();
+        metrics.record(&entry("llama3", 200, 10));
+        metrics.record(&entry("llama3", 404, 10));
+        metrics.record(&entry("made-up-model", 404, 10));
+        let mut unknown_route = entry("llama3", 200, 10);
+        unknown_route.route = route_label("/v1/anything/goes");
+        metrics.record(&unknown_route);
+
+        let output = metrics.render_prometheus(0, 0, 0, &HashMap::new());
+        assert!(output.contains(
+            "jan_server_requests_total{path=\"/v1/chat/completions\",model=\"llama3\",status=\"404\"} 1\n"
+        ));
+        assert!(output.contains(
+            "jan_server_requests_total{path=\"/v1/chat/completions\",model=\"other\",status=\"404\"} 1\n"
+        ));
+        assert!(output.contains(
+            "jan_server_requests_total{path=\"other\",model=\"llama3\",status=\"200\"} 1\n"
+        ));
+        assert!(!output.contains("made-up-model"));
+    }
+
+    #[tokio::test]
+    async fn test_access_log_is_rotated() {
+        let dir = std::env::temp_dir().join(format!("jan-access-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let line_size = serde_json::to_string(&entry("llama3", 200, 1))
+            .unwrap()
+            .len() as u64
+            + 1;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        for _ in 0..5 {
+            sender.send(entry("llama3", 200, 1)).unwrap();
+        }
+        drop(sender);
+        write_access_log(dir.clone(), receiver, line_size * 3).await;
+
+        let lines = |file: &str| {
+            std::fs::read_to_string(dir.join(file))
+                .unwrap()
+                .lines()
+                .count()
+        };
+        assert_eq!(lines(ROTATED_ACCESS_LOG_FILE), 3);
+        assert_eq!(lines(ACCESS_LOG_FILE), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let metrics = ServerMetrics::default();
+        metrics.record(&entry("llama3", 200, 10));
+        metrics.record(&entry("llama3", 200, 12));
+        metrics.record_upstream_error("http://127.0.0.1:39291");
+
+        let mcp_connected = HashMap::from([("fetch".to_string(), true)]);
+        let output = metrics.render_prometheus(2, 0, 1, &mcp_connected);
+
+        assert!(output.contains(
+            "jan_server_requests_total{path=\"/v1/chat/completions\",model=\"llama3\",status=\"200\"} 2\n"
+        ));
+        assert!(output.contains("jan_server_streams_in_flight 2\n"));
+        assert!(output
+            .contains("jan_server_upstream_errors_total{upstream=\"http://127.0.0.1:39291\"} 1\n"));
+        assert!(output.contains("jan_cortex_restarts 1\n"));
+        assert!(output.contains("jan_mcp_server_connected{server=\"fetch\"} 1\n"));
+    }
+}
@@ -0,0 +1,2584 @@
+use flate2::read::GzDecoder;
+use futures_util::{FutureExt, StreamExt};
+use hyper::server::accept;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::core::state::ServerHandle;
+
+mod aliases;
+mod anthropic;
+mod api_keys;
+mod audit;
+mod cache;
+mod conversations;
+mod cors;
+mod discovery;
+mod errors;
+mod hooks;
+mod listener;
+mod metrics;
+mod ollama;
+mod queue;
+mod rate_limit;
+mod realtime;
+mod responses;
+mod routing;
+mod shutdown;
+mod sse;
+mod tls;
+mod tools;
+mod translate;
+mod upstream;
+mod validation;
+
+use aliases::ModelAliases;
+use audit::AuditLog;
+use cache::ResponseCache;
+use conversations::{ThreadTarget, ThreadWriter};
+use discovery::{Advertiser, ServiceDetails};
+use errors::ApiError;
+use hooks::HookPipeline;
+use listener::ClientConnection;
+use metrics::{AccessLog, RequestInfo};
+use queue::{Priority, RequestQueue};
+use rate_limit::{RateLimitError, RateLimiter};
+use responses::Exchange;
+use routing::ModelRouter;
+use shutdown::StreamTracker;
+use translate::ForeignApi;
+use upstream::UpstreamPool;
+
+pub use aliases::ModelAlias;
+pub use api_keys::{ApiKey, ApiKeyStore, CreatedApiKey};
+pub use audit::{verify_audit_log, AuditVerification};
+pub use cache::{CacheConfig, CACHE_DIR};
+pub use conversations::ConversationStore;
+pub use cors::CorsConfig;
+pub use discovery::{browse_servers, DiscoveredServer};
+pub use hooks::Hook;
+pub use listener::UNIX_SOCKET_FILE;
+pub use metrics::{ServerMetrics, ServerStats};
+pub use queue::QueueConfig;
+pub use rate_limit::RateLimitConfig;
+pub use routing::ModelRoute;
+pub use shutdown::ShutdownSummary;
+pub use tls::{TlsConfig, TlsIdentity};
+pub use tools::{McpToolsConfig, ToolProvider};
+pub use upstream::UpstreamConfig;
+
+/// Address of the cortex sidecar, always the first upstream in the pool
+const CORTEX_UPSTREAM: &str = "http://127.0.0.1:39291";
+
+/// How often a streaming response checks that its client is still there
+const CLIENT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Settings used to start the proxy server
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub prefix: String,
+    /// Token used to authenticate against the cortex sidecar
+    pub auth_token: String,
+    /// Server-wide key with unrestricted access
+    pub api_key: String,
+    /// Named, scoped keys, shared with the key management commands
+    pub api_keys: Arc<Mutex<ApiKeyStore>>,
+    pub trusted_hosts: Vec<String>,
+    /// Which web pages may call the API from a browser
+    pub cors: CorsConfig,
+    /// Additional upstreams to fail over to when cortex is unavailable
+    pub upstreams: Vec<UpstreamConfig>,
+    /// Models served by a dedicated upstream instead of the pool
+    pub model_routes: Vec<ModelRoute>,
+    /// Other names local models answer to
+    pub model_aliases: Vec<ModelAlias>,
+    pub rate_limits: RateLimitConfig,
+    pub queue: QueueConfig,
+    /// Rewrites applied to request bodies, in order
+    pub hooks: Vec<Hook>,
+    /// Folder the access and audit logs are written to
+    pub log_dir: PathBuf,
+    /// Keep a hash-chained record of who accessed the server
+    pub audit_log: bool,
+    /// Request statistics, shared with the stats command
+    pub metrics: Arc<ServerMetrics>,
+    /// Sidecar restart counter, reported on `/metrics`
+    pub cortex_restart_count: Arc<Mutex<u32>>,
+    /// Connection state of the MCP servers, reported on `/metrics`
+    pub mcp_connected: Arc<Mutex<HashMap<String, bool>>>,
+    /// Serve HTTPS with this certificate instead of plain HTTP
+    pub tls: Option<TlsIdentity>,
+    /// Also listen on this Unix socket, whose file permissions are the access control
+    pub unix_socket: Option<PathBuf>,
+    /// Machine name announced over mDNS when bound to a non-loopback address
+    pub advertise_host: Option<String>,
+    pub response_cache: CacheConfig,
+    /// Folder cached responses are stored in
+    pub cache_dir: PathBuf,
+    /// Thread storage backing `/v1/responses` chains
+    pub conversations: Arc<dyn ConversationStore>,
+    pub mcp_tools: McpToolsConfig,
+    /// MCP tools run for requests that reference them, when enabled
+    pub tool_provider: Arc<dyn ToolProvider>,
+}
+
+/// Settings of a running server that can be changed without restarting it.
+/// Unset fields keep their current value.
+#[derive(Debug, Default)]
+pub struct ServerConfigUpdate {
+    pub prefix: Option<String>,
+    pub api_key: Option<String>,
+    pub trusted_hosts: Option<Vec<String>>,
+    pub model_routes: Option<Vec<ModelRoute>>,
+    pub model_aliases: Option<Vec<ModelAlias>>,
+    /// Request counters and open streams carry over to the new limits
+    pub rate_limits: Option<RateLimitConfig>,
+    /// Running and waiting requests keep their place. When the queue is
+    /// disabled, requests already waiting are still served in turn.
+    pub queue: Option<QueueConfig>,
+    pub hooks: Option<Vec<Hook>>,
+    pub cors: Option<CorsConfig>,
+}
+
+/// A running proxy server and what is needed to reconfigure or shut it down
+pub struct RunningServer {
+    task: JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+    shutdown: oneshot::Sender<()>,
+    streams: Arc<StreamTracker>,
+    config: SharedProxyConfig,
+}
+
+/// Configuration for the proxy server
+#[derive(Clone)]
+struct ProxyConfig {
+    upstreams: Arc<UpstreamPool>,
+    model_router: Arc<ModelRouter>,
+    model_aliases: Arc<ModelAliases>,
+    prefix: String,
+    trusted_hosts: Vec<String>,
+    cors: Arc<CorsConfig>,
+    api_key: String,
+    api_keys: Arc<Mutex<ApiKeyStore>>,
+    rate_limiter: Arc<RateLimiter>,
+    request_queue: Option<Arc<RequestQueue>>,
+    hooks: Arc<HookPipeline>,
+    access_log: Arc<AccessLog>,
+    audit_log: Option<Arc<AuditLog>>,
+    metrics: Arc<ServerMetrics>,
+    cortex_restart_count: Arc<Mutex<u32>>,
+    mcp_connected: Arc<Mutex<HashMap<String, bool>>>,
+    streams: Arc<StreamTracker>,
+    response_cache: Option<Arc<ResponseCache>>,
+    conversations: Arc<dyn ConversationStore>,
+    tool_provider: Option<Arc<dyn ToolProvider>>,
+    max_tool_iterations: u32,
+    /// Set for requests over the Unix socket, which skip host validation and
+    /// don't need a key
+    local_socket: bool,
+}
+
+/// Proxy configuration shared by all connections. Every request works on a
+/// snapshot taken when it arrives, so swapping it only affects new requests.
+type SharedProxyConfig = Arc<RwLock<ProxyConfig>>;
+
+impl ProxyConfig {
+    /// Returns a copy with the update applied
+    fn updated(&self, update: ServerConfigUpdate) -> Self {
+        let mut config = self.clone();
+        if let Some(prefix) = update.prefix {
+            config.prefix = prefix;
+        }
+        if let Some(api_key) = update.api_key {
+            config.api_key = api_key;
+        }
+        if let Some(trusted_hosts) = update.trusted_hosts {
+            config.trusted_hosts = trusted_hosts;
+        }
+        if let Some(model_routes) = update.model_routes {
+            config.model_router = Arc::new(ModelRouter::new(model_routes));
+        }
+        if let Some(model_aliases) = update.model_aliases {
+            config.model_aliases = Arc::new(ModelAliases::new(model_aliases));
+        }
+        if let Some(rate_limits) = update.rate_limits {
+            config.rate_limiter.reconfigure(rate_limits);
+        }
+        if let Some(queue) = update.queue {
+            config.request_queue = match (&config.request_queue, queue.enabled) {
+                (_, false) => None,
+                (Some(current), true) => {
+                    current.reconfigure(&queue);
+                    Some(current.clone())
+                }
+                (None, true) => Some(Arc::new(RequestQueue::new(&queue))),
+            };
+        }
+        if let Some(hooks) = update.hooks {
+            config.hooks = Arc::new(HookPipeline::new(hooks));
+        }
+        if let Some(cors) = update.cors {
+            config.cors = Arc::new(cors);
+        }
+        config
+    }
+}
+
+/// Checks that request paths with the prefix in front are valid URIs
+fn validate_prefix(prefix: &str) -> Result<(), String> {
+    format!("{}/v1/models", prefix)
+        .parse::<hyper::Uri>()
+        .map(|_| ())
+        .map_err(|e| format!("Invalid prefix '{}': {}", prefix, e))
+}
+
+/// The URI of an endpoint under the server prefix
+fn prefixed_uri(prefix: &str, path: &str) -> Result<hyper::Uri, ApiError> {
+    format!("{}{}", prefix, path).parse().map_err(|e| {
+        log::error!("Invalid prefixed path {}{}: {}", prefix, path, e);
+        ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "The server prefix is not a valid path",
+            "server_error",
+            "invalid_prefix",
+        )
+    })
+}
+
+/// Removes a prefix from a path, ensuring proper formatting
+fn remove_prefix(path: &str, prefix: &str) -> String {
+    log::debug!("Processing path: {}, removing prefix: {}", path, prefix);
+
+    if !prefix.is_empty() && path.starts_with(prefix) {
+        let result = path[prefix.len()..].to_string();
+        if result.is_empty() {
+            "/".to_string()
+        } else {
+            result
+        }
+    } else {
+        path.to_string()
+    }
+}
+
+/// Determines the final destination path based on the original request path
+fn get_destination_path(original_path: &str, prefix: &str) -> String {
+    let removed_prefix_path = remove_prefix(original_path, prefix);
+
+    // Special paths don't need the /v1 prefix
+    if !original_path.contains(prefix)
+        || removed_prefix_path.contains("/healthz")
+        || removed_prefix_path.contains("/process")
+    {
+        original_path.to_string()
+    } else {
+        format!("/v1{}", removed_prefix_path)
+    }
+}
+
+/// Prometheus metrics are served at the root whatever the prefix, so the
+/// original path is matched rather than the destination
+fn is_metrics_request(method: &hyper::Method, original_path: &str) -> bool {
+    method == hyper::Method::GET && original_path == "/metrics"
+}
+
+/// Scheduling class of a queued generation. Without a key the app can't be
+/// told from any other client on the network, so only marked keys go first.
+fn queue_priority(api_key: Option<&ApiKey>) -> Priority {
+    if api_key.is_some_and(|key| key.interactive) {
+        Priority::Interactive
+    } else {
+        Priority::Background
+    }
+}
+
+/// Name a client goes by in rate limits, the queue and the logs. Clients of
+/// the Unix socket have no address and share a name of their own, apart
+/// from loopback clients.
+fn client_name(config: &ProxyConfig, remote_addr: SocketAddr) -> String {
+    if config.local_socket {
+        "unix".to_string()
+    } else {
+        remote_addr.ip().to_string()
+    }
+}
+
+/// Creates the full upstream URL for the proxied request
+fn build_upstream_url(upstream: &str, path: &str) -> String {
+    let upstream_clean = upstream.trim_end_matches('/');
+    let path_clean = path.trim_start_matches('/');
+
+    format!("{}/{}", upstream_clean, path_clean)
+}
+
+/// What the logs need to know about a request, taken before it is handled
+struct RequestRecord {
+    started: Instant,
+    method: String,
+    path: String,
+    destination: String,
+    origin: String,
+    client: String,
+    access_log: Arc<AccessLog>,
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+impl RequestRecord {
+    fn new(req: &Request<Body>, config: &ProxyConfig, remote_addr: SocketAddr) -> Self {
+        let path = req.uri().path().to_string();
+        Self {
+            started: Instant::now(),
+            method: req.method().to_string(),
+            destination: get_destination_path(&path, &config.prefix),
+            path,
+            origin: header_value(req.headers(), hyper::header::ORIGIN),
+            client: client_name(config, remote_addr),
+            access_log: config.access_log.clone(),
+            audit_log: config.audit_log.clone(),
+        }
+    }
+
+    /// Writes the audit log entry, the access log entry follows once the
+    /// response has been sent
+    fn finish(self, response: Response<Body>, info: RequestInfo) -> Response<Body> {
+        if let Some(audit_log) = &self.audit_log {
+            let mut entry = audit::audit_entry(
+                self.client.clone(),
+                &self.origin,
+                &self.method,
+                &self.path,
+                response.status().as_u16(),
+            );
+            entry.key_name = info.key_name.clone();
+            entry.model = info.model.clone();
+            entry.denied = info.denied.map(str::to_string);
+            audit_log.record(entry);
+        }
+
+        let mut entry = metrics::access_log_entry(
+            self.client,
+            &self.method,
+            &self.path,
+            response.status().as_u16(),
+            info,
+        );
+        entry.route = metrics::route_label(&self.destination);
+        self.access_log.track(response, self.started, entry)
+    }
+}
+
+/// Handles a request and records it in the access log once the response has been sent
+async fn handle_request(
+    req: Request<Body>,
+    client: Client,
+    config: ProxyConfig,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, hyper::Error> {
+    let record = RequestRecord::new(&req, &config, remote_addr);
+    let destination = record.destination.as_str();
+
+    let mut info = RequestInfo::default();
+    let response = if req.method() == hyper::Method::POST
+        && destination == responses::RESPONSES_PATH
+    {
+        proxy_responses_request(req, client, config, remote_addr, &mut info).await?
+    } else if req.method() == hyper::Method::POST && destination == "/v1/chat/completions" {
+        proxy_chat_request(req, client, config, remote_addr, &mut info).await?
+    } else if req.method() == hyper::Method::GET && destination == realtime::REALTIME_PATH {
+        proxy_realtime_request(req, client, config, remote_addr, &mut info).await?
+    } else {
+        let path = req.uri().path().to_string();
+        match ForeignApi::detect(req.method(), &path, destination) {
+            Some(api) => {
+                proxy_translated_request(api, req, client, config, remote_addr, &mut info).await?
+            }
+            None => proxy_request(req, client, config, remote_addr, &mut info).await?,
+        }
+    };
+    Ok(record.finish(response, info))
+}
+
+/// Serves a foreign client protocol by translating it to and from the OpenAI API
+async fn proxy_translated_request(
+    api: ForeignApi,
+    req: Request<Body>,
+    client: Client,
+    config: ProxyConfig,
+    remote_addr: SocketAddr,
+    info: &mut RequestInfo,
+) -> Result<Response<Body>, hyper::Error> {
+    let (mut parts, body) = req.into_parts();
+    let body_bytes = hyper::body::to_bytes(body).await?;
+
+    let openai_request = if api.has_body() {
+        serde_json::from_slice::<Value>(&body_bytes)
+            .map_err(|e| {
+                ApiError::invalid_request(
+                    format!("We could not parse the JSON body of your request: {}", e),
+                    "invalid_json",
+                )
+            })
+            .and_then(|request| api.translate_request(&request))
+    } else {
+        Ok(Value::Null)
+    };
+    let openai_request = match openai_request {
+        Ok(openai_request) => openai_request,
+        Err(e) => {
+            let host = header_value(&parts.headers, hyper::header::HOST);
+            let origin = header_value(&parts.headers, hyper::header::ORIGIN);
+            let response = error_response(e, &host, &origin, &config.trusted_hosts, &config.cors);
+            return Ok(translate_response(api, response, false).await);
+        }
+    };
+    let is_streaming = openai_request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    // Anthropic clients send their key in `x-api-key`
+    if !parts.headers.contains_key(hyper::header::AUTHORIZATION) {
+        if let Some(api_key) = parts.headers.get("x-api-key").cloned() {
+            let mut authorization = b"Bearer ".to_vec();
+            authorization.extend_from_slice(api_key.as_bytes());
+            if let Ok(authorization) = hyper::header::HeaderValue::from_bytes(&authorization) {
+                parts
+                    .headers
+                    .insert(hyper::header::AUTHORIZATION, authorization);
+            }
+        }
+    }
+    parts.headers.remove(hyper::header::CONTENT_LENGTH);
+    parts.uri = match prefixed_uri(&config.prefix, api.openai_path(&openai_request)) {
+        Ok(uri) => uri,
+        Err(e) => {
+            let host = header_value(&parts.headers, hyper::header::HOST);
+            let origin = header_value(&parts.headers, hyper::header::ORIGIN);
+            let response = error_response(e, &host, &origin, &config.trusted_hosts, &config.cors);
+            return Ok(translate_response(api, response, false).await);
+        }
+    };
+
+    let body = if api.has_body() {
+        Body::from(serde_json::to_vec(&openai_request).unwrap_or_default())
+    } else {
+        Body::empty()
+    };
+    let req = Request::from_parts(parts, body);
+    let response = proxy_request(req, client, config, remote_addr, info).await?;
+    Ok(translate_response(api, response, is_streaming).await)
+}
+
+/// Translates an OpenAI response, error or event stream into the client's protocol
+async fn translate_response(
+    api: ForeignApi,
+    response: Response<Body>,
+    is_streaming: bool,
+) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(hyper::header::CONTENT_LENGTH);
+
+    if parts.status.is_success() && is_streaming {
+        parts.headers.insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static(api.stream_content_type()),
+        );
+        return Response::from_parts(parts, api.translate_stream(body));
+    }
+
+    let mut bytes = hyper::body::to_bytes(body)
+        .await
+        .unwrap_or_default()
+        .to_vec();
+    if is_gzip_encoded(&bytes) {
+        if let Ok(decompressed) = decompress_gzip(&bytes) {
+            bytes = decompressed;
+            parts.headers.remove(hyper::header::CONTENT_ENCODING);
+        }
+    }
+    let translated = if parts.status.is_success() {
+        match serde_json::from_slice::<Value>(&bytes) {
+            Ok(response) => api.translate_response(&response).to_string().into_bytes(),
+            Err(e) => {
+                log::error!("Failed to parse upstream response: {}", e);
+                parts.status = StatusCode::BAD_GATEWAY;
+                api.translate_error(parts.status, b"Invalid response from upstream")
+            }
+        }
+    } else {
+        api.translate_error(parts.status, &bytes)
+    };
+    parts.headers.insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    Response::from_parts(parts, Body::from(translated))
+}
+
+/// Serves the OpenAI Responses API on top of chat completions. Stored responses
+/// are kept as Jan threads, which `previous_response_id` continues.
+async fn proxy_responses_request(
+    req: Request<Body>,
+    client: Client,
+    config: ProxyConfig,
+    remote_addr: SocketAddr,
+    info: &mut RequestInfo,
+) -> Result<Response<Body>, hyper::Error> {
+    let (mut parts, body) = req.into_parts();
+    let body_bytes = hyper::body::to_bytes(body).await?;
+    let host = header_value(&parts.headers, hyper::header::HOST);
+    let origin = header_value(&parts.headers, hyper::header::ORIGIN);
+
+    // Stored responses are only looked up and written for authorized clients
+    if let Err(e) = check_access(&config, &parts.headers, "/v1/chat/completions", info).await {
+        return Ok(error_response(
+            e,
+            &host,
+            &origin,
+            &config.trusted_hosts,
+            &config.cors,
+        ));
+    }
+    let exchange = match serde_json::from_slice::<Value>(&body_bytes) {
+        Ok(request) => Exchange::new(&request, config.conversations.as_ref()).await,
+        Err(e) => Err(ApiError::invalid_request(
+            format!("We could not parse the JSON body of your request: {}", e),
+            "invalid_json",
+        )),
+    };
+    let mut exchange = match exchange {
+        Ok(exchange) => exchange,
+        Err(e) => {
+            return Ok(error_response(
+                e,
+                &host,
+                &origin,
+                &config.trusted_hosts,
+                &config.cors,
+            ))
+        }
+    };
+
+    parts.headers.remove(hyper::header::CONTENT_LENGTH);
+    parts.uri = match prefixed_uri(&config.prefix, "/chat/completions") {
+        Ok(uri) => uri,
+        Err(e) => {
+            return Ok(error_response(
+                e,
+                &host,
+                &origin,
+                &config.trusted_hosts,
+                &config.cors,
+            ))
+        }
+    };
+    let body = Body::from(serde_json::to_vec(exchange.chat_request()).unwrap_or_default());
+    let conversations = config.conversations.clone();
+    let trusted_hosts = config.trusted_hosts.clone();
+    let cors = config.cors.clone();
+    let response = proxy_request(
+        Request::from_parts(parts, body),
+        client,
+        config,
+        remote_addr,
+        info,
+    )
+    .await?;
+    // Errors are already in the OpenAI format the Responses API uses
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+
+    let reply = match exchange.begin(&conversations).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            log::error!("Failed to store response: {}", e);
+            let error = ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to store the response: {}", e),
+                "server_error",
+                "storage_error",
+            );
+            return Ok(error_response(error, &host, &origin, &trusted_hosts, &cors));
+        }
+    };
+
+    let (mut parts, body) = response.into_parts();
+    parts.headers.remove(hyper::header::CONTENT_LENGTH);
+    if exchange.is_streaming() {
+        parts.headers.insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("text/event-stream"),
+        );
+        return Ok(Response::from_parts(
+            parts,
+            exchange.into_stream(body, reply),
+        ));
+    }
+
+    let mut bytes = hyper::body::to_bytes(body).await?.to_vec();
+    if is_gzip_encoded(&bytes) {
+        if let Ok(decompressed) = decompress_gzip(&bytes) {
+            bytes = decompressed;
+            parts.headers.remove(hyper::header::CONTENT_ENCODING);
+        }
+    }
+    let completion = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(completion) => completion,
+        Err(e) => {
+            log::error!("Failed to parse upstream response: {}", e);
+            let error =
+                ApiError::upstream(StatusCode::BAD_GATEWAY, "Invalid response from upstream");
+            return Ok(error_response(error, &host, &origin, &trusted_hosts, &cors));
+        }
+    };
+    let response = exchange.finish(&completion, reply).await;
+    parts.headers.insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    Ok(Response::from_parts(
+        parts,
+        Body::from(response.to_string()),
+    ))
+}
+
+/// Upgrades to a WebSocket on which chat completions are streamed as frames.
+/// Each request on the socket goes through the regular chat completions path,
+/// with its own access and audit log entry.
+async fn proxy_realtime_request(
+    req: Request<Body>,
+    client: Client,
+    config: ProxyConfig,
+    remote_addr: SocketAddr,
+    info: &mut RequestInfo,
+) -> Result<Response<Body>, hyper::Error> {
+    let host = header_value(req.headers(), hyper::header::HOST);
+    let origin = header_value(req.headers(), hyper::header::ORIGIN);
+    let trusted_hosts = config.trusted_hosts.clone();
+    let cors = config.cors.clone();
+
+    if !realtime::is_upgrade(req.headers()) {
+        let error = ApiError::new(
+            StatusCode::UPGRADE_REQUIRED,
+            "This endpoint only accepts WebSocket connections",
+            "invalid_request_error",
+            "upgrade_required",
+        );
+        return Ok(error_response(error, &host, &origin, &trusted_hosts, &cors));
+    }
+    let Some(accept_key) = realtime::accept_key(req.headers()) else {
+        let error =
+            ApiError::invalid_request("Missing Sec-WebSocket-Key header", "missing_websocket_key");
+        return Ok(error_response(error, &host, &origin, &trusted_hosts, &cors));
+    };
+    if !config.local_socket && !is_valid_host(&host, &trusted_hosts) {
+        let error = ApiError::forbidden("Invalid host header", "host_not_allowed");
+        return Ok(error_response(
+            deny(info, error),
+            &host,
+            &origin,
+            &trusted_hosts,
+            &cors,
+        ));
+    }
+
+    // Browsers can't set headers on WebSockets, they pass the key as a subprotocol
+    let mut headers = hyper::HeaderMap::new();
+    for name in [
+        hyper::header::HOST,
+        hyper::header::ORIGIN,
+        hyper::header::USER_AGENT,
+        hyper::header::AUTHORIZATION,
+    ] {
+        if let Some(value) = req.headers().get(&name) {
+            headers.insert(name, value.clone());
+        }
+    }
+    if !headers.contains_key(hyper::header::AUTHORIZATION) {
+        let bearer = realtime::protocol_api_key(req.headers())
+            .and_then(|key| format!("Bearer {}", key).parse().ok());
+        if let Some(bearer) = bearer {
+            headers.insert(hyper::header::AUTHORIZATION, bearer);
+        }
+    }
+    match authenticate(&config, &headers).await {
+        Ok(Some(key)) => {
+            if !key.allows_path("POST", "/v1/chat/completions") {
+                let error = ApiError::forbidden(
+                    "API key is not allowed to access this endpoint",
+                    "endpoint_not_allowed",
+                );
+                return Ok(error_response(
+                    deny(info, error),
+                    &host,
+                    &origin,
+                    &trusted_hosts,
+                    &cors,
+                ));
+            }
+            info.key_name = Some(key.name);
+        }
+        Ok(None) => {}
+        Err(message) => {
+            return Ok(error_response(
+                deny(info, ApiError::unauthorized(message)),
+                &host,
+                &origin,
+                &trusted_hosts,
+                &cors,
+            ));
+        }
+    }
+
+    let uri = match prefixed_uri(&config.prefix, "/chat/completions") {
+        Ok(uri) => uri,
+        Err(e) => return Ok(error_response(e, &host, &origin, &trusted_hosts, &cors)),
+    };
+    let offers_protocol = realtime::offers_realtime_protocol(req.headers());
+    let streams = config.streams.clone();
+    let dispatch: realtime::Dispatch = Arc::new(move |request: Value| {
+        let mut req = Request::new(Body::from(request.to_string()));
+        *req.method_mut() = hyper::Method::POST;
+        *req.uri_mut() = uri.clone();
+        *req.headers_mut() = headers.clone();
+        req.headers_mut().insert(
+            hyper::header::CONTENT_TYPE,
+            hyper::header::HeaderValue::from_static("application/json"),
+        );
+        let (client, config) = (client.clone(), config.clone());
+        Box::pin(async move {
+            // Logged and counted like any other chat completion
+            let record = RequestRecord::new(&req, &config, remote_addr);
+            let mut info = RequestInfo::default();
+            let response = proxy_chat_request(req, client, config, remote_addr, &mut info)
+                .await
+                .unwrap_or_else(|e| {
+                    let error = ApiError::upstream(StatusCode::BAD_GATEWAY, e.to_string());
+                    Response::builder()
+                        .status(error.status)
+                        .body(error.body())
+                        .unwrap()
+                });
+            record.finish(response, info)
+        })
+    });
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(req).await {
+            Ok(upgraded) => {
+                let socket = tokio_tungstenite::WebSocketStream::from_raw_socket(
+                    upgraded,
+                    tokio_tungstenite::tungstenite::protocol::Role::Server,
+                    None,
+                )
+                .await;
+                // Sessions outlive the connection in hyper's eyes, so they are
+                // closed along with the streams on shutdown
+                let stream_guard = streams.track();
+                realtime::run_session(socket, dispatch, stream_guard.cancelled()).await;
+            }
+            Err(e) => log::warn!("WebSocket upgrade from {} failed: {}", remote_addr, e),
+        }
+    });
+
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(hyper::header::UPGRADE, "websocket")
+        .header(hyper::header::CONNECTION, "Upgrade")
+        .header(hyper::header::SEC_WEBSOCKET_ACCEPT, accept_key);
+    if offers_protocol {
+        response = response.header(
+            hyper::header::SEC_WEBSOCKET_PROTOCOL,
+            realtime::REALTIME_PROTOCOL,
+        );
+    }
+    Ok(response.body(Body::empty()).unwrap())
+}
+
+/// Handles chat completions: runs the MCP tool loop for requests that
+/// reference MCP tools and records conversations asked to be kept as a thread
+async fn proxy_chat_request(
+    req: Request<Body>,
+    client: Client,
+    config: ProxyConfig,
+    remote_addr: SocketAddr,
+    info: &mut RequestInfo,
+) -> Result<Response<Body>, hyper::Error> {
+    let (mut parts, body) = req.into_parts();
+    let body_bytes = hyper::body::to_bytes(body).await?;
+    let Ok(mut request) = serde_json::from_slice::<Value>(&body_bytes) else {
+        let req = Request::from_parts(parts, Body::from(body_bytes));
+        return proxy_request(req, client, config, remote_addr, info).await;
+    };
+    let host = header_value(&parts.headers, hyper::header::HOST);
+    let origin = header_value(&parts.headers, hyper::header::ORIGIN);
+    let conversations = config.conversations.clone();
+    let trusted_hosts = config.trusted_hosts.clone();
+    let cors = config.cors.clone();
+
+    let has_store_field = request.get("store").is_some();
+    let target = ThreadTarget::from_request(&parts.headers, &mut request);
+    let uses_tools = tools::references_mcp_tools(&request);
+
+    // Threads and MCP servers are only touched on behalf of authorized clients
+    if target.is_some() || uses_tools {
+        let access = check_access(&config, &parts.headers, "/v1/chat/completions", info).await;
+        if let Err(e) = access {
+            return Ok(error_response(e, &host, &origin, &trusted_hosts, &cors));
+        }
+    }
+    let mut writer = None;
+    if let Some(ThreadTarget::Existing(thread_id)) = &target {
+        match ThreadWriter::open(conversations.clone(), thread_id.clone()).await {
+            Ok(Some(existing)) => writer = Some(existing),
+            Ok(None) => {
+                let error = ApiError::new(
+                    StatusCode::NOT_FOUND,
+                    format!("No thread found with id '{}'.", thread_id),
+                    "invalid_request_error",
+                    "thread_not_found",
+                )
+                .with_param(conversations::THREAD_ID_HEADER);
+                return Ok(error_response(error, &host, &origin, &trusted_hosts, &cors));
+            }
+            Err(e) => {
+                log::error!("Failed to read thread {}: {}", thread_id, e);
+                let error = ApiError::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Failed to read the thread: {}", e),
+                    "server_error",
+                    "storage_error",
+                );
+                return Ok(error_response(error, &host, &origin, &trusted_hosts, &cors));
+            }
+        }
+    }
+
+    let response = if uses_tools {
+        proxy_tool_loop_request(parts, request.clone(), client, config, remote_addr, info).await?
+    } else {
+        let body = if has_store_field {
+            parts.headers.remove(hyper::header::CONTENT_LENGTH);
+            Body::from(serde_json::to_vec(&request).unwrap_or_default())
+        } else {
+            Body::from(body_bytes)
+        };
+        let req = Request::from_parts(parts, body);
+        proxy_request(req, client, config, remote_addr, info).await?
+    };
+    if target.is_none() || !response.status().is_success() {
+        return Ok(response);
+    }
+
+    // The conversation is only recorded once the model server accepted it
+    let writer = match writer {
+        Some(writer) => Ok(writer),
+        None => {
+            let messages = request
+                .get("messages")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            ThreadWriter::create(
+                conversations,
+                request
+                    .get("model")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default(),
+                &conversations::thread_title(&messages),
+                conversations::instructions(&request).as_deref(),
+            )
+            .await
+        }
+    };
+    let writer = match writer {
+        Ok(writer) => writer,
+        Err(e) => {
+            log::error!("Failed to create thread: {}", e);
+            return Ok(response);
+        }
+    };
+    if let Err(e) = conversations::record_request(&writer, &request).await {
+        log::error!("Failed to store request messages: {}", e);
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    if let Ok(thread_id) = hyper::header::HeaderValue::from_str(writer.thread_id()) {
+        parts
+            .headers
+            .insert(conversations::THREAD_ID_HEADER, thread_id);
+    }
+    let is_event_stream = parts
+        .headers
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("text/event-stream"));
+    if is_event_stream {
+        let body = conversations::record_stream(writer, body);
+        return Ok(Response::from_parts(parts, body));
+    }
+
+    let bytes = hyper::body::to_bytes(body).await?;
+    let decompressed = if is_gzip_encoded(&bytes) {
+        decompress_gzip(&bytes).ok()
+    } else {
+        None
+    };
+    let reply = serde_json::from_slice::<Value>(decompressed.as_deref().unwrap_or(&bytes))
+        .ok()
+        .and_then(|completion| completion.pointer("/choices/0/message").cloned());
+    match reply {
+        Some(reply) => {
+            if let Err(e) = writer.append(&reply, None).await {
+                log::error!("Failed to store reply: {}", e);
+            }
+        }
+        None => log::warn!("Reply could not be stored"),
+    }
+    Ok(Response::from_parts(parts, Body::from(bytes)))
+}
+
+/// Executes the MCP tool calls of the model on the server and calls the model
+/// again with their results, until it answers or the iteration limit is hit.
+/// The caller has already checked the client's access.
+async fn proxy_tool_loop_request(
+    parts: hyper::http::request::Parts,
+    mut request: Value,
+    client: Client,
+    config: ProxyConfig,
+    remote_addr: SocketAddr,
+    info: &mut RequestInfo,
+) -> Result<Response<Body>, hyper::Error> {
+    let host = header_value(&parts.headers, hyper::header::HOST);
+    let origin = header_value(&parts.headers, hyper::header::ORIGIN);
+
+    let Some(provider) = config.tool_provider.clone() else {
+        let error = ApiError::invalid_request(
+            "MCP tools are not enabled on this server.",
+            "mcp_tools_disabled",
+        )
+        .with_param("tools");
+        return Ok(error_response(
+            error,
+            &host,
+            &origin,
+            &config.trusted_hosts,
+            &config.cors,
+        ));
+    };
+    let names = match tools::inject_tools(&mut request, provider.as_ref()).await {
+        Ok(names) => names,
+        Err(e) => {
+            return Ok(error_response(
+                e,
+                &host,
+                &origin,
+                &config.trusted_hosts,
+                &config.cors,
+            ))
+        }
+    };
+
+    // Intermediate turns are never shown to the client, the final answer is
+    // replayed as a stream when one was asked for
+    let is_streaming = request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    if let Some(request) = request.as_object_mut() {
+        request.insert("stream".to_string(), Value::Bool(false));
+        request.remove("stream_options");
+    }
+
+    let mut iteration = 0;
+    loop {
+        if iteration == config.max_tool_iterations {
+            request["tool_choice"] = Value::String("none".to_string());
+        }
+
+        let mut builder = Request::builder()
+            .method(parts.method.clone())
+            .uri(parts.uri.clone())
+            .version(parts.version);
+        for (name, value) in parts.headers.iter() {
+            if name != hyper::header::CONTENT_LENGTH {
+                builder = builder.header(name, value);
+            }
+        }
+        let req = builder
+            .body(Body::from(serde_json::to_vec(&request).unwrap_or_default()))
+            .expect("request parts are valid");
+        let response =
+            proxy_request(req, client.clone(), config.clone(), remote_addr, info).await?;
+        if !response.status().is_success() {
+            return Ok(response);
+        }
+
+        let (mut response_parts, body) = response.into_parts();
+        let mut bytes = hyper::body::to_bytes(body).await?.to_vec();
+        if is_gzip_encoded(&bytes) {
+            if let Ok(decompressed) = decompress_gzip(&bytes) {
+                bytes = decompressed;
+                response_parts
+                    .headers
+                    .remove(hyper::header::CONTENT_ENCODING);
+            }
+        }
+        let Ok(completion) = serde_json::from_slice::<Value>(&bytes) else {
+            let error =
+                ApiError::upstream(StatusCode::BAD_GATEWAY, "Invalid response from upstream");
+            return Ok(error_response(
+                error,
+                &host,
+                &origin,
+                &config.trusted_hosts,
+                &config.cors,
+            ));
+        };
+
+        match tools::mcp_tool_calls(&completion, &names) {
+            Some(tool_calls) if iteration < config.max_tool_iterations => {
+                log::info!(
+                    "Running {} MCP tool call(s) for {}",
+                    tool_calls.len(),
+                    remote_addr
+                );
+                let mut messages = vec![completion["choices"][0]["message"].clone()];
+                for tool_call in &tool_calls {
+                    messages.push(tools::run_tool_call(provider.as_ref(), tool_call).await);
+                }
+                if let Some(history) = request.get_mut("messages").and_then(Value::as_array_mut) {
+                    history.extend(messages);
+                }
+                iteration += 1;
+            }
+            _ => {
+                response_parts.headers.remove(hyper::header::CONTENT_LENGTH);
+                let (content_type, body) = match is_streaming
+                    .then(|| cache::replay_as_stream(&bytes))
+                    .flatten()
+                {
+                    Some(events) => ("text/event-stream", events.into_bytes()),
+                    None => ("application/json", bytes),
+                };
+                response_parts.headers.insert(
+                    hyper::header::CONTENT_TYPE,
+                    hyper::header::HeaderValue::from_static(content_type),
+                );
+                return Ok(Response::from_parts(response_parts, Body::from(body)));
+            }
+        }
+    }
+}
+
+/// Reads a header as a string, empty when missing or not valid UTF-8
+fn header_value(headers: &hyper::HeaderMap, name: hyper::header::HeaderName) -> String {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Checks the bearer token, returning the named key it belongs to. The
+/// server-wide key and an unprotected server give unscoped access.
+async fn authenticate(
+    config: &ProxyConfig,
+    headers: &hyper::HeaderMap,
+) -> Result<Option<ApiKey>, String> {
+    let key_store = config.api_keys.lock().await;
+    if config.api_key.is_empty() && key_store.is_empty() {
+        Ok(None)
+    } else if let Some(authorization) = headers.get(hyper::header::AUTHORIZATION) {
+        let auth_str = authorization.to_str().unwrap_or("");
+        let token = auth_str.strip_prefix("Bearer ").unwrap_or("");
+
+        // The server-wide key keeps full access, named keys are scoped
+        if !config.api_key.is_empty() && token == config.api_key {
+            Ok(None)
+        } else {
+            key_store
+                .authenticate(token)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        }
+    } else if config.local_socket {
+        Ok(None)
+    } else {
+        Err("Missing authorization header".to_string())
+    }
+}
+
+/// Checks the host and key of a request the proxy answers itself before
+/// anything is read or stored for it, `path` is the endpoint it ends up at
+async fn check_access(
+    config: &ProxyConfig,
+    headers: &hyper::HeaderMap,
+    path: &str,
+    info: &mut RequestInfo,
+) -> Result<(), ApiError> {
+    let host = header_value(headers, hyper::header::HOST);
+    if !config.local_socket {
+        if host.is_empty() {
+            let error = ApiError::invalid_request("Missing host header", "missing_host_header");
+            return Err(deny(info, error));
+        }
+        if !is_valid_host(&host, &config.trusted_hosts) {
+            let error = ApiError::forbidden("Invalid host header", "host_not_allowed");
+            return Err(deny(info, error));
+        }
+    }
+
+    match authenticate(config, headers).await {
+        Ok(Some(key)) if !key.allows_path("POST", path) => {
+            log::warn!("API key '{}' is not allowed to access {}", key.name, path);
+            let error = ApiError::forbidden(
+                "API key is not allowed to access this endpoint",
+                "endpoint_not_allowed",
+            );
+            Err(deny(info, error))
+        }
+        Ok(key) => {
+            info.key_name = key.map(|key| key.name);
+            Ok(())
+        }
+        Err(message) => Err(deny(info, ApiError::unauthorized(message))),
+    }
+}
+
+/// Handles the proxy request logic
+async fn proxy_request(
+    req: Request<Body>,
+    client: Client,
+    config: ProxyConfig,
+    remote_addr: SocketAddr,
+    info: &mut RequestInfo,
+) -> Result<Response<Body>, hyper::Error> {
+    // Handle OPTIONS requests for CORS preflight
+    log::debug!(
+        "Received request: {} {} {:?} {:?} {:?}",
+        req.method(),
+        req.uri().path(),
+        req.headers().get(hyper::header::HOST),
+        req.headers().get(hyper::header::ORIGIN),
+        req.headers()
+            .get(hyper::header::ACCESS_CONTROL_REQUEST_METHOD)
+    );
+    if req.method() == hyper::Method::OPTIONS {
+        log::debug!(
+            "Handling CORS preflight request from {:?} {:?}",
+            req.headers().get(hyper::header::HOST),
+            req.headers()
+                .get(hyper::header::ACCESS_CONTROL_REQUEST_METHOD)
+        );
+
+        // Get the Host header to validate the target (where request is going)
+        let host = req
+            .headers()
+            .get(hyper::header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        // Get the Origin header for CORS response
+        let origin = req
+            .headers()
+            .get(hyper::header::ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        // Validate requested method
+        let requested_method = req
+            .headers()
+            .get("Access-Control-Request-Method")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        if let Err(error) = config.cors.check_preflight_method(requested_method) {
+            return Ok(preflight_error_response(error));
+        }
+
+        // Check if the host (target) is trusted, but bypass for whitelisted paths
+        let request_path = req.uri().path();
+        let whitelisted_paths = ["/", "/openapi.json", "/favicon.ico"];
+        let is_whitelisted_path = whitelisted_paths.contains(&request_path);
+
+        let is_trusted = if is_whitelisted_path {
+            log::debug!(
+                "CORS preflight: Bypassing host check for whitelisted path: {}",
+                request_path
+            );
+            true
+        } else if !host.is_empty() {
+            log::debug!(
+                "CORS preflight: Host is '{}', trusted hosts: [{}]",
+                host,
+                &config.trusted_hosts.join(", ")
+            );
+            is_valid_host(host, &config.trusted_hosts)
+        } else {
+            log::warn!("CORS preflight: No Host header present");
+            false
+        };
+
+        if !is_trusted {
+            log::warn!(
+                "CORS preflight: Host '{}' not trusted for path '{}'",
+                host,
+                request_path
+            );
+            return Ok(preflight_error_response(deny(
+                info,
+                ApiError::forbidden("Host not allowed", "host_not_allowed"),
+            )));
+        }
+
+        // Get and validate requested headers
+        let requested_headers = req
+            .headers()
+            .get("Access-Control-Request-Headers")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        // Credentials follow the same host check as regular responses
+        let host_trusted = !host.is_empty() && is_valid_host(host, &config.trusted_hosts);
+        let response = match config
+            .cors
+            .preflight(origin, requested_headers, host_trusted)
+        {
+            Ok(response) => response,
+            Err(error) => return Ok(preflight_error_response(error)),
+        };
+
+        log::debug!(
+            "CORS preflight response: host_trusted={}, origin='{}'",
+            is_trusted,
+            origin
+        );
+        return Ok(response.body(Body::empty()).unwrap());
+    }
+
+    // Extract headers early for validation and CORS responses
+    let origin_header = req
+        .headers()
+        .get(hyper::header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let host_header = req
+        .headers()
+        .get(hyper::header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let original_path = req.uri().path();
+    let path = get_destination_path(original_path, &config.prefix);
+    let method = req.method().clone();
+
+    // Verify Host header (check target), but bypass for whitelisted paths
+    let whitelisted_paths = ["/", "/openapi.json", "/favicon.ico"];
+    let is_whitelisted_path = whitelisted_paths.contains(&path.as_str());
+
+    if config.local_socket {
+        log::debug!("Bypassing host validation for Unix socket client");
+    } else if !is_whitelisted_path {
+        if !host_header.is_empty() {
+            if !is_valid_host(&host_header, &config.trusted_hosts) {
+                return Ok(error_response(
+                    deny(
+                        info,
+                        ApiError::forbidden("Invalid host header", "host_not_allowed"),
+                    ),
+                    &host_header,
+                    &origin_header,
+                    &config.trusted_hosts,
+                    &config.cors,
+                ));
+            }
+        } else {
+            return Ok(error_response(
+                deny(
+                    info,
+                    ApiError::invalid_request("Missing host header", "missing_host_header"),
+                ),
+                &host_header,
+                &origin_header,
+                &config.trusted_hosts,
+                &config.cors,
+            ));
+        }
+    } else {
+        log::debug!("Bypassing host validation for whitelisted path: {}", path);
+    }
+
+    // Prometheus metrics are served by the proxy itself, without a key but
+    // only through a trusted host
+    if is_metrics_request(&method, original_path) {
+        let sidecar_restarts = *config.cortex_restart_count.lock().await;
+        let mcp_connected = config.mcp_connected.lock().await.clone();
+        let body = config.metrics.render_prometheus(
+            config.rate_limiter.active_streams(),
+            config
+                .request_queue
+                .as_ref()
+                .map_or(0, |queue| queue.waiting()),
+            sidecar_restarts,
+            &mcp_connected,
+        );
+
+        let mut response = Response::builder().status(StatusCode::OK).header(
+            hyper::header::CONTENT_TYPE,
+            metrics::PROMETHEUS_CONTENT_TYPE,
+        );
+        response = add_cors_headers_with_host_and_origin(
+            response,
+            &host_header,
+            &origin_header,
+            &config.trusted_hosts,
+            &config.cors,
+        );
+        return Ok(response.body(Body::from(body)).unwrap());
+    }
+
+    // Skip authorization check for whitelisted paths and requests already
+    // admitted, whose key is known and whose scope didn't change
+    let admitted = info.admitted;
+    let mut api_key: Option<ApiKey> = None;
+    if admitted {
+        log::debug!("Request to {} was already admitted", path);
+    } else if !is_whitelisted_path {
+        match authenticate(&config, req.headers()).await {
+            Ok(key) => api_key = key,
+            Err(message) => {
+                return Ok(error_response(
+                    deny(info, ApiError::unauthorized(message)),
+                    &host_header,
+                    &origin_header,
+                    &config.trusted_hosts,
+                    &config.cors,
+                ));
+            }
+        }
+
+        if let Some(key) = &api_key {
+            if !key.allows_path(method.as_str(), &path) {
+                log::warn!("API key '{}' is not allowed to access {}", key.name, path);
+                return Ok(error_response(
+                    deny(
+                        info,
+                        ApiError::forbidden(
+                            "API key is not allowed to access this endpoint",
+                            "endpoint_not_allowed",
+                        ),
+                    ),
+                    &host_header,
+                    &origin_header,
+                    &config.trusted_hosts,
+                    &config.cors,
+                ));
+            }
+        }
+    } else {
+        log::debug!(
+            "Bypassing authorization check for whitelisted path: {}",
+            path
+        );
+    }
+
+    // Enforce request rate limits for the key and client IP
+    if !admitted {
+        info.key_name = api_key.as_ref().map(|key| key.name.clone());
+    }
+    let key_name = info.key_name.clone();
+    let client_id = client_name(&config, remote_addr);
+    if !is_whitelisted_path && !admitted {
+        if let Err(e) = config
+            .rate_limiter
+            .check_request(key_name.as_deref(), &client_id)
+        {
+            log::warn!("Rate limit exceeded for {}: {}", client_id, e);
+            return Ok(rate_limited_response(
+                &e,
+                &host_header,
+                &origin_header,
+                &config.trusted_hosts,
+                &config.cors,
+            ));
+        }
+        info.admitted = true;
+    }
+
+    // Block access to /configs endpoint
+    if path.contains("/configs") {
+        return Ok(error_response(
+            ApiError::not_found(),
+            &host_header,
+            &origin_header,
+            &config.trusted_hosts,
+            &config.cors,
+        ));
+    }
+
+    let (parts, body) = req.into_parts();
+
+    // Buffer the body so the request can be replayed against another upstream
+    let mut body_bytes = hyper::body::to_bytes(body).await?;
+    if let Some(rewritten) = config.hooks.apply(&parts.method, &path, &body_bytes) {
+        body_bytes = rewritten.into();
+    }
+    if parts.method == hyper::Method::POST {
+        if let Some(rewritten) = config.model_aliases.rewrite_request(&body_bytes) {
+            body_bytes = rewritten.into();
+        }
+
+        if let Err(e) = validation::validate_request(&path, &body_bytes) {
+            log::debug!("Rejected invalid request to {}: {}", path, e.message);
+            return Ok(error_response(
+                e,
+                &host_header,
+                &origin_header,
+                &config.trusted_hosts,
+                &config.cors,
+            ));
+        }
+    }
+    let request_json: Option<Value> = serde_json::from_slice(&body_bytes).ok();
+    let request_model = request_json.as_ref().and_then(routing::extract_model);
+    info.model = request_model.clone();
+    let is_streaming = request_json
+        .as_ref()
+        .and_then(|json| json.get("stream"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    // Enforce the key's model scope
+    if let (Some(key), Some(model)) = (&api_key, &request_model) {
+        if !key.allows_model(model) {
+            log::warn!(
+                "API key '{}' is not allowed to use model {}",
+                key.name,
+                model
+            );
+            return Ok(error_response(
+                deny(
+                    info,
+                    ApiError::forbidden(
+                        format!("API key is not allowed to use model '{}'", model),
+                        "model_not_allowed",
+                    )
+                    .with_param("model"),
+                ),
+                &host_header,
+                &origin_header,
+                &config.trusted_hosts,
+                &config.cors,
+            ));
+        }
+    }
+
+    // Deterministic requests are answered from the response cache when possible
+    let cache_key = match (&config.response_cache, &request_json) {
+        (Some(_), Some(json)) if method == hyper::Method::POST => cache::cache_key(&path, json),
+        _ => None,
+    };
+    if let (Some(cache), Some(key)) = (&config.response_cache, &cache_key) {
+        if let Some(cached) = cache.get(key).await {
+            let replay = if is_streaming {
+                cache::replay_as_stream(&cached)
+                    .map(|events| ("text/event-stream", Body::from(events)))
+            } else {
+                Some(("application/json", Body::from(cached)))
+            };
+            if let Some((content_type, body)) = replay {
+                log::debug!("Serving {} from the response cache", path);
+                let mut response = Response::builder()
+                    .status(StatusCode::OK)
+                    .header(hyper::header::CONTENT_TYPE, content_type)
+                    .header(cache::CACHE_HEADER, "HIT");
+                response = add_cors_headers_with_host_and_origin(
+                    response,
+                    &host_header,
+                    &origin_header,
+                    &config.trusted_hosts,
+                    &config.cors,
+                );
+                return Ok(response.body(body).unwrap());
+            }
+        }
+    }
+
+    // Streaming responses hold a slot until the client has received the whole stream
+    let stream_permit = if is_streaming {
+        match config
+            .rate_limiter
+            .acquire_stream(key_name.as_deref(), &client_id)
+        {
+            Ok(permit) => Some(permit),
+            Err(e) => {
+                log::warn!("Stream limit exceeded for {}: {}", client_id, e);
+                return Ok(rate_limited_response(
+                    &e,
+                    &host_header,
+                    &origin_header,
+                    &config.trusted_hosts,
+                    &config.cors,
+                ));
+            }
+        }
+    } else {
+        None
+    };
+
+    // Requests for a routed model go to their dedicated upstream, everything else to the pool
+    let routed_upstream = match &request_model {
+        Some(model) if routing::is_model_routed_path(&path) => {
+            let upstream = config.model_router.resolve(model);
+            if let Some(upstream) = &upstream {
+                log::debug!("Routing model '{}' to {}", model, upstream.url);
+            }
+            upstream
+        }
+        _ => None,
+    };
+
+    // Generations on the local upstream wait for their turn, interactive keys
+    // first and otherwise taking turns between keys and clients
+    let mut queue_position = None;
+    let queue_slot = match &config.request_queue {
+        Some(queue)
+            if !admitted && routed_upstream.is_none() && queue::is_generation_path(&path) =>
+        {
+            let client = match &key_name {
+                Some(name) => format!("key:{}", name),
+                None => format!("client:{}", client_id),
+            };
+            match queue.enter(client, queue_priority(api_key.as_ref())).await {
+                Ok((slot, position)) => {
+                    queue_position = Some(position);
+                    Some(slot)
+                }
+                Err(e) => {
+                    log::warn!("Request queue full, rejecting {}", path);
+                    let mut response = error_response(
+                        ApiError::new(
+                            StatusCode::TOO_MANY_REQUESTS,
+                            e.to_string(),
+                            "requests",
+                            "queue_full",
+                        ),
+                        &host_header,
+                        &origin_header,
+                        &config.trusted_hosts,
+                        &config.cors,
+                    );
+                    response.headers_mut().insert(
+                        hyper::header::RETRY_AFTER,
+                        hyper::header::HeaderValue::from(1),
+                    );
+                    return Ok(response);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let candidates = match routed_upstream {
+        Some(upstream) => vec![upstream],
+        None if upstream::is_failover_path(&path) => config.upstreams.candidates(),
+        // Model management and downloads are cortex's own API
+        None => vec![config.upstreams.primary()],
+    };
+    let mut upstream_result = None;
+
+    for (index, upstream) in candidates.iter().enumerate() {
+        let has_fallback = index + 1 < candidates.len();
+
+        // Build the outbound request
+        let upstream_url = build_upstream_url(&upstream.url, &path);
+        log::debug!("Proxying request to: {}", upstream_url);
+
+        let mut outbound_req = client.request(method.clone(), &upstream_url);
+
+        // Copy original headers
+        for (name, value) in parts.headers.iter() {
+            // Skip host & authorization header, the length is set from the (possibly rewritten) body
+            if name != hyper::header::HOST
+                && name != hyper::header::AUTHORIZATION
+                && name != hyper::header::CONTENT_LENGTH
+            {
+                outbound_req = outbound_req.header(name, value);
+            }
+        }
+
+        // Add authorization header
+        if let Some(api_key) = &upstream.api_key {
+            outbound_req = outbound_req.header("Authorization", format!("Bearer {}", api_key));
+        }
+
+        match outbound_req.body(body_bytes.clone()).send().await {
+            Ok(response) if response.status().is_server_error() && has_fallback => {
+                log::warn!(
+                    "Upstream {} returned {}, failing over",
+                    upstream.url,
+                    response.status()
+                );
+                config.metrics.record_upstream_error(&upstream.url);
+                upstream.set_healthy(false);
+            }
+            Ok(response) => {
+                if response.status().is_server_error() {
+                    config.metrics.record_upstream_error(&upstream.url);
+                }
+                upstream_result = Some(Ok((response, upstream.clone())));
+                break;
+            }
+            Err(e) if e.is_connect() => {
+                log::warn!("Failed to connect to upstream {}: {}", upstream.url, e);
+                config.metrics.record_upstream_error(&upstream.url);
+                upstream.set_healthy(false);
+                upstream_result = Some(Err(e));
+            }
+            Err(e) => {
+                config.metrics.record_upstream_error(&upstream.url);
+                upstream_result = Some(Err(e));
+                break;
+            }
+        }
+    }
+
+    // Handle the response from the upstream that answered
+    match upstream_result.expect("upstream pool is never empty") {
+        Ok((response, upstream)) => {
+            let status = response.status();
+            log::debug!("Received response with status: {}", status);
+
+            let mut builder = Response::builder().status(status);
+            if let Some(position) = queue_position {
+                builder = builder.header(queue::QUEUE_POSITION_HEADER, position);
+            }
+
+            // Copy response headers, excluding CORS headers and Content-Length to avoid conflicts
+            for (name, value) in response.headers() {
+                // Skip CORS headers from upstream to avoid duplicates
+                // Skip Content-Length header when filtering models response to avoid mismatch
+                if !is_cors_header(name.as_str()) && name != hyper::header::CONTENT_LENGTH {
+                    builder = builder.header(name, value);
+                }
+            }
+
+            // Add our own CORS headers
+            builder = add_cors_headers_with_host_and_origin(
+                builder,
+                &host_header,
+                &origin_header,
+                &config.trusted_hosts,
+                &config.cors,
+            );
+
+            // Error bodies are small, buffer them to return OpenAI-style errors
+            if status.is_client_error() || status.is_server_error() {
+                let bytes = match response.bytes().await {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        log::error!("Failed to read error response body: {}", e);
+                        return Ok(error_response(
+                            ApiError::upstream(StatusCode::BAD_GATEWAY, e.to_string()),
+                            &host_header,
+                            &origin_header,
+                            &config.trusted_hosts,
+                            &config.cors,
+                        ));
+                    }
+                };
+                let normalized = if is_gzip_encoded(&bytes) {
+                    None
+                } else {
+                    errors::normalize_upstream_error(status, &bytes)
+                };
+                let body = match normalized {
+                    Some(normalized) => {
+                        if let Some(headers) = builder.headers_mut() {
+                            headers.insert(
+                                hyper::header::CONTENT_TYPE,
+                                hyper::header::HeaderValue::from_static("application/json"),
+                            );
+                        }
+                        Body::from(normalized)
+                    }
+                    None => Body::from(bytes),
+                };
+                return Ok(builder.body(body).unwrap());
+            }
+
+            // Handle streaming vs non-streaming responses
+            if path.contains("/models") && method == hyper::Method::GET {
+                // For /models endpoint, we need to buffer and filter the response
+                match response.bytes().await {
+                    Ok(bytes) => match filter_models_response(&bytes, &config.model_aliases) {
+                        Ok(filtered_bytes) => Ok(builder.body(Body::from(filtered_bytes)).unwrap()),
+                        Err(e) => {
+                            log::warn!(
+                                "Failed to filter models response: {}, returning original",
+                                e
+                            );
+                            Ok(builder.body(Body::from(bytes)).unwrap())
+                        }
+                    },
+                    Err(e) => {
+                        log::error!("Failed to read response body: {}", e);
+                        Ok(error_response(
+                            ApiError::upstream(
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                "Error reading upstream response",
+                            ),
+                            &host_header,
+                            &origin_header,
+                            &config.trusted_hosts,
+                            &config.cors,
+                        ))
+                    }
+                }
+            } else {
+                // Successful uncompressed responses to cacheable requests are stored once complete
+                let cache_entry = match (&config.response_cache, cache_key) {
+                    (Some(cache), Some(key))
+                        if status.is_success()
+                            && !response
+                                .headers()
+                                .contains_key(hyper::header::CONTENT_ENCODING) =>
+                    {
+                        builder = builder.header(cache::CACHE_HEADER, "MISS");
+                        Some((cache.clone(), key))
+                    }
+                    _ => None,
+                };
+
+                // For streaming endpoints (like chat completions), we need to collect and forward the stream
+                let mut stream = response.bytes_stream();
+                let (mut sender, body) = hyper::Body::channel();
+                let stream_guard = config.streams.track();
+
+                // Spawn a task to forward the stream
+                tokio::spawn(async move {
+                    // Release the stream slot only once forwarding has finished
+                    let _stream_permit = stream_permit;
+                    let _queue_slot = queue_slot;
+                    let mut received = Vec::new();
+                    let mut completed = false;
+                    let mut abandoned = false;
+                    let mut completion_id = None;
+                    // Notices a client that left while the upstream is quiet,
+                    // e.g. still processing a long prompt
+                    let mut disconnect_check = tokio::time::interval(CLIENT_CHECK_INTERVAL);
+                    loop {
+                        let chunk_result = tokio::select! {
+                            chunk_result = stream.next() => chunk_result,
+                            _ = disconnect_check.tick() => {
+                                if is_client_gone(&mut sender) {
+                                    abandoned = true;
+                                    break;
+                                }
+                                continue;
+                            }
+                            _ = stream_guard.cancelled() => {
+                                log::debug!("Stream cancelled by server shutdown");
+                                sender.abort();
+                                break;
+                            }
+                        };
+                        let Some(chunk_result) = chunk_result else {
+                            completed = true;
+                            break;
+                        };
+                        match chunk_result {
+                            Ok(chunk) => {
+                                if cache_entry.is_some() {
+                                    received.extend_from_slice(&chunk);
+                                }
+                                if completion_id.is_none() && upstream.can_cancel() {
+                                    completion_id = upstream::stream_completion_id(&chunk);
+                                }
+                                if sender.send_data(chunk).await.is_err() {
+                                    abandoned = true;
+                                    break;
+                                }
+                            }
+                            Err(e) => {
+                                log::error!("Stream error: {}", e);
+                                break;
+                            }
+                        }
+                    }
+
+                    if abandoned {
+                        log::debug!(
+                            "Client disconnected during streaming, aborting upstream request"
+                        );
+                        // Dropping the response closes the upstream connection
+                        drop(stream);
+                        if let Some(completion_id) = completion_id {
+                            upstream.cancel(&client, &completion_id).await;
+                        }
+                    }
+
+                    if let (Some((cache, key)), true) = (cache_entry, completed) {
+                        let response = if is_streaming {
+                            cache::assemble_stream(&received)
+                        } else {
+                            serde_json::from_slice::<Value>(&received)
+                                .ok()
+                                .map(|_| received)
+                        };
+                        if let Some(response) = response {
+                            cache.put(&key, &response).await;
+                        }
+                    }
+                });
+
+                Ok(builder.body(body).unwrap())
+            }
+        }
+        Err(e) => {
+            log::error!("Proxy request failed: {}", e);
+            Ok(error_response(
+                ApiError::upstream(StatusCode::BAD_GATEWAY, format!("Upstream error: {}", e)),
+                &host_header,
+                &origin_header,
+                &config.trusted_hosts,
+                &config.cors,
+            ))
+        }
+    }
+}
+
+/// Whether the client stopped reading a streamed response body
+fn is_client_gone(sender: &mut hyper::body::Sender) -> bool {
+    matches!(
+        futures_util::future::poll_fn(|cx| sender.poll_ready(cx)).now_or_never(),
+        Some(Err(_))
+    )
+}
+
+/// Notes for the audit log that access was denied
+fn deny(info: &mut RequestInfo, error: ApiError) -> ApiError {
+    info.denied = Some(error.code);
+    error
+}
+
+/// Builds an OpenAI-style JSON error response with CORS headers
+fn error_response(
+    error: ApiError,
+    host: &str,
+    origin: &str,
+    trusted_hosts: &[String],
+    cors: &CorsConfig,
+) -> Response<Body> {
+    let mut response = Response::builder()
+        .status(error.status)
+        .header(hyper::header::CONTENT_TYPE, "application/json");
+    response = add_cors_headers_with_host_and_origin(response, host, origin, trusted_hosts, cors);
+    response.body(error.body()).unwrap()
+}
+
+/// Builds a rejected CORS preflight response, which carries no CORS headers
+fn preflight_error_response(error: ApiError) -> Response<Body> {
+    Response::builder()
+        .status(error.status)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(error.body())
+        .unwrap()
+}
+
+/// Builds the 429 response for a request rejected by the rate limiter
+fn rate_limited_response(
+    error: &RateLimitError,
+    host: &str,
+    origin: &str,
+    trusted_hosts: &[String],
+    cors: &CorsConfig,
+) -> Response<Body> {
+    let mut response = error_response(
+        ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            error.to_string(),
+            "requests",
+            "rate_limit_exceeded",
+        ),
+        host,
+        origin,
+        trusted_hosts,
+        cors,
+    );
+    response.headers_mut().insert(
+        hyper::header::RETRY_AFTER,
+        hyper::header::HeaderValue::from(error.retry_after_secs()),
+    );
+    response
+}
+
+/// Checks if the byte array starts with gzip magic number
+fn is_gzip_encoded(bytes: &[u8]) -> bool {
+    bytes.len() >= 2 && bytes[0] == 0x1f && bytes[1] == 0x8b
+}
+
+/// Decompresses gzip-encoded bytes
+fn decompress_gzip(bytes: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut decoder = GzDecoder::new(bytes);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+/// Compresses bytes using gzip
+fn compress_gzip(bytes: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    let compressed = encoder.finish()?;
+    Ok(compressed)
+}
+
+/// Filters models response to keep only models with status "downloaded", and
+/// lists the aliases of those models
+fn filter_models_response(
+    bytes: &[u8],
+    aliases: &ModelAliases,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    // Try to decompress if it's gzip-encoded
+    let decompressed_bytes = if is_gzip_encoded(bytes) {
+        log::debug!("Response is gzip-encoded, decompressing...");
+        decompress_gzip(bytes)?
+    } else {
+        bytes.to_vec()
+    };
+
+    let response_text = std::str::from_utf8(&decompressed_bytes)?;
+    let mut response_json: Value = serde_json::from_str(response_text)?;
+
+    // Check if this is a ListModelsResponseDto format with data array
+    if let Some(data_array) = response_json.get_mut("data") {
+        if let Some(models) = data_array.as_array_mut() {
+            // Keep only models where status == "downloaded"
+            models.retain(|model| {
+                if let Some(status) = model.get("status") {
+                    if let Some(status_str) = status.as_str() {
+                        status_str == "downloaded"
+                    } else {
+                        false // Remove models without string status
+                    }
+                } else {
+                    false // Remove models without status field
+                }
+            });
+            log::debug!(
+                "Filtered models response: {} downloaded models remaining",
+                models.len()
+            );
+            aliases.extend_models(models);
+        }
+    } else if response_json.is_array() {
+        // Handle direct array format
+        if let Some(models) = response_json.as_array_mut() {
+            models.retain(|model| {
+                if let Some(status) = model.get("status") {
+                    if let Some(status_str) = status.as_str() {
+                        status_str == "downloaded"
+                    } else {
+                        false // Remove models without string status
+                    }
+                } else {
+                    false // Remove models without status field
+                }
+            });
+            log::debug!(
+                "Filtered models response: {} downloaded models remaining",
+                models.len()
+            );
+            aliases.extend_models(models);
+        }
+    }
+
+    let filtered_json = serde_json::to_vec(&response_json)?;
+
+    // If original was gzip-encoded, re-compress the filtered response
+    if is_gzip_encoded(bytes) {
+        log::debug!("Re-compressing filtered response with gzip");
+        compress_gzip(&filtered_json)
+    } else {
+        Ok(filtered_json)
+    }
+}
+
+/// Checks if a header is a CORS-related header that should be filtered out from upstream responses
+fn is_cors_header(header_name: &str) -> bool {
+    let header_lower = header_name.to_lowercase();
+    header_lower.starts_with("access-control-")
+}
+
+/// Adds CORS headers to a response builder using host for validation and origin for response
+fn add_cors_headers_with_host_and_origin(
+    builder: hyper::http::response::Builder,
+    host: &str,
+    origin: &str,
+    trusted_hosts: &[String],
+    cors: &CorsConfig,
+) -> hyper::http::response::Builder {
+    // Check if host (target) is trusted - this is what we validate
+    let is_trusted = if !host.is_empty() {
+        is_valid_host(host, trusted_hosts)
+    } else {
+        false // Host is required for validation
+    };
+
+    cors.apply(builder, origin, is_trusted)
+}
+
+// Validates if the host header is allowed
+fn is_valid_host(host: &str, trusted_hosts: &[String]) -> bool {
+    if host.is_empty() {
+        return false;
+    }
+
+    let host_without_port = if host.starts_with('[') {
+        host.split(']')
+            .next()
+            .unwrap_or(host)
+            .trim_start_matches('[')
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+    let default_valid_hosts = ["localhost", "127.0.0.1", "0.0.0.0"];
+
+    // Check default valid hosts (host part only)
+    if default_valid_hosts
+        .iter()
+        .any(|&valid| host_without_port.to_lowercase() == valid.to_lowercase())
+    {
+        return true;
+    }
+
+    // Check trusted hosts - support both full host:port and host-only formats
+    trusted_hosts.iter().any(|valid| {
+        let host_lower = host.to_lowercase();
+        let valid_lower = valid.to_lowercase();
+
+        // First check exact match (including port)
+        if host_lower == valid_lower {
+            return true;
+        }
+
+        // Then check host part only (without port)
+        let valid_without_port = if valid.starts_with('[') {
+            valid
+                .split(']')
+                .next()
+                .unwrap_or(valid)
+                .trim_start_matches('[')
+        } else {
+            valid.split(':').next().unwrap_or(valid)
+        };
+
+        host_without_port.to_lowercase() == valid_without_port.to_lowercase()
+    })
+}
+
+pub async fn is_server_running(server_handle: Arc<Mutex<Option<ServerHandle>>>) -> bool {
+    let handle_guard = server_handle.lock().await;
+
+    if handle_guard.is_some() {
+        true
+    } else {
+        false
+    }
+}
+
+/// Starts the proxy server
+pub async fn start_server(
+    server_handle: Arc<Mutex<Option<ServerHandle>>>,
+    server_config: ServerConfig,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    // Check if server is already running
+    let mut handle_guard = server_handle.lock().await;
+    if handle_guard.is_some() {
+        return Err("Server is already running".into());
+    }
+
+    validate_prefix(&server_config.prefix)?;
+
+    // Create server address
+    let addr: SocketAddr = format!("{}:{}", server_config.host, server_config.port)
+        .parse()
+        .map_err(|e| format!("Invalid address: {}", e))?;
+
+    // The cortex sidecar is always part of the pool, extra upstreams are failover targets
+    let mut upstreams = vec![UpstreamConfig::cortex(
+        CORTEX_UPSTREAM,
+        server_config.auth_token,
+    )];
+    upstreams.extend(server_config.upstreams);
+    let upstream_pool = Arc::new(UpstreamPool::new(upstreams));
+
+    let response_cache = if server_config.response_cache.enabled {
+        let cache = ResponseCache::open(&server_config.cache_dir, &server_config.response_cache)
+            .map_err(|e| format!("Failed to open response cache: {}", e))?;
+        Some(Arc::new(cache))
+    } else {
+        None
+    };
+
+    // Configure proxy settings
+    let config = ProxyConfig {
+        upstreams: upstream_pool.clone(),
+        model_router: Arc::new(ModelRouter::new(server_config.model_routes)),
+        model_aliases: Arc::new(ModelAliases::new(server_config.model_aliases)),
+        prefix: server_config.prefix,
+        api_key: server_config.api_key,
+        api_keys: server_config.api_keys,
+        rate_limiter: Arc::new(RateLimiter::new(server_config.rate_limits)),
+        request_queue: server_config
+            .queue
+            .enabled
+            .then(|| Arc::new(RequestQueue::new(&server_config.queue))),
+        hooks: Arc::new(HookPipeline::new(server_config.hooks)),
+        access_log: AccessLog::start(&server_config.log_dir, server_config.metrics.clone()),
+        audit_log: server_config
+            .audit_log
+            .then(|| AuditLog::start(&server_config.log_dir)),
+        metrics: server_config.metrics,
+        cortex_restart_count: server_config.cortex_restart_count,
+        mcp_connected: server_config.mcp_connected,
+        trusted_hosts: server_config.trusted_hosts,
+        cors: Arc::new(server_config.cors),
+        streams: Arc::new(StreamTracker::default()),
+        response_cache,
+        conversations: server_config.conversations,
+        tool_provider: server_config
+            .mcp_tools
+            .enabled
+            .then_some(server_config.tool_provider),
+        max_tool_iterations: server_config.mcp_tools.max_iterations,
+        local_socket: false,
+    };
+    let streams = config.streams.clone();
+    let config: SharedProxyConfig = Arc::new(RwLock::new(config));
+    let shared_config = config.clone();
+
+    // Create HTTP client with longer timeout for streaming
+    let client = Client::builder()
+        .timeout(std::time::Duration::from_secs(300)) // 5 minutes for streaming
+        .pool_max_idle_per_host(10)
+        .pool_idle_timeout(std::time::Duration::from_secs(30))
+        .build()?;
+
+    let health_checks = upstream_pool.run_health_checks(client.clone());
+    let advertise_client = client.clone();
+    let tls_acceptor = server_config
+        .tls
+        .map(TlsIdentity::into_acceptor)
+        .transpose()?;
+
+    // Create service handler
+    let make_svc = make_service_fn(move |conn: &ClientConnection| {
+        let client = client.clone();
+        let config = config.clone();
+        let remote_addr = conn.remote_addr();
+        let local_socket = conn.is_local_socket();
+
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let mut config = config.read().unwrap().clone();
+                config.local_socket = local_socket;
+                handle_request(req, client.clone(), config, remote_addr)
+            }))
+        }
+    });
+
+    // Create and start the server
+    let tcp_listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+    let scheme = if tls_acceptor.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    #[allow(unused_mut)]
+    let mut incoming = listener::incoming(tcp_listener, tls_acceptor);
+    if let Some(path) = &server_config.unix_socket {
+        #[cfg(unix)]
+        {
+            let unix_incoming = listener::incoming_unix(path)
+                .map_err(|e| format!("Failed to listen on {:?}: {}", path, e))?;
+            incoming = Box::pin(futures_util::stream::select(incoming, unix_incoming));
+            log::info!("Proxy server listening on {:?}", path);
+        }
+        #[cfg(not(unix))]
+        log::warn!(
+            "Unix sockets are not supported on this platform, not listening on {:?}",
+            path
+        );
+    }
+
+    // Servers reachable from other machines are announced on the local network
+    let advertiser = match &server_config.advertise_host {
+        Some(host) if discovery::is_advertised(&addr) => {
+            match Advertiser::new(host, addr, scheme) {
+                Ok(advertiser) => Some(advertiser),
+                Err(e) => {
+                    log::warn!("Failed to start mDNS advertisement: {}", e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let advertisement = advertise_server(advertiser, shared_config.clone(), advertise_client);
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let server = Server::builder(accept::from_stream(incoming))
+        .serve(make_svc)
+        .with_graceful_shutdown(async {
+            shutdown_rx.await.ok();
+        });
+    log::info!("Proxy server started on {}://{}", scheme, addr);
+
+    // Spawn server task
+    let server_task = tokio::spawn(async move {
+        // Health checks share the server task so stopping the server stops both
+        tokio::select! {
+            result = server => {
+                if let Err(e) = result {
+                    log::error!("Server error: {}", e);
+                    return Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>);
+                }
+            }
+            _ = health_checks => {}
+            _ = advertisement => {}
+        }
+        Ok(())
+    });
+
+    *handle_guard = Some(RunningServer {
+        task: server_task,
+        shutdown: shutdown_tx,
+        streams,
+        config: shared_config,
+    });
+    Ok(true)
+}
+
+/// Keeps the mDNS announcement of the server up to date until the server stops
+async fn advertise_server(
+    advertiser: Option<Advertiser>,
+    config: SharedProxyConfig,
+    client: Client,
+) {
+    let Some(mut advertiser) = advertiser else {
+        return std::future::pending().await;
+    };
+    let mut models = 0;
+    loop {
+        let config = config.read().unwrap().clone();
+        if let Some(count) = count_models(&client, &config).await {
+            models = count;
+        }
+        let key_required = !config.api_key.is_empty() || !config.api_keys.lock().await.is_empty();
+        let details = ServiceDetails {
+            prefix: config.prefix.clone(),
+            models,
+            key_required,
+        };
+        if let Err(e) = advertiser.update(details) {
+            log::warn!("Failed to advertise the server: {}", e);
+        }
+        tokio::time::sleep(discovery::REFRESH_INTERVAL).await;
+    }
+}
+
+/// Number of downloaded models the primary upstream serves
+async fn count_models(client: &Client, config: &ProxyConfig) -> Option<usize> {
+    let upstream = config.upstreams.candidates().into_iter().next()?;
+    let mut request = client.get(build_upstream_url(&upstream.url, "/v1/models"));
+    if let Some(api_key) = &upstream.api_key {
+        request = request.header("Authorization", format!("Bearer {}", api_key));
+    }
+    let bytes = request.send().await.ok()?.bytes().await.ok()?;
+    let filtered = filter_models_response(&bytes, &ModelAliases::default()).ok()?;
+    let filtered = if is_gzip_encoded(&filtered) {
+        decompress_gzip(&filtered).ok()?
+    } else {
+        filtered
+    };
+    let models: Value = serde_json::from_slice(&filtered).ok()?;
+    models
+        .get("data")
+        .unwrap_or(&models)
+        .as_array()
+        .map(Vec::len)
+}
+
+/// Applies new settings to the running server. Requests already being handled
+/// finish with the settings they started with.
+pub async fn update_server_config(
+    server_handle: Arc<Mutex<Option<ServerHandle>>>,
+    update: ServerConfigUpdate,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let handle_guard = server_handle.lock().await;
+    let server = handle_guard.as_ref().ok_or("Server is not running")?;
+    if let Some(prefix) = &update.prefix {
+        validate_prefix(prefix)?;
+    }
+
+    let mut config = server.config.write().unwrap();
+    *config = config.updated(update);
+    log::info!("Proxy server configuration updated");
+    Ok(())
+}
+
+/// Stops the currently running proxy server.
+/// New connections are refused right away, active streams get until `drain_timeout`
+/// to finish before they are cancelled.
+pub async fn stop_server(
+    server_handle: Arc<Mutex<Option<ServerHandle>>>,
+    drain_timeout: Duration,
+) -> Result<ShutdownSummary, Box<dyn std::error::Error + Send + Sync>> {
+    let mut handle_guard = server_handle.lock().await;
+
+    let Some(server) = handle_guard.take() else {
+        log::debug!("No server was running");
+        return Ok(ShutdownSummary::default());
+    };
+
+    let active = server.streams.active();
+    log::info!(
+        "Stopping proxy server, draining {} active streams for up to {:?}",
+        active,
+        drain_timeout
+    );
+    let _ = server.shutdown.send(());
+
+    let mut task = server.task;
+    let deadline = tokio::time::Instant::now() + drain_timeout;
+    let stopped = tokio::time::timeout_at(deadline, &mut task).await.is_ok();
+    // WebSocket sessions are detached from the server task, they are waited for separately
+    let drained = tokio::time::timeout_at(deadline, server.streams.drained())
+        .await
+        .is_ok();
+    let killed = if stopped && drained {
+        0
+    } else {
+        server.streams.cancel_all()
+    };
+    if !stopped
+        && tokio::time::timeout(shutdown::KILL_GRACE_PERIOD, &mut task)
+            .await
+            .is_err()
+    {
+        task.abort();
+    }
+
+    let summary = ShutdownSummary {
+        drained: active.saturating_sub(killed),
+        killed,
+    };
+    log::info!(
+        "Proxy server stopped: {} streams drained, {} killed",
+        summary.drained,
+        summary.killed
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_filter_models_response_with_downloaded_status() {
+        let test_response = json!({
+            "object": "list",
+            "data": [
+                {
+                    "id": "model1",
+                    "name": "Model 1",
+                    "status": "downloaded"
+                },
+                {
+                    "id": "model2",
+                    "name": "Model 2",
+                    "status": "available"
+                },
+                {
+                    "id": "model3",
+                    "name": "Model 3"
+                }
+            ]
+        });
+
+        let response_bytes = serde_json::to_vec(&test_response).unwrap();
+        let filtered_bytes =
+            filter_models_response(&response_bytes, &ModelAliases::default()).unwrap();
+        let filtered_response: serde_json::Value = serde_json::from_slice(&filtered_bytes).unwrap();
+
+        let data = filtered_response["data"].as_array().unwrap();
+        assert_eq!(data.len(), 1); // Should have 1 model (only model1 with "downloaded" status)
+
+        // Verify only model1 (with "downloaded" status) is kept
+        assert!(data.iter().any(|model| model["id"] == "model1"));
+
+        // Verify model2 and model3 are filtered out
+        assert!(!data.iter().any(|model| model["id"] == "model2"));
+        assert!(!data.iter().any(|model| model["id"] == "model3"));
+    }
+
+    #[test]
+    fn test_filter_models_response_direct_array() {
+        let test_response = json!([
+            {
+                "id": "model1",
+                "name": "Model 1",
+                "status": "downloaded"
+            },
+            {
+                "id": "model2",
+                "name": "Model 2",
+                "status": "available"
+            }
+        ]);
+
+        let response_bytes = serde_json::to_vec(&test_response).unwrap();
+        let filtered_bytes =
+            filter_models_response(&response_bytes, &ModelAliases::default()).unwrap();
+        let filtered_response: serde_json::Value = serde_json::from_slice(&filtered_bytes).unwrap();
+
+        let data = filtered_response.as_array().unwrap();
+        assert_eq!(data.len(), 1); // Should have 1 model (only model1 with "downloaded" status)
+        assert!(data.iter().any(|model| model["id"] == "model1"));
+        assert!(!data.iter().any(|model| model["id"] == "model2"));
+    }
+
+    #[test]
+    fn test_filter_models_response_no_status_field() {
+        let test_response = json!({
+            "object": "list",
+            "data": [
+                {
+                    "id": "model1",
+                    "name": "Model 1"
+                },
+                {
+                    "id": "model2",
+                    "name": "Model 2"
+                }
+            ]
+        });
+
+        let response_bytes = serde_json::to_vec(&test_response).unwrap();
+        let filtered_bytes =
+            filter_models_response(&response_bytes, &ModelAliases::default()).unwrap();
+        let filtered_response: serde_json::Value = serde_json::from_slice(&filtered_bytes).unwrap();
+
+        let data = filtered_response["data"].as_array().unwrap();
+        assert_eq!(data.len(), 0); // Should remove all models when no status field (no "downloaded" status)
+    }
+
+    #[test]
+    fn test_filter_models_response_multiple_downloaded() {
+        let test_response = json!({
+            "object": "list",
+            "data": [
+                {
+                    "id": "model1",
+                    "name": "Model 1",
+                    "status": "downloaded"
+                },
+                {
+                    "id": "model2",
+                    "name": "Model 2",
+                    "status": "available"
+                },
+                {
+                    "id": "model3",
+                    "name": "Model 3",
+                    "status": "downloaded"
+                },
+                {
+                    "id": "model4",
+                    "name": "Model 4",
+                    "status": "installing"
+                }
+            ]
+        });
+
+        let response_bytes = serde_json::to_vec(&test_response).unwrap();
+        let filtered_bytes =
+            filter_models_response(&response_bytes, &ModelAliases::default()).unwrap();
+        let filtered_response: serde_json::Value = serde_json::from_slice(&filtered_bytes).unwrap();
+
+        let data = filtered_response["data"].as_array().unwrap();
+        assert_eq!(data.len(), 2); // Should have 2 models (model1 and model3 with "downloaded" status)
+
+        // Verify only models with "downloaded" status are kept
+        assert!(data.iter().any(|model| model["id"] == "model1"));
+        assert!(data.iter().any(|model| model["id"] == "model3"));
+
+        // Verify other models are filtered out
+        assert!(!data.iter().any(|model| model["id"] == "model2"));
+        assert!(!data.iter().any(|model| model["id"] == "model4"));
+    }
+
+    #[test]
+    fn test_only_interactive_keys_jump_the_queue() {
+        let mut store = ApiKeyStore::default();
+        let app = store
+            .create("app".to_string(), vec![], vec![], None, true)
+            .key;
+        let ci = store
+            .create("ci".to_string(), vec![], vec![], None, false)
+            .key;
+
+        assert_eq!(queue_priority(Some(&app)), Priority::Interactive);
+        assert_eq!(queue_priority(Some(&ci)), Priority::Background);
+        assert_eq!(queue_priority(None), Priority::Background);
+    }
+
+    #[test]
+    fn test_metrics_are_served_without_a_prefix() {
+        // The destination gets /v1 in front, the original path is what counts
+        assert_eq!(get_destination_path("/metrics", ""), "/v1/metrics");
+        assert!(is_metrics_request(&hyper::Method::GET, "/metrics"));
+        assert!(!is_metrics_request(&hyper::Method::GET, "/v1/metrics"));
+        assert!(!is_metrics_request(&hyper::Method::POST, "/metrics"));
+    }
+
+    #[test]
+    fn test_prefix_must_form_valid_paths() {
+        assert!(validate_prefix("").is_ok());
+        assert!(validate_prefix("/api").is_ok());
+        assert!(validate_prefix("/my api").is_err());
+        assert_eq!(
+            prefixed_uri("/my api", "/chat/completions")
+                .unwrap_err()
+                .code,
+            "invalid_prefix"
+        );
+    }
+}
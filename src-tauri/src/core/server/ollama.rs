@@ -0,0 +1,626 @@
+use futures_util::{stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::Body;
+use serde_json::{json, Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::errors::ApiError;
+use super::sse::SseReader;
+
+/// Content type of Ollama's streamed responses
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Ollama endpoints served by the facade
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endpoint {
+    Chat,
+    Generate,
+    Tags,
+    /// Legacy `/api/embeddings`, one prompt per request
+    Embeddings,
+    /// `/api/embed`, one or more inputs per request
+    Embed,
+}
+
+impl Endpoint {
+    /// Matches the request path, Ollama clients always call the server root
+    pub fn from_request(method: &hyper::Method, path: &str) -> Option<Self> {
+        match (method, path.trim_end_matches('/')) {
+            (&hyper::Method::POST, "/api/chat") => Some(Self::Chat),
+            (&hyper::Method::POST, "/api/generate") => Some(Self::Generate),
+            (&hyper::Method::GET, "/api/tags") => Some(Self::Tags),
+            (&hyper::Method::POST, "/api/embeddings") => Some(Self::Embeddings),
+            (&hyper::Method::POST, "/api/embed") => Some(Self::Embed),
+            _ => None,
+        }
+    }
+
+    /// OpenAI endpoint the request is translated to, relative to the server prefix
+    pub fn openai_path(self, raw: bool) -> &'static str {
+        match self {
+            Self::Chat => "/chat/completions",
+            Self::Generate if raw => "/completions",
+            Self::Generate => "/chat/completions",
+            Self::Tags => "/models",
+            Self::Embeddings | Self::Embed => "/embeddings",
+        }
+    }
+}
+
+/// Ollama `options` and the OpenAI parameters they map to
+const OPTIONS: [(&str, &str); 7] = [
+    ("temperature", "temperature"),
+    ("top_p", "top_p"),
+    ("top_k", "top_k"),
+    ("seed", "seed"),
+    ("stop", "stop"),
+    ("num_predict", "max_tokens"),
+    ("presence_penalty", "presence_penalty"),
+];
+
+/// Translates an Ollama request body into the OpenAI request for its endpoint
+pub fn to_openai_request(endpoint: Endpoint, request: &Value) -> Result<Value, ApiError> {
+    let model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or_else(|| {
+            ApiError::invalid_request("model is required", "missing_required_parameter")
+        })?;
+    let mut openai = Map::new();
+    openai.insert("model".to_string(), json!(model));
+
+    match endpoint {
+        Endpoint::Chat => {
+            let messages = request
+                .get("messages")
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            let messages: Vec<Value> = messages.iter().map(chat_message).collect();
+            openai.insert("messages".to_string(), Value::Array(messages));
+            if let Some(tools) = request.get("tools") {
+                openai.insert("tools".to_string(), tools.clone());
+            }
+        }
+        Endpoint::Generate if is_raw(request) => {
+            openai.insert(
+                "prompt".to_string(),
+                request.get("prompt").cloned().unwrap_or(json!("")),
+            );
+        }
+        Endpoint::Generate => {
+            let mut messages = Vec::new();
+            if let Some(system) = request.get("system").and_then(Value::as_str) {
+                messages.push(json!({"role": "system", "content": system}));
+            }
+            let user = json!({
+                "role": "user",
+                "content": request.get("prompt").cloned().unwrap_or(json!("")),
+                "images": request.get("images").cloned().unwrap_or(json!([])),
+            });
+            messages.push(chat_message(&user));
+            openai.insert("messages".to_string(), Value::Array(messages));
+        }
+        Endpoint::Embeddings => {
+            openai.insert(
+                "input".to_string(),
+                request.get("prompt").cloned().unwrap_or(json!("")),
+            );
+        }
+        Endpoint::Embed => {
+            openai.insert(
+                "input".to_string(),
+                request.get("input").cloned().unwrap_or(json!("")),
+            );
+        }
+        Endpoint::Tags => {}
+    }
+
+    if matches!(endpoint, Endpoint::Chat | Endpoint::Generate) {
+        openai.insert("stream".to_string(), json!(is_streaming(request)));
+        if let Some(options) = request.get("options").and_then(Value::as_object) {
+            for (option, param) in OPTIONS {
+                if let Some(value) = options.get(option) {
+                    openai.insert(param.to_string(), value.clone());
+                }
+            }
+        }
+        match request.get("format") {
+            Some(Value::String(format)) if format == "json" => {
+                openai.insert(
+                    "response_format".to_string(),
+                    json!({"type": "json_object"}),
+                );
+            }
+            Some(schema @ Value::Object(_)) => {
+                openai.insert(
+                    "response_format".to_string(),
+                    json!({"type": "json_schema", "json_schema": {"name": "response", "schema": schema}}),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Value::Object(openai))
+}
+
+/// Ollama streams unless the client sends `"stream": false`
+pub fn is_streaming(request: &Value) -> bool {
+    request
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or(true)
+}
+
+/// `raw` generate requests skip the chat template
+pub fn is_raw(request: &Value) -> bool {
+    request.get("raw").and_then(Value::as_bool).unwrap_or(false)
+}
+
+/// Converts an Ollama chat message, attached images become image parts
+fn chat_message(message: &Value) -> Value {
+    let role = message.get("role").cloned().unwrap_or(json!("user"));
+    let content = message.get("content").cloned().unwrap_or(json!(""));
+    let images: Vec<&str> = message
+        .get("images")
+        .and_then(Value::as_array)
+        .map(|images| images.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+
+    let mut converted = if images.is_empty() {
+        json!({"role": role, "content": content})
+    } else {
+        let mut parts = vec![json!({"type": "text", "text": content})];
+        parts.extend(images.iter().map(|image| {
+            json!({"type": "image_url", "image_url": {"url": format!("data:image/png;base64,{}", image)}})
+        }));
+        json!({"role": role, "content": parts})
+    };
+
+    if let Some(tool_calls) = message.get("tool_calls").and_then(Value::as_array) {
+        let tool_calls: Vec<Value> = tool_calls
+            .iter()
+            .enumerate()
+            .map(|(index, call)| {
+                json!({
+                    "id": format!("call_{}", index),
+                    "type": "function",
+                    "function": {
+                        "name": call.pointer("/function/name"),
+                        "arguments": call.pointer("/function/arguments").map(Value::to_string).unwrap_or_else(|| "{}".to_string()),
+                    }
+                })
+            })
+            .collect();
+        converted["tool_calls"] = Value::Array(tool_calls);
+    }
+    converted
+}
+
+fn done_reason(finish_reason: Option<&str>) -> &'static str {
+    match finish_reason {
+        Some("length") => "length",
+        _ => "stop",
+    }
+}
+
+/// Ollama reports tool call arguments as objects, OpenAI as JSON strings
+fn tool_calls(message: &Value) -> Option<Value> {
+    let calls = message.get("tool_calls")?.as_array()?;
+    let calls: Vec<Value> = calls
+        .iter()
+        .map(|call| {
+            let arguments = call
+                .pointer("/function/arguments")
+                .and_then(Value::as_str)
+                .and_then(|arguments| serde_json::from_str::<Value>(arguments).ok())
+                .unwrap_or_else(|| json!({}));
+            json!({"function": {"name": call.pointer("/function/name"), "arguments": arguments}})
+        })
+        .collect();
+    Some(Value::Array(calls))
+}
+
+/// Translates an OpenAI response body into the Ollama response for the endpoint
+pub fn to_ollama_response(endpoint: Endpoint, response: &Value) -> Value {
+    let model = response.get("model").cloned().unwrap_or(json!(""));
+    let created_at = created_at(response);
+
+    match endpoint {
+        Endpoint::Tags => {
+            let models = response
+                .get("data")
+                .or(Some(response))
+                .and_then(Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            let models: Vec<Value> = models
+                .iter()
+                .filter_map(|model| model.get("id").or_else(|| model.get("model")))
+                .map(|id| {
+                    json!({
+                        "name": id,
+                        "model": id,
+                        "modified_at": created_at,
+                        "size": 0,
+                        "digest": "",
+                        "details": {"format": "gguf", "family": "", "parameter_size": "", "quantization_level": ""},
+                    })
+                })
+                .collect();
+            json!({"models": models})
+        }
+        Endpoint::Embeddings => json!({
+            "embedding": response.pointer("/data/0/embedding").cloned().unwrap_or(json!([])),
+        }),
+        Endpoint::Embed => {
+            let embeddings: Vec<Value> = response
+                .get("data")
+                .and_then(Value::as_array)
+                .map(|data| {
+                    data.iter()
+                        .filter_map(|item| item.get("embedding").cloned())
+                        .collect()
+                })
+                .unwrap_or_default();
+            json!({
+                "model": model,
+                "embeddings": embeddings,
+                "prompt_eval_count": response.pointer("/usage/prompt_tokens").cloned().unwrap_or(json!(0)),
+            })
+        }
+        Endpoint::Chat | Endpoint::Generate => {
+            let choice = response.pointer("/choices/0").cloned().unwrap_or_default();
+            let mut ollama = json!({
+                "model": model,
+                "created_at": created_at,
+                "done": true,
+                "done_reason": done_reason(choice.get("finish_reason").and_then(Value::as_str)),
+                "total_duration": 0,
+                "prompt_eval_count": response.pointer("/usage/prompt_tokens").cloned().unwrap_or(json!(0)),
+                "eval_count": response.pointer("/usage/completion_tokens").cloned().unwrap_or(json!(0)),
+            });
+            let text = choice
+                .pointer("/message/content")
+                .or_else(|| choice.get("text"))
+                .cloned()
+                .filter(|text| !text.is_null())
+                .unwrap_or(json!(""));
+            if endpoint == Endpoint::Chat {
+                let mut message = json!({"role": "assistant", "content": text});
+                if let Some(calls) = choice.get("message").and_then(tool_calls) {
+                    message["tool_calls"] = calls;
+                }
+                ollama["message"] = message;
+            } else {
+                ollama["response"] = text;
+            }
+            ollama
+        }
+    }
+}
+
+/// Translates an error body into Ollama's `{"error": "..."}` format
+pub fn to_ollama_error(body: &[u8]) -> Vec<u8> {
+    let json: Option<Value> = serde_json::from_slice(body).ok();
+    let message = json
+        .as_ref()
+        .and_then(|json| {
+            json.pointer("/error/message")
+                .or_else(|| json.get("message"))
+        })
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| String::from_utf8_lossy(body).trim().to_string());
+    json!({"error": message}).to_string().into_bytes()
+}
+
+/// Turns chat completion chunks into newline-delimited Ollama responses
+#[derive(Debug)]
+pub struct StreamTranslator {
+    endpoint: Endpoint,
+    reader: SseReader,
+    model: Value,
+    finished: bool,
+    done_reason: &'static str,
+    prompt_tokens: Value,
+    completion_tokens: Value,
+    /// Tool calls streamed so far, in OpenAI form, by index
+    tool_calls: Vec<Value>,
+}
+
+impl StreamTranslator {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            reader: SseReader::default(),
+            model: json!(""),
+            finished: false,
+            done_reason: "stop",
+            prompt_tokens: json!(0),
+            completion_tokens: json!(0),
+            tool_calls: Vec::new(),
+        }
+    }
+
+    /// Feeds raw SSE bytes from upstream, returns the NDJSON lines to send
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        let mut lines = String::new();
+        for data in self.reader.push(bytes) {
+            if data == "[DONE]" {
+                lines.push_str(&self.finish());
+            } else if let Ok(chunk) = serde_json::from_str::<Value>(&data) {
+                lines.push_str(&self.chunk(&chunk));
+            }
+        }
+        lines
+    }
+
+    fn chunk(&mut self, chunk: &Value) -> String {
+        if let Some(model) = chunk.get("model") {
+            self.model = model.clone();
+        }
+        if let Some(usage) = chunk.get("usage").filter(|usage| !usage.is_null()) {
+            self.prompt_tokens = usage.get("prompt_tokens").cloned().unwrap_or(json!(0));
+            self.completion_tokens = usage.get("completion_tokens").cloned().unwrap_or(json!(0));
+        }
+
+        let Some(choice) = chunk.pointer("/choices/0") else {
+            return String::new();
+        };
+        for tool_call in choice
+            .pointer("/delta/tool_calls")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            self.collect_tool_call(tool_call);
+        }
+
+        let text = choice
+            .pointer("/delta/content")
+            .or_else(|| choice.get("text"))
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        let mut lines = String::new();
+        if !text.is_empty() {
+            lines.push_str(&self.line(chunk, text, None));
+        }
+        if let Some(finish_reason) = choice.get("finish_reason").and_then(Value::as_str) {
+            self.done_reason = done_reason(Some(finish_reason));
+            lines.push_str(&self.flush_tool_calls(chunk));
+        }
+        lines
+    }
+
+    /// Appends a tool call fragment to the call with the same index
+    fn collect_tool_call(&mut self, tool_call: &Value) {
+        let index = tool_call.get("index").and_then(Value::as_u64).unwrap_or(0) as usize;
+        while self.tool_calls.len() <= index {
+            self.tool_calls
+                .push(json!({"function": {"name": "", "arguments": ""}}));
+        }
+        let collected = &mut self.tool_calls[index];
+        for field in ["name", "arguments"] {
+            if let Some(part) = tool_call
+                .get("function")
+                .and_then(|function| function.get(field))
+                .and_then(Value::as_str)
+            {
+                let existing = collected["function"][field].as_str().unwrap_or_default();
+                collected["function"][field] = json!(format!("{}{}", existing, part));
+            }
+        }
+    }
+
+    /// Sends the collected tool calls as one message, Ollama has no partial calls
+    fn flush_tool_calls(&mut self, chunk: &Value) -> String {
+        if self.tool_calls.is_empty() {
+            return String::new();
+        }
+        let message = json!({"tool_calls": std::mem::take(&mut self.tool_calls)});
+        self.line(chunk, "", tool_calls(&message))
+    }
+
+    fn line(&self, chunk: &Value, text: &str, calls: Option<Value>) -> String {
+        let mut line = json!({"model": self.model, "created_at": created_at(chunk), "done": false});
+        if self.endpoint == Endpoint::Chat {
+            let mut message = json!({"role": "assistant", "content": text});
+            if let Some(calls) = calls {
+                message["tool_calls"] = calls;
+            }
+            line["message"] = message;
+        } else {
+            line["response"] = json!(text);
+        }
+        format!("{}\n", line)
+    }
+
+    /// Sends the final `done` line, also called when upstream ends without `[DONE]`
+    pub fn finish(&mut self) -> String {
+        if self.finished {
+            return String::new();
+        }
+        self.finished = true;
+
+        // Upstream may end without a finish reason
+        let mut lines = self.flush_tool_calls(&json!({}));
+        let mut line = json!({
+            "model": self.model,
+            "created_at": rfc3339(unix_now()),
+            "done": true,
+            "done_reason": self.done_reason,
+            "total_duration": 0,
+            "prompt_eval_count": self.prompt_tokens,
+            "eval_count": self.completion_tokens,
+        });
+        if self.endpoint == Endpoint::Chat {
+            line["message"] = json!({"role": "assistant", "content": ""});
+        } else {
+            line["response"] = json!("");
+        }
+        lines.push_str(&format!("{}\n", line));
+        lines
+    }
+}
+
+/// Wraps a chat completion SSE body into an NDJSON stream
+pub fn translate_stream(endpoint: Endpoint, body: Body) -> Body {
+    let lines = stream::unfold(
+        (body, StreamTranslator::new(endpoint), false),
+        |(mut body, mut translator, done)| async move {
+            if done {
+                return None;
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => {
+                    let lines = Bytes::from(translator.push(&chunk));
+                    Some((Ok(lines), (body, translator, false)))
+                }
+                Some(Err(e)) => Some((Err(e), (body, translator, true))),
+                None => {
+                    let lines = Bytes::from(translator.finish());
+                    Some((Ok(lines), (body, translator, true)))
+                }
+            }
+        },
+    );
+    Body::wrap_stream(lines)
+}
+
+fn created_at(response: &Value) -> String {
+    let created = response
+        .get("created")
+        .and_then(Value::as_u64)
+        .unwrap_or_else(unix_now);
+    rfc3339(created)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Formats a Unix timestamp as an RFC 3339 UTC date time
+fn rfc3339(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let seconds = timestamp % 86_400;
+
+    // Civil date from days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chat_translation() {
+        let request = json!({
+            "model": "llama3.2:3b",
+            "messages": [{"role": "user", "content": "Describe this", "images": ["aGk="]}],
+            "options": {"temperature": 0, "num_predict": 64},
+            "format": "json"
+        });
+        let chat = to_openai_request(Endpoint::Chat, &request).unwrap();
+
+        assert_eq!(chat["stream"], true);
+        assert_eq!(chat["max_tokens"], 64);
+        assert_eq!(chat["response_format"]["type"], "json_object");
+        assert_eq!(
+            chat["messages"][0]["content"][1]["image_url"]["url"],
+            "data:image/png;base64,aGk="
+        );
+
+        let response = json!({
+            "model": "llama3.2:3b",
+            "created": 0,
+            "choices": [{"message": {"role": "assistant", "content": "{}"}, "finish_reason": "stop"}],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 1}
+        });
+        let ollama = to_ollama_response(Endpoint::Chat, &response);
+        assert_eq!(ollama["message"]["content"], "{}");
+        assert_eq!(ollama["created_at"], "1970-01-01T00:00:00Z");
+        assert_eq!(ollama["eval_count"], 1);
+    }
+
+    #[test]
+    fn test_generate_stream_translation() {
+        let mut translator = StreamTranslator::new(Endpoint::Generate);
+        let mut lines = translator.push(
+            b"data: {\"model\":\"m\",\"created\":1700000000,\"choices\":[{\"delta\":{\"content\":\"Hi\"},\"finish_reason\":null}]}\n\n",
+        );
+        lines.push_str(&translator.push(
+            b"data: {\"model\":\"m\",\"choices\":[{\"delta\":{},\"finish_reason\":\"length\"}]}\n\ndata: [DONE]\n\n",
+        ));
+        lines.push_str(&translator.finish());
+
+        let lines: Vec<Value> = lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["response"], "Hi");
+        assert_eq!(lines[0]["created_at"], "2023-11-14T22:13:20Z");
+        assert_eq!(lines[1]["done"], true);
+        assert_eq!(lines[1]["done_reason"], "length");
+    }
+
+    #[test]
+    fn test_chat_stream_assembles_tool_calls() {
+        let mut translator = StreamTranslator::new(Endpoint::Chat);
+        let mut lines = String::new();
+        for delta in [
+            json!({"tool_calls": [{"index": 0, "id": "call_1", "function": {"name": "get_weather", "arguments": ""}}]}),
+            json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"city\":"}}]}),
+            json!({"tool_calls": [{"index": 0, "function": {"arguments": "\"Paris\"}"}}]}),
+        ] {
+            let chunk = json!({"model": "m", "choices": [{"delta": delta, "finish_reason": null}]});
+            lines.push_str(&translator.push(format!("data: {}\n\n", chunk).as_bytes()));
+        }
+        assert!(lines.is_empty());
+        lines.push_str(&translator.push(
+            b"data: {\"model\":\"m\",\"choices\":[{\"delta\":{},\"finish_reason\":\"tool_calls\"}]}\n\ndata: [DONE]\n\n",
+        ));
+
+        let lines: Vec<Value> = lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        let calls = &lines[0]["message"]["tool_calls"];
+        assert_eq!(calls.as_array().unwrap().len(), 1);
+        assert_eq!(calls[0]["function"]["name"], "get_weather");
+        assert_eq!(calls[0]["function"]["arguments"], json!({"city": "Paris"}));
+        assert_eq!(lines[1]["done"], true);
+    }
+
+    #[test]
+    fn test_tags_translation() {
+        let models = json!({"data": [{"id": "llama3.2:3b", "status": "downloaded"}]});
+        let tags = to_ollama_response(Endpoint::Tags, &models);
+        assert_eq!(tags["models"][0]["name"], "llama3.2:3b");
+    }
+}
@@ -0,0 +1,435 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Response header carrying a request's place in line when it arrived, 0 when it didn't wait
+pub const QUEUE_POSITION_HEADER: &str = "X-Jan-Queue-Position";
+
+/// Endpoints that run a generation on the model
+const GENERATION_PATHS: [&str; 2] = ["/v1/chat/completions", "/v1/completions"];
+
+/// Settings of the generation request queue, disabled by default
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueueConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Generation requests the local upstream works on at the same time
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent: u32,
+    /// Requests allowed to wait, further ones are rejected
+    #[serde(default)]
+    pub max_waiting: Option<u32>,
+}
+
+fn default_max_concurrent() -> u32 {
+    1
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_concurrent: default_max_concurrent(),
+            max_waiting: None,
+        }
+    }
+}
+
+/// Scheduling class of a request, interactive requests always go first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    /// Requests made with an API key marked interactive, such as the app's own
+    Interactive,
+    /// Every other request, including those without a key
+    Background,
+}
+
+/// The queue is full
+#[derive(Debug, PartialEq)]
+pub struct QueueFull;
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Too many requests are waiting for the model, please try again later"
+        )
+    }
+}
+
+/// Checks whether requests to this path wait in the queue
+pub fn is_generation_path(path: &str) -> bool {
+    GENERATION_PATHS.contains(&path)
+}
+
+struct Waiter {
+    ticket: u64,
+    admit: oneshot::Sender<()>,
+}
+
+/// Waiting requests of one class, grouped by client and served round-robin
+#[derive(Default)]
+struct ClassQueue {
+    clients: VecDeque<(String, VecDeque<Waiter>)>,
+}
+
+impl ClassQueue {
+    fn len(&self) -> usize {
+        self.clients.iter().map(|(_, waiters)| waiters.len()).sum()
+    }
+
+    fn push(&mut self, client: String, waiter: Waiter) {
+        match self.clients.iter_mut().find(|(name, _)| *name == client) {
+            Some((_, waiters)) => waiters.push_back(waiter),
+            None => self.clients.push_back((client, VecDeque::from([waiter]))),
+        }
+    }
+
+    /// Takes the next request of the client whose turn it is, who then moves
+    /// to the back of the line
+    fn pop(&mut self) -> Option<Waiter> {
+        let (client, mut waiters) = self.clients.pop_front()?;
+        let waiter = waiters.pop_front();
+        if !waiters.is_empty() {
+            self.clients.push_back((client, waiters));
+        }
+        waiter
+    }
+
+    fn remove(&mut self, ticket: u64) -> bool {
+        for (_, waiters) in self.clients.iter_mut() {
+            if let Some(index) = waiters.iter().position(|w| w.ticket == ticket) {
+                waiters.remove(index);
+                self.clients.retain(|(_, waiters)| !waiters.is_empty());
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Tickets in the order they will be served
+    fn order(&self) -> Vec<u64> {
+        let rounds = self
+            .clients
+            .iter()
+            .map(|(_, waiters)| waiters.len())
+            .max()
+            .unwrap_or(0);
+        (0..rounds)
+            .flat_map(|round| {
+                self.clients
+                    .iter()
+                    .filter_map(move |(_, waiters)| waiters.get(round).map(|w| w.ticket))
+            })
+            .collect()
+    }
+}
+
+struct QueueState {
+    max_concurrent: usize,
+    max_waiting: Option<usize>,
+    running: usize,
+    next_ticket: u64,
+    interactive: ClassQueue,
+    background: ClassQueue,
+}
+
+impl QueueState {
+    fn class(&mut self, priority: Priority) -> &mut ClassQueue {
+        match priority {
+            Priority::Interactive => &mut self.interactive,
+            Priority::Background => &mut self.background,
+        }
+    }
+
+    /// Hands free slots to the next waiting requests
+    fn admit_waiting(&mut self) {
+        while self.running < self.max_concurrent {
+            let Some(waiter) = self.interactive.pop().or_else(|| self.background.pop()) else {
+                break;
+            };
+            // A waiter that went away is removed before its sender is dropped
+            if waiter.admit.send(()).is_ok() {
+                self.running += 1;
+            }
+        }
+    }
+}
+
+/// Bounds the generation requests sent to the local upstream at the same
+/// time, serving waiting interactive requests first and taking turns between
+/// clients within a class
+pub struct RequestQueue {
+    state: Mutex<QueueState>,
+}
+
+impl RequestQueue {
+    pub fn new(config: &QueueConfig) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                max_concurrent: config.max_concurrent.max(1) as usize,
+                max_waiting: config.max_waiting.map(|max| max as usize),
+                running: 0,
+                next_ticket: 0,
+                interactive: ClassQueue::default(),
+                background: ClassQueue::default(),
+            }),
+        }
+    }
+
+    /// Waits for a slot, returning it along with the request's place in line
+    /// when it arrived
+    pub async fn enter(
+        self: &Arc<Self>,
+        client: String,
+        priority: Priority,
+    ) -> Result<(QueueSlot, usize), QueueFull> {
+        let (ticket, position, admitted) = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < state.max_concurrent {
+                state.running += 1;
+                return Ok((self.slot(), 0));
+            }
+            let waiting = state.interactive.len() + state.background.len();
+            if state.max_waiting.is_some_and(|max| waiting >= max) {
+                return Err(QueueFull);
+            }
+
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            let (admit, admitted) = oneshot::channel();
+            state.class(priority).push(client, Waiter { ticket, admit });
+            (ticket, Self::position(&state, ticket), admitted)
+        };
+        log::debug!("Request queued at position {}", position);
+
+        let mut waiting = Waiting {
+            queue: self.clone(),
+            ticket,
+            admitted: false,
+        };
+        // The sender is only dropped after being used to admit this request
+        let _ = admitted.await;
+        waiting.admitted = true;
+        Ok((self.slot(), position))
+    }
+
+    /// Place in line of a waiting ticket, interactive requests come first
+    fn position(state: &QueueState, ticket: u64) -> usize {
+        state
+            .interactive
+            .order()
+            .into_iter()
+            .chain(state.background.order())
+            .position(|t| t == ticket)
+            .unwrap_or(0)
+            + 1
+    }
+
+    fn slot(self: &Arc<Self>) -> QueueSlot {
+        QueueSlot {
+            queue: self.clone(),
+        }
+    }
+
+    /// Frees a slot and hands free slots to the next waiting requests
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running = state.running.saturating_sub(1);
+        state.admit_waiting();
+    }
+
+    /// Applies new limits, running and waiting requests keep their place.
+    /// Running requests over a lowered limit finish before others start.
+    pub fn reconfigure(&self, config: &QueueConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.max_concurrent = config.max_concurrent.max(1) as usize;
+        state.max_waiting = config.max_waiting.map(|max| max as usize);
+        state.admit_waiting();
+    }
+
+    /// Requests currently waiting for a slot
+    pub fn waiting(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.interactive.len() + state.background.len()
+    }
+}
+
+/// Held while a request is being served, frees its slot when dropped
+pub struct QueueSlot {
+    queue: Arc<RequestQueue>,
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+/// Leaves the queue when a client goes away while waiting
+struct Waiting {
+    queue: Arc<RequestQueue>,
+    ticket: u64,
+    admitted: bool,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if self.admitted {
+            return;
+        }
+        let removed = {
+            let mut state = self.queue.state.lock().unwrap();
+            state.interactive.remove(self.ticket) || state.background.remove(self.ticket)
+        };
+        // Admitted just before going away, pass the slot on
+        if !removed {
+            self.queue.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn queue(max_waiting: u32) -> Arc<RequestQueue> {
+        Arc::new(RequestQueue::new(&QueueConfig {
+            enabled: true,
+            max_concurrent: 1,
+            max_waiting: Some(max_waiting),
+        }))
+    }
+
+    /// Queues the clients one after the other, each reports when it is served
+    async fn wait_in_line(
+        queue: &Arc<RequestQueue>,
+        clients: &[(&'static str, Priority)],
+    ) -> (
+        Vec<tokio::task::JoinHandle<()>>,
+        tokio::sync::mpsc::UnboundedReceiver<&'static str>,
+    ) {
+        let (served, order) = tokio::sync::mpsc::unbounded_channel();
+        let mut tasks = Vec::new();
+        for &(client, priority) in clients {
+            let (queue, served) = (queue.clone(), served.clone());
+            tasks.push(tokio::spawn(async move {
+                let (_slot, _) = queue.enter(client.to_string(), priority).await.unwrap();
+                served.send(client).unwrap();
+            }));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        (tasks, order)
+    }
+
+    #[tokio::test]
+    async fn test_free_queue_runs_right_away() {
+        let queue = queue(4);
+        let (_running, position) = queue
+            .enter("ci".to_string(), Priority::Background)
+            .await
+            .unwrap();
+        assert_eq!(position, 0);
+        assert_eq!(queue.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejects_requests() {
+        let queue = queue(1);
+        let (_running, _) = queue
+            .enter("ci".to_string(), Priority::Background)
+            .await
+            .unwrap();
+        let (_tasks, _order) = wait_in_line(&queue, &[("bot", Priority::Background)]).await;
+
+        assert_eq!(queue.waiting(), 1);
+        assert_eq!(
+            queue
+                .enter("ci".to_string(), Priority::Background)
+                .await
+                .err(),
+            Some(QueueFull)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_interactive_requests_go_first() {
+        let queue = queue(4);
+        let (running, _) = queue
+            .enter("ci".to_string(), Priority::Background)
+            .await
+            .unwrap();
+        let (tasks, mut order) = wait_in_line(
+            &queue,
+            &[("ci", Priority::Background), ("app", Priority::Interactive)],
+        )
+        .await;
+
+        drop(running);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let served: Vec<_> = std::iter::from_fn(|| order.try_recv().ok()).collect();
+        assert_eq!(served, ["app", "ci"]);
+    }
+
+    #[tokio::test]
+    async fn test_clients_take_turns() {
+        let queue = queue(4);
+        let (running, _) = queue
+            .enter("ci".to_string(), Priority::Background)
+            .await
+            .unwrap();
+        let (tasks, mut order) = wait_in_line(
+            &queue,
+            &[
+                ("ci", Priority::Background),
+                ("ci", Priority::Background),
+                ("bot", Priority::Background),
+            ],
+        )
+        .await;
+
+        drop(running);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let served: Vec<_> = std::iter::from_fn(|| order.try_recv().ok()).collect();
+        assert_eq!(served, ["ci", "bot", "ci"]);
+    }
+
+    #[tokio::test]
+    async fn test_reconfigure_keeps_running_requests() {
+        let queue = queue(4);
+        let (running, _) = queue
+            .enter("ci".to_string(), Priority::Background)
+            .await
+            .unwrap();
+        let (tasks, mut order) = wait_in_line(&queue, &[("bot", Priority::Background)]).await;
+
+        // A raised limit admits the waiting request next to the running one
+        queue.reconfigure(&QueueConfig {
+            enabled: true,
+            max_concurrent: 2,
+            max_waiting: Some(4),
+        });
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(order.try_recv().ok(), Some("bot"));
+
+        // A lowered limit still counts the request that is running
+        queue.reconfigure(&QueueConfig {
+            enabled: true,
+            max_concurrent: 1,
+            max_waiting: Some(4),
+        });
+        let (_tasks, _order) = wait_in_line(&queue, &[("bot", Priority::Background)]).await;
+        assert_eq!(queue.waiting(), 1);
+        drop(running);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queue.waiting(), 0);
+    }
+}
@@ -0,0 +1,380 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Time after which a bucket nobody took from has refilled completely, whatever its rate
+const BUCKET_REFILL_TIME: Duration = Duration::from_secs(60);
+
+/// Limits applied to a single API key or client
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Limits {
+    /// Sustained request rate, bursts up to the same amount are allowed
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// Maximum number of streaming responses open at the same time
+    #[serde(default)]
+    pub max_concurrent_streams: Option<u32>,
+}
+
+/// Rate limits of the local server, unset limits are not enforced
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub per_key: Limits,
+    /// Limits of each client address, clients of the Unix socket share one
+    #[serde(default)]
+    pub per_ip: Limits,
+}
+
+/// Why a request was rejected by the rate limiter
+#[derive(Debug, PartialEq)]
+pub enum RateLimitError {
+    TooManyRequests { retry_after: Duration },
+    TooManyStreams,
+}
+
+impl RateLimitError {
+    /// Seconds a client should wait before retrying, as sent in `Retry-After`
+    pub fn retry_after_secs(&self) -> u64 {
+        match self {
+            RateLimitError::TooManyRequests { retry_after } => {
+                retry_after.as_secs_f64().ceil().max(1.0) as u64
+            }
+            RateLimitError::TooManyStreams => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateLimitError::TooManyRequests { .. } => {
+                write!(f, "Rate limit reached for requests, please try again later")
+            }
+            RateLimitError::TooManyStreams => write!(
+                f,
+                "Too many concurrent streaming requests, please try again later"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn full(capacity: f64, now: Instant) -> Self {
+        Self {
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, capacity: f64, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * capacity / 60.0).min(capacity);
+        self.last_refill = now;
+    }
+
+    /// Time until one token is available, zero if one already is
+    fn wait_time(&self, capacity: f64) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) * 60.0 / capacity)
+        }
+    }
+}
+
+/// Token buckets by subject. Full buckets are forgotten, so clients that
+/// come and go don't accumulate.
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, TokenBucket>,
+    last_sweep: Option<Instant>,
+}
+
+impl Buckets {
+    /// Drops the buckets that have refilled, at most once per refill time
+    fn sweep(&mut self, now: Instant) {
+        if self
+            .last_sweep
+            .is_some_and(|last| now.duration_since(last) < BUCKET_REFILL_TIME)
+        {
+            return;
+        }
+        self.buckets
+            .retain(|_, bucket| now.duration_since(bucket.last_refill) < BUCKET_REFILL_TIME);
+        self.last_sweep = Some(now);
+    }
+}
+
+/// Identities a request is accounted against, flagged with whether they name an API key
+fn subjects(key_name: Option<&str>, client: &str) -> Vec<(String, bool)> {
+    let mut subjects = vec![(format!("client:{}", client), false)];
+    if let Some(key_name) = key_name {
+        subjects.push((format!("key:{}", key_name), true));
+    }
+    subjects
+}
+
+/// Token-bucket rate limiter and concurrent stream counter
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    config: Mutex<RateLimitConfig>,
+    buckets: Mutex<Buckets>,
+    streams: Mutex<HashMap<String, u32>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: Mutex::new(config),
+            ..Default::default()
+        }
+    }
+
+    /// Applies new limits, request counters and open streams carry over
+    pub fn reconfigure(&self, config: RateLimitConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    fn limits(&self, is_key: bool) -> Limits {
+        let config = self.config.lock().unwrap();
+        if is_key {
+            config.per_key.clone()
+        } else {
+            config.per_ip.clone()
+        }
+    }
+
+    /// Takes a token from the key and client buckets, or reports how long to wait
+    pub fn check_request(
+        &self,
+        key_name: Option<&str>,
+        client: &str,
+    ) -> Result<(), RateLimitError> {
+        self.check_request_at(key_name, client, Instant::now())
+    }
+
+    fn check_request_at(
+        &self,
+        key_name: Option<&str>,
+        client: &str,
+        now: Instant,
+    ) -> Result<(), RateLimitError> {
+        let subjects: Vec<_> = subjects(key_name, client)
+            .into_iter()
+            .filter_map(|(subject, is_key)| {
+                self.limits(is_key)
+                    .requests_per_minute
+                    .filter(|rpm| *rpm > 0)
+                    .map(|rpm| (subject, rpm as f64))
+            })
+            .collect();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.sweep(now);
+        let buckets = &mut buckets.buckets;
+
+        // Only consume tokens when every bucket has one, so a rejected
+        // request does not count against the other limits
+        let mut retry_after = Duration::ZERO;
+        for (subject, capacity) in &subjects {
+            let bucket = buckets
+                .entry(subject.clone())
+                .or_insert_with(|| TokenBucket::full(*capacity, now));
+            bucket.refill(*capacity, now);
+            retry_after = retry_after.max(bucket.wait_time(*capacity));
+        }
+        if !retry_after.is_zero() {
+            return Err(RateLimitError::TooManyRequests { retry_after });
+        }
+
+        for (subject, _) in &subjects {
+            if let Some(bucket) = buckets.get_mut(subject) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reserves a streaming slot for the key and client, released when the permit is dropped
+    pub fn acquire_stream(
+        self: &Arc<Self>,
+        key_name: Option<&str>,
+        client: &str,
+    ) -> Result<StreamPermit, RateLimitError> {
+        let subjects: Vec<_> = subjects(key_name, client)
+            .into_iter()
+            .map(|(subject, is_key)| (subject, self.limits(is_key).max_concurrent_streams))
+            .collect();
+
+        let mut streams = self.streams.lock().unwrap();
+        let at_capacity = subjects.iter().any(|(subject, max)| {
+            max.is_some_and(|max| streams.get(subject).copied().unwrap_or(0) >= max)
+        });
+        if at_capacity {
+            return Err(RateLimitError::TooManyStreams);
+        }
+
+        for (subject, _) in &subjects {
+            *streams.entry(subject.clone()).or_insert(0) += 1;
+        }
+
+        Ok(StreamPermit {
+            limiter: self.clone(),
+            subjects: subjects.into_iter().map(|(subject, _)| subject).collect(),
+        })
+    }
+
+    /// Number of streaming responses currently open
+    pub fn active_streams(&self) -> u32 {
+        self.streams
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(subject, _)| subject.starts_with("client:"))
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// Holds a streaming slot for as long as the response is being forwarded
+#[derive(Debug)]
+pub struct StreamPermit {
+    limiter: Arc<RateLimiter>,
+    subjects: Vec<String>,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        let mut streams = self.limiter.streams.lock().unwrap();
+        for subject in &self.subjects {
+            if let Some(count) = streams.get_mut(subject) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    streams.remove(subject);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn localhost() -> &'static str {
+        "127.0.0.1"
+    }
+
+    #[test]
+    fn test_request_limit_per_key() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            per_key: Limits {
+                requests_per_minute: Some(2),
+                max_concurrent_streams: None,
+            },
+            per_ip: Limits::default(),
+        });
+
+        assert!(limiter.check_request(Some("ci"), localhost()).is_ok());
+        assert!(limiter.check_request(Some("ci"), localhost()).is_ok());
+        let error = limiter.check_request(Some("ci"), localhost()).unwrap_err();
+        assert!(error.retry_after_secs() >= 1);
+
+        // Other keys have their own bucket, unauthenticated requests only hit the IP limit
+        assert!(limiter.check_request(Some("ui"), localhost()).is_ok());
+        assert!(limiter.check_request(None, localhost()).is_ok());
+    }
+
+    #[test]
+    fn test_unix_socket_clients_are_limited_apart_from_loopback() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            per_key: Limits::default(),
+            per_ip: Limits {
+                requests_per_minute: Some(1),
+                max_concurrent_streams: Some(1),
+            },
+        }));
+        assert!(limiter.check_request(None, localhost()).is_ok());
+        let _permit = limiter.acquire_stream(None, localhost()).unwrap();
+
+        assert!(limiter.check_request(None, "unix").is_ok());
+        assert!(limiter.acquire_stream(None, "unix").is_ok());
+    }
+
+    #[test]
+    fn test_refilled_buckets_are_dropped() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            per_key: Limits::default(),
+            per_ip: Limits {
+                requests_per_minute: Some(10),
+                max_concurrent_streams: None,
+            },
+        });
+        let start = Instant::now();
+        for last in 0..100u8 {
+            let ip = format!("10.0.0.{}", last);
+            assert!(limiter.check_request_at(None, &ip, start).is_ok());
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 100);
+
+        let later = start + BUCKET_REFILL_TIME;
+        assert!(limiter.check_request_at(None, localhost(), later).is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 1);
+    }
+
+    #[test]
+    fn test_stream_permits_are_released_on_drop() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            per_key: Limits::default(),
+            per_ip: Limits {
+                requests_per_minute: None,
+                max_concurrent_streams: Some(1),
+            },
+        }));
+
+        let permit = limiter.acquire_stream(Some("ci"), localhost()).unwrap();
+        assert_eq!(limiter.active_streams(), 1);
+        assert_eq!(
+            limiter.acquire_stream(Some("ci"), localhost()).unwrap_err(),
+            RateLimitError::TooManyStreams
+        );
+
+        drop(permit);
+        assert_eq!(limiter.active_streams(), 0);
+        assert!(limiter.acquire_stream(Some("ci"), localhost()).is_ok());
+    }
+
+    #[test]
+    fn test_reconfigure_keeps_counters() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            per_key: Limits::default(),
+            per_ip: Limits {
+                requests_per_minute: Some(1),
+                max_concurrent_streams: Some(1),
+            },
+        }));
+        assert!(limiter.check_request(None, localhost()).is_ok());
+        let _permit = limiter.acquire_stream(None, localhost()).unwrap();
+
+        limiter.reconfigure(RateLimitConfig {
+            per_key: Limits::default(),
+            per_ip: Limits {
+                requests_per_minute: Some(2),
+                max_concurrent_streams: Some(2),
+            },
+        });
+        assert_eq!(limiter.active_streams(), 1);
+        let _second = limiter.acquire_stream(None, localhost()).unwrap();
+        assert!(limiter.acquire_stream(None, localhost()).is_err());
+        // The token taken before the change is still spent
+        assert!(limiter.check_request(None, localhost()).is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use futures_util::future::BoxFuture;
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use hyper::body::HttpBody;
+use hyper::{Body, HeaderMap, Response};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use super::sse::SseReader;
+
+pub const REALTIME_PATH: &str = "/v1/realtime";
+
+/// Subprotocol browsers offer, as they can't set headers on WebSockets
+pub const REALTIME_PROTOCOL: &str = "realtime";
+
+/// Subprotocol prefix carrying the API key, as in OpenAI's Realtime API
+const API_KEY_PROTOCOL: &str = "openai-insecure-api-key.";
+
+/// Frames waiting for a slow client, beyond this the requests stop reading upstream
+const FRAME_BUFFER: usize = 64;
+
+/// Sends a chat completion request through the proxy and returns its response
+pub type Dispatch = Arc<dyn Fn(Value) -> BoxFuture<'static, Response<Body>> + Send + Sync>;
+
+fn header_tokens<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+}
+
+/// Whether the request asks to switch to the WebSocket protocol
+pub fn is_upgrade(headers: &HeaderMap) -> bool {
+    header_tokens(headers, "upgrade").any(|token| token.eq_ignore_ascii_case("websocket"))
+}
+
+/// The `Sec-WebSocket-Accept` value answering the client's key
+pub fn accept_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("sec-websocket-key")
+        .map(|key| derive_accept_key(key.as_bytes()))
+}
+
+/// Whether the client offered the realtime subprotocol, which has to be echoed
+pub fn offers_realtime_protocol(headers: &HeaderMap) -> bool {
+    header_tokens(headers, "sec-websocket-protocol").any(|token| token == REALTIME_PROTOCOL)
+}
+
+/// The API key a browser passed as a subprotocol
+pub fn protocol_api_key(headers: &HeaderMap) -> Option<String> {
+    header_tokens(headers, "sec-websocket-protocol")
+        .find_map(|token| token.strip_prefix(API_KEY_PROTOCOL))
+        .map(str::to_string)
+}
+
+fn error_frame(id: Option<&str>, status: u16, error: Value) -> Value {
+    json!({"type": "error", "id": id, "status": status, "error": error})
+}
+
+fn invalid_frame(id: Option<&str>, message: &str) -> Value {
+    error_frame(
+        id,
+        400,
+        json!({
+            "message": message,
+            "type": "invalid_request_error",
+            "param": null,
+            "code": "invalid_frame",
+        }),
+    )
+}
+
+/// Whether a frame ends the request it belongs to
+fn is_final(frame: &Value) -> bool {
+    matches!(
+        frame.get("type").and_then(Value::as_str),
+        Some("chat.completion.done" | "chat.completion.cancelled" | "error")
+    )
+}
+
+/// Serves one WebSocket connection until the client closes it or `closed`
+/// resolves. Requests run concurrently, each one identified by the client's id.
+pub async fn run_session<S>(
+    socket: WebSocketStream<S>,
+    dispatch: Dispatch,
+    closed: impl Future<Output = ()>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut sink, mut messages) = socket.split();
+    let (frames, mut outgoing) = mpsc::channel::<Value>(FRAME_BUFFER);
+    let mut active: HashMap<String, AbortHandle> = HashMap::new();
+    tokio::pin!(closed);
+
+    loop {
+        tokio::select! {
+            message = messages.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let Some(frame) = handle_frame(&text, &dispatch, &frames, &mut active) else {
+                        continue;
+                    };
+                    // Answers go straight out, as the channel may be full. The
+                    // frames already produced go first, so a cancellation
+                    // follows the chunks sent before it.
+                    let mut sent = true;
+                    while let Ok(queued) = outgoing.try_recv() {
+                        sent = sent && forward(&mut sink, &mut active, queued).await;
+                    }
+                    if !sent || sink.send(Message::Text(frame.to_string())).await.is_err() {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by the protocol layer
+                Some(Ok(_)) => {}
+            },
+            Some(frame) = outgoing.recv() => {
+                if !forward(&mut sink, &mut active, frame).await {
+                    break;
+                }
+            }
+            _ = &mut closed => {
+                let _ = sink.send(Message::Close(None)).await;
+                break;
+            }
+        }
+    }
+
+    for request in active.values() {
+        request.abort();
+    }
+}
+
+/// Sends a frame of a running request, forgetting the request once it ended.
+/// Returns whether the client can still be written to.
+async fn forward<S>(
+    sink: &mut SplitSink<WebSocketStream<S>, Message>,
+    active: &mut HashMap<String, AbortHandle>,
+    frame: Value,
+) -> bool
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if is_final(&frame) {
+        if let Some(id) = frame.get("id").and_then(Value::as_str) {
+            active.remove(id);
+        }
+    }
+    sink.send(Message::Text(frame.to_string())).await.is_ok()
+}
+
+/// Starts or cancels a request, returning a frame to answer with right away
+fn handle_frame(
+    text: &str,
+    dispatch: &Dispatch,
+    frames: &mpsc::Sender<Value>,
+    active: &mut HashMap<String, AbortHandle>,
+) -> Option<Value> {
+    let Ok(frame) = serde_json::from_str::<Value>(text) else {
+        return Some(invalid_frame(None, "Frames must be JSON objects"));
+    };
+    let Some(id) = frame.get("id").and_then(Value::as_str) else {
+        return Some(invalid_frame(None, "Missing required field: 'id'"));
+    };
+
+    match frame.get("type").and_then(Value::as_str) {
+        Some("chat.completion.create") => {
+            if active.contains_key(id) {
+                return Some(invalid_frame(
+                    Some(id),
+                    "A request with this id is already running",
+                ));
+            }
+            let Some(mut request) = frame.get("request").filter(|r| r.is_object()).cloned() else {
+                return Some(invalid_frame(Some(id), "Missing required field: 'request'"));
+            };
+            request["stream"] = json!(true);
+            let task = tokio::spawn(run_request(
+                id.to_string(),
+                dispatch(request),
+                frames.clone(),
+            ));
+            active.insert(id.to_string(), task.abort_handle());
+            None
+        }
+        Some("chat.completion.cancel") => match active.remove(id) {
+            Some(request) => {
+                // Dropping the response body stops the upstream generation.
+                // Answers that aren't streamed are relayed in one piece, once
+                // upstream is done, so cancelling one only drops the call
+                // while it still runs and is refused afterwards.
+                request.abort();
+                Some(json!({"type": "chat.completion.cancelled", "id": id}))
+            }
+            None => Some(invalid_frame(Some(id), "No running request has this id")),
+        },
+        _ => Some(invalid_frame(
+            Some(id),
+            "Unknown frame type, expected 'chat.completion.create' or 'chat.completion.cancel'",
+        )),
+    }
+}
+
+/// Relays one response as frames: a chunk per SSE event, then `done`
+async fn run_request(
+    id: String,
+    response: BoxFuture<'static, Response<Body>>,
+    frames: mpsc::Sender<Value>,
+) {
+    let response = response.await;
+    let status = response.status();
+    let is_stream = response
+        .headers()
+        .get(hyper::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+    let mut body = response.into_body();
+
+    // Errors and tool loop answers arrive fully buffered
+    if !status.is_success() || !is_stream {
+        let bytes = hyper::body::to_bytes(body).await.unwrap_or_default();
+        let json: Value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        let frame = if status.is_success() {
+            json!({"type": "chat.completion", "id": id, "completion": json})
+        } else {
+            error_frame(Some(&id), status.as_u16(), json["error"].clone())
+        };
+        let _ = frames.send(frame).await;
+        if status.is_success() {
+            let _ = frames
+                .send(json!({"type": "chat.completion.done", "id": id}))
+                .await;
+        }
+        return;
+    }
+
+    let mut reader = SseReader::default();
+    while let Some(chunk) = body.data().await {
+        let Ok(chunk) = chunk else {
+            break;
+        };
+        for data in reader.push(&chunk) {
+            if data == "[DONE]" {
+                continue;
+            }
+            let Ok(chunk) = serde_json::from_str::<Value>(&data) else {
+                continue;
+            };
+            if let Some(error) = chunk.get("error") {
+                let _ = frames
+                    .send(error_frame(Some(&id), 500, error.clone()))
+                    .await;
+                return;
+            }
+            let _ = frames
+                .send(json!({"type": "chat.completion.chunk", "id": id, "chunk": chunk}))
+                .await;
+        }
+    }
+    let _ = frames
+        .send(json!({"type": "chat.completion.done", "id": id}))
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    /// Streams one chunk, or never finishes for the `slow` model
+    fn dispatch() -> Dispatch {
+        Arc::new(|request: Value| {
+            Box::pin(async move {
+                let body = if request["model"] == "slow" {
+                    // Never finishes, until it is cancelled
+                    let (sender, body) = Body::channel();
+                    std::mem::forget(sender);
+                    body
+                } else {
+                    Body::from(
+                        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"}}]}\n\ndata: [DONE]\n\n",
+                    )
+                };
+                Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "text/event-stream")
+                    .body(body)
+                    .unwrap()
+            })
+        })
+    }
+
+    async fn connect(dispatch: Dispatch) -> WebSocketStream<DuplexStream> {
+        let (server_io, client_io) = tokio::io::duplex(4096);
+        let server = WebSocketStream::from_raw_socket(server_io, Role::Server, None).await;
+        tokio::spawn(run_session(server, dispatch, std::future::pending()));
+        WebSocketStream::from_raw_socket(client_io, Role::Client, None).await
+    }
+
+    async fn send(client: &mut WebSocketStream<DuplexStream>, frame: Value) {
+        client.send(Message::Text(frame.to_string())).await.unwrap();
+    }
+
+    async fn next_frame<S: AsyncRead + AsyncWrite + Unpin>(
+        client: &mut WebSocketStream<S>,
+    ) -> Value {
+        loop {
+            if let Message::Text(text) = client.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_is_relayed_as_frames() {
+        let mut client = connect(dispatch()).await;
+        send(
+            &mut client,
+            json!({"type": "chat.completion.create", "id": "a", "request": {"model": "m"}}),
+        )
+        .await;
+
+        let chunk = next_frame(&mut client).await;
+        assert_eq!(chunk["type"], "chat.completion.chunk");
+        assert_eq!(chunk["id"], "a");
+        assert_eq!(chunk["chunk"]["choices"][0]["delta"]["content"], "Hi");
+        assert_eq!(
+            next_frame(&mut client).await["type"],
+            "chat.completion.done"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_requests_run_concurrently() {
+        let mut client = connect(dispatch()).await;
+        for (id, model) in [("slow", "slow"), ("fast", "m")] {
+            send(
+                &mut client,
+                json!({"type": "chat.completion.create", "id": id, "request": {"model": model}}),
+            )
+            .await;
+        }
+
+        // The slow request doesn't hold up the fast one
+        assert_eq!(next_frame(&mut client).await["id"], "fast");
+    }
+
+    #[tokio::test]
+    async fn test_running_request_is_cancelled() {
+        let mut client = connect(dispatch()).await;
+        send(
+            &mut client,
+            json!({"type": "chat.completion.create", "id": "slow", "request": {"model": "slow"}}),
+        )
+        .await;
+        send(
+            &mut client,
+            json!({"type": "chat.completion.cancel", "id": "slow"}),
+        )
+        .await;
+
+        let cancelled = next_frame(&mut client).await;
+        assert_eq!(cancelled["type"], "chat.completion.cancelled");
+        assert_eq!(cancelled["id"], "slow");
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_id_is_refused() {
+        let mut client = connect(dispatch()).await;
+        let create =
+            json!({"type": "chat.completion.create", "id": "slow", "request": {"model": "slow"}});
+        send(&mut client, create.clone()).await;
+        send(&mut client, create).await;
+
+        let refused = next_frame(&mut client).await;
+        assert_eq!(refused["type"], "error");
+        assert_eq!(refused["id"], "slow");
+        assert_eq!(refused["error"]["code"], "invalid_frame");
+    }
+
+    #[tokio::test]
+    async fn test_buffered_answer_can_not_be_cancelled() {
+        let dispatch: Dispatch = Arc::new(|_| {
+            Box::pin(async {
+                Response::builder()
+                    .header(hyper::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from("{\"choices\":[]}"))
+                    .unwrap()
+            })
+        });
+        let mut client = connect(dispatch).await;
+        send(
+            &mut client,
+            json!({"type": "chat.completion.create", "id": "a", "request": {}}),
+        )
+        .await;
+        assert_eq!(next_frame(&mut client).await["type"], "chat.completion");
+        assert_eq!(
+            next_frame(&mut client).await["type"],
+            "chat.completion.done"
+        );
+
+        // Nothing is left running once the whole answer was sent
+        send(
+            &mut client,
+            json!({"type": "chat.completion.cancel", "id": "a"}),
+        )
+        .await;
+        let refused = next_frame(&mut client).await;
+        assert_eq!(refused["type"], "error");
+        assert_eq!(refused["error"]["code"], "invalid_frame");
+    }
+}
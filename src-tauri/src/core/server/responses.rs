@@ -0,0 +1,996 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures_util::{stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::{Body, StatusCode};
+use serde_json::{json, Map, Value};
+
+use super::conversations::{self, ConversationStore, ThreadWriter};
+use super::errors::ApiError;
+use super::sse::SseReader;
+
+/// Destination path of the OpenAI Responses API
+pub const RESPONSES_PATH: &str = "/v1/responses";
+
+/// Id of a stored response: the thread of its chain and the assistant
+/// message holding its output
+#[derive(Debug, Clone, PartialEq)]
+struct ResponseId {
+    thread_id: String,
+    message_id: String,
+}
+
+impl ResponseId {
+    fn parse(id: &str) -> Option<Self> {
+        let (thread_id, message_id) = id.strip_prefix("resp_")?.split_once('_')?;
+        // The thread id becomes part of a path, only accept the ids Jan assigns
+        uuid::Uuid::parse_str(thread_id).ok()?;
+        if message_id.is_empty() {
+            return None;
+        }
+        Some(Self {
+            thread_id: thread_id.to_string(),
+            message_id: message_id.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for ResponseId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "resp_{}_{}", self.thread_id, self.message_id)
+    }
+}
+
+/// A Responses API request translated to chat completions, together with the
+/// conversation it continues
+pub struct Exchange {
+    chat_request: Value,
+    /// Response object without output, completed from the chat completion
+    response: Value,
+    /// Chat messages taken from the request input
+    input: Vec<Value>,
+    /// Thread messages up to the previous response
+    history: Vec<Value>,
+    /// Thread to append to, set when the previous response is the latest of its thread
+    thread_id: Option<String>,
+    store: bool,
+}
+
+/// Where the output of a stored response is written
+pub struct StoredReply {
+    writer: ThreadWriter,
+    message_id: String,
+}
+
+impl StoredReply {
+    async fn record(self, reply: &Value) {
+        if let Err(e) = self.writer.append(reply, Some(self.message_id)).await {
+            log::error!("Failed to store response output: {}", e);
+        }
+    }
+}
+
+impl Exchange {
+    /// Translates the request, loading the conversation of `previous_response_id`
+    pub async fn new(request: &Value, store: &dyn ConversationStore) -> Result<Self, ApiError> {
+        let request = request.as_object().ok_or_else(|| {
+            ApiError::invalid_request("The request body must be a JSON object", "invalid_type")
+        })?;
+        let input = input_messages(request.get("input"))?;
+
+        let mut history = Vec::new();
+        let mut thread_id = None;
+        let previous = request.get("previous_response_id").and_then(Value::as_str);
+        if let Some(previous) = previous {
+            let not_found = || {
+                ApiError::new(
+                    StatusCode::NOT_FOUND,
+                    format!("Previous response with id '{}' not found.", previous),
+                    "invalid_request_error",
+                    "previous_response_not_found",
+                )
+                .with_param("previous_response_id")
+            };
+            let id = ResponseId::parse(previous).ok_or_else(not_found)?;
+            let messages = store
+                .list_messages(id.thread_id.clone())
+                .await
+                .map_err(|e| {
+                    log::error!("Failed to read thread {}: {}", id.thread_id, e);
+                    not_found()
+                })?;
+            let position = messages
+                .iter()
+                .position(|message| {
+                    message.get("id").and_then(Value::as_str) == Some(id.message_id.as_str())
+                })
+                .ok_or_else(not_found)?;
+            if position + 1 == messages.len() {
+                thread_id = Some(id.thread_id);
+            }
+            history = messages;
+            history.truncate(position + 1);
+        }
+
+        let chat_request = to_chat_request(request, &history, &input)?;
+        let store = request
+            .get("store")
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+        let mut response = response_object(request, previous);
+        response["store"] = json!(store);
+        if !store {
+            response["id"] = json!(format!("resp_{}", uuid::Uuid::new_v4().simple()));
+        }
+
+        Ok(Self {
+            chat_request,
+            response,
+            input,
+            history,
+            thread_id,
+            store,
+        })
+    }
+
+    pub fn chat_request(&self) -> &Value {
+        &self.chat_request
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.chat_request
+            .get("stream")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+    }
+
+    /// Writes the input to the thread of the chain when the response is stored
+    /// and assigns the response its id
+    pub async fn begin(
+        &mut self,
+        store: &Arc<dyn ConversationStore>,
+    ) -> Result<Option<StoredReply>, String> {
+        if !self.store {
+            return Ok(None);
+        }
+
+        let writer = match self.thread_id.take() {
+            Some(thread_id) => {
+                let writer = ThreadWriter::open(store.clone(), thread_id.clone())
+                    .await?
+                    .ok_or_else(|| format!("Thread {} not found", thread_id))?;
+                writer.touch().await?;
+                writer
+            }
+            None => {
+                // New chains, and branches off an earlier response, get their own thread
+                let mut messages: Vec<Value> = self
+                    .history
+                    .iter()
+                    .filter_map(conversations::to_chat_message)
+                    .collect();
+                messages.extend(self.input.iter().cloned());
+                let model = self.response["model"].as_str().unwrap_or_default();
+                let writer = ThreadWriter::create(
+                    store.clone(),
+                    model,
+                    &conversations::thread_title(&messages),
+                    self.response["instructions"].as_str(),
+                )
+                .await?;
+                for message in &self.history {
+                    writer.copy(message.clone()).await?;
+                }
+                writer
+            }
+        };
+        for message in &self.input {
+            writer.append(message, None).await?;
+        }
+
+        let id = ResponseId {
+            thread_id: writer.thread_id().to_string(),
+            message_id: uuid::Uuid::new_v4().to_string(),
+        };
+        self.response["id"] = json!(id.to_string());
+        Ok(Some(StoredReply {
+            writer,
+            message_id: id.message_id,
+        }))
+    }
+
+    /// Builds the response from a chat completion, storing its output first
+    pub async fn finish(&self, completion: &Value, reply: Option<StoredReply>) -> Value {
+        if let Some(reply) = reply {
+            let message = completion
+                .pointer("/choices/0/message")
+                .cloned()
+                .unwrap_or_default();
+            reply.record(&message).await;
+        }
+        to_response(&self.response, completion)
+    }
+
+    /// Wraps a chat completion SSE body into a Responses event stream. The output
+    /// is stored before `response.completed` is sent, so clients can chain off it
+    /// right away.
+    pub fn into_stream(self, body: Body, reply: Option<StoredReply>) -> Body {
+        let translator = StreamTranslator::new(self.response);
+        let events = stream::unfold(
+            (body, translator, reply, false),
+            |(mut body, mut translator, reply, done)| async move {
+                if done {
+                    return None;
+                }
+                match body.next().await {
+                    Some(Ok(chunk)) => {
+                        let events = Bytes::from(translator.push(&chunk));
+                        Some((Ok(events), (body, translator, reply, false)))
+                    }
+                    Some(Err(e)) => Some((Err(e), (body, translator, None, true))),
+                    None => {
+                        if let Some(reply) = reply {
+                            reply.record(&translator.reply()).await;
+                        }
+                        let events = Bytes::from(translator.finish());
+                        Some((Ok(events), (body, translator, None, true)))
+                    }
+                }
+            },
+        );
+        Body::wrap_stream(events)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Response object echoing the request, before any output
+fn response_object(request: &Map<String, Value>, previous: Option<&str>) -> Value {
+    let field = |name: &str, default: Value| request.get(name).cloned().unwrap_or(default);
+    json!({
+        "id": null,
+        "object": "response",
+        "created_at": now(),
+        "status": "in_progress",
+        "model": request.get("model"),
+        "output": [],
+        "instructions": field("instructions", Value::Null),
+        "previous_response_id": previous,
+        "temperature": field("temperature", Value::Null),
+        "top_p": field("top_p", Value::Null),
+        "max_output_tokens": field("max_output_tokens", Value::Null),
+        "tools": field("tools", json!([])),
+        "tool_choice": field("tool_choice", json!("auto")),
+        "parallel_tool_calls": field("parallel_tool_calls", json!(true)),
+        "text": field("text", json!({"format": {"type": "text"}})),
+        "metadata": field("metadata", json!({})),
+        "user": field("user", Value::Null),
+        "error": null,
+        "incomplete_details": null,
+        "usage": null,
+    })
+}
+
+/// Converts the `input` of a request into chat messages
+fn input_messages(input: Option<&Value>) -> Result<Vec<Value>, ApiError> {
+    let items = match input {
+        Some(Value::String(text)) => return Ok(vec![json!({"role": "user", "content": text})]),
+        Some(Value::Array(items)) => items,
+        Some(_) => {
+            return Err(ApiError::invalid_request(
+                "Invalid type for 'input': expected a string or an array.",
+                "invalid_type",
+            )
+            .with_param("input"))
+        }
+        None => {
+            return Err(ApiError::invalid_request(
+                "Missing required parameter: 'input'.",
+                "missing_required_parameter",
+            )
+            .with_param("input"))
+        }
+    };
+
+    let mut messages = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        push_input_item(&mut messages, item).map_err(|message| {
+            ApiError::invalid_request(message, "invalid_value")
+                .with_param(format!("input[{}]", index))
+        })?;
+    }
+    Ok(messages)
+}
+
+fn push_input_item(messages: &mut Vec<Value>, item: &Value) -> Result<(), String> {
+    match item
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("message")
+    {
+        "message" => {
+            let role = item
+                .get("role")
+                .and_then(Value::as_str)
+                .ok_or("Missing required parameter: 'role'.")?;
+            let content = message_content(item.get("content"))?;
+            messages.push(json!({"role": role, "content": content}));
+        }
+        "function_call" => {
+            let tool_call = json!({
+                "id": item.get("call_id"),
+                "type": "function",
+                "function": {"name": item.get("name"), "arguments": item.get("arguments")},
+            });
+            // Parallel calls are separate items but one assistant message
+            match messages.last_mut() {
+                Some(Value::Object(last)) if last.contains_key("tool_calls") => {
+                    if let Some(Value::Array(tool_calls)) = last.get_mut("tool_calls") {
+                        tool_calls.push(tool_call);
+                    }
+                }
+                _ => messages.push(json!({
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [tool_call],
+                })),
+            }
+        }
+        "function_call_output" => {
+            let output = match item.get("output") {
+                Some(Value::String(output)) => output.clone(),
+                Some(output) => output.to_string(),
+                None => String::new(),
+            };
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": item.get("call_id"),
+                "content": output,
+            }));
+        }
+        // Reasoning from earlier turns is not sent back to the model
+        "reasoning" => {}
+        other => return Err(format!("Unsupported input item type: '{}'.", other)),
+    }
+    Ok(())
+}
+
+fn message_content(content: Option<&Value>) -> Result<Value, String> {
+    let parts = match content {
+        Some(Value::String(text)) => return Ok(json!(text)),
+        Some(Value::Array(parts)) => parts,
+        _ => return Err("Invalid type for 'content': expected a string or an array.".to_string()),
+    };
+
+    let mut converted = Vec::new();
+    for part in parts {
+        match part.get("type").and_then(Value::as_str) {
+            Some("input_text") | Some("output_text") => {
+                converted.push(json!({"type": "text", "text": part.get("text")}))
+            }
+            Some("refusal") => converted.push(json!({"type": "text", "text": part.get("refusal")})),
+            Some("input_image") => {
+                let url = part
+                    .get("image_url")
+                    .filter(|url| url.is_string())
+                    .ok_or("Only images given by 'image_url' are supported.")?;
+                converted.push(json!({
+                    "type": "image_url",
+                    "image_url": {"url": url, "detail": part.get("detail").unwrap_or(&json!("auto"))},
+                }));
+            }
+            other => {
+                return Err(format!(
+                    "Unsupported content type: '{}'.",
+                    other.unwrap_or_default()
+                ))
+            }
+        }
+    }
+
+    // Most local models only take text content as a plain string
+    if converted.iter().all(|part| part["type"] == "text") {
+        let text = converted
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        return Ok(json!(text));
+    }
+    Ok(json!(converted))
+}
+
+/// Builds the chat completions request: instructions, the previous turns and the new input
+fn to_chat_request(
+    request: &Map<String, Value>,
+    history: &[Value],
+    input: &[Value],
+) -> Result<Value, ApiError> {
+    let model = match request.get("model") {
+        Some(Value::String(model)) => model,
+        Some(_) => {
+            return Err(ApiError::invalid_request(
+                "Invalid type for 'model': expected a string.",
+                "invalid_type",
+            )
+            .with_param("model"))
+        }
+        None => {
+            return Err(ApiError::invalid_request(
+                "Missing required parameter: 'model'.",
+                "missing_required_parameter",
+            )
+            .with_param("model"))
+        }
+    };
+
+    let mut messages = Vec::new();
+    if let Some(instructions) = request.get("instructions").and_then(Value::as_str) {
+        messages.push(json!({"role": "system", "content": instructions}));
+    }
+    messages.extend(history.iter().filter_map(conversations::to_chat_message));
+    messages.extend(input.iter().cloned());
+
+    let mut chat = Map::new();
+    chat.insert("model".to_string(), json!(model));
+    chat.insert("messages".to_string(), json!(messages));
+    for (from, to) in [
+        ("temperature", "temperature"),
+        ("top_p", "top_p"),
+        ("max_output_tokens", "max_tokens"),
+        ("stream", "stream"),
+        ("user", "user"),
+        ("parallel_tool_calls", "parallel_tool_calls"),
+    ] {
+        if let Some(value) = request.get(from).filter(|value| !value.is_null()) {
+            chat.insert(to.to_string(), value.clone());
+        }
+    }
+
+    if let Some(tools) = request.get("tools").and_then(Value::as_array) {
+        let mut functions = Vec::new();
+        for (index, tool) in tools.iter().enumerate() {
+            if tool.get("type").and_then(Value::as_str) != Some("function") {
+                return Err(ApiError::invalid_request(
+                    "Only function tools are supported.",
+                    "invalid_value",
+                )
+                .with_param(format!("tools[{}].type", index)));
+            }
+            functions.push(json!({
+                "type": "function",
+                "function": {
+                    "name": tool.get("name"),
+                    "description": tool.get("description"),
+                    "parameters": tool.get("parameters"),
+                    "strict": tool.get("strict"),
+                },
+            }));
+        }
+        if !functions.is_empty() {
+            chat.insert("tools".to_string(), json!(functions));
+        }
+    }
+    match request.get("tool_choice") {
+        Some(Value::String(choice)) => {
+            chat.insert("tool_choice".to_string(), json!(choice));
+        }
+        Some(choice) if choice.get("type").and_then(Value::as_str) == Some("function") => {
+            chat.insert(
+                "tool_choice".to_string(),
+                json!({"type": "function", "function": {"name": choice.get("name")}}),
+            );
+        }
+        _ => {}
+    }
+
+    if let Some(format) = request.get("text").and_then(|text| text.get("format")) {
+        match format.get("type").and_then(Value::as_str) {
+            Some("json_schema") => {
+                chat.insert(
+                    "response_format".to_string(),
+                    json!({
+                        "type": "json_schema",
+                        "json_schema": {
+                            "name": format.get("name"),
+                            "schema": format.get("schema"),
+                            "strict": format.get("strict"),
+                        },
+                    }),
+                );
+            }
+            Some("json_object") => {
+                chat.insert(
+                    "response_format".to_string(),
+                    json!({"type": "json_object"}),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Value::Object(chat))
+}
+
+/// An output item being assembled from a chat completion
+#[derive(Debug, Clone)]
+enum Item {
+    Message {
+        id: String,
+        text: String,
+    },
+    FunctionCall {
+        id: String,
+        call_id: String,
+        name: String,
+        arguments: String,
+        /// Index of the tool call in the chat completion chunks
+        index: u64,
+    },
+}
+
+impl Item {
+    fn message(text: &str) -> Self {
+        Self::Message {
+            id: format!("msg_{}", uuid::Uuid::new_v4().simple()),
+            text: text.to_string(),
+        }
+    }
+
+    fn function_call(tool_call: &Value, index: u64) -> Self {
+        let field = |pointer: &str| {
+            tool_call
+                .pointer(pointer)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        Self::FunctionCall {
+            id: format!("fc_{}", uuid::Uuid::new_v4().simple()),
+            call_id: field("/id"),
+            name: field("/function/name"),
+            arguments: field("/function/arguments"),
+            index,
+        }
+    }
+
+    fn id(&self) -> &str {
+        match self {
+            Self::Message { id, .. } | Self::FunctionCall { id, .. } => id,
+        }
+    }
+
+    fn to_json(&self, status: &str) -> Value {
+        match self {
+            Self::Message { id, text } => json!({
+                "type": "message",
+                "id": id,
+                "status": status,
+                "role": "assistant",
+                "content": if status == "completed" { vec![output_text(text)] } else { vec![] },
+            }),
+            Self::FunctionCall {
+                id,
+                call_id,
+                name,
+                arguments,
+                ..
+            } => json!({
+                "type": "function_call",
+                "id": id,
+                "call_id": call_id,
+                "name": name,
+                "arguments": if status == "completed" { arguments.as_str() } else { "" },
+                "status": status,
+            }),
+        }
+    }
+}
+
+fn output_text(text: &str) -> Value {
+    json!({"type": "output_text", "text": text, "annotations": []})
+}
+
+/// Response status and incomplete details for a chat completion finish reason
+fn status(finish_reason: Option<&str>) -> (&'static str, Value) {
+    match finish_reason {
+        Some("length") => ("incomplete", json!({"reason": "max_output_tokens"})),
+        Some("content_filter") => ("incomplete", json!({"reason": "content_filter"})),
+        _ => ("completed", Value::Null),
+    }
+}
+
+fn usage(usage: Option<&Value>) -> Value {
+    let Some(usage) = usage.filter(|usage| usage.is_object()) else {
+        return Value::Null;
+    };
+    let tokens = |name: &str| usage.get(name).and_then(Value::as_u64).unwrap_or(0);
+    json!({
+        "input_tokens": tokens("prompt_tokens"),
+        "input_tokens_details": {"cached_tokens": 0},
+        "output_tokens": tokens("completion_tokens"),
+        "output_tokens_details": {"reasoning_tokens": 0},
+        "total_tokens": tokens("total_tokens"),
+    })
+}
+
+/// Completes a response object from a chat completion
+fn to_response(response: &Value, completion: &Value) -> Value {
+    let choice = completion
+        .pointer("/choices/0")
+        .cloned()
+        .unwrap_or_default();
+    let message = choice.get("message").cloned().unwrap_or_default();
+
+    let mut items = Vec::new();
+    if let Some(text) = message
+        .get("content")
+        .and_then(Value::as_str)
+        .filter(|text| !text.is_empty())
+    {
+        items.push(Item::message(text));
+    }
+    for (index, tool_call) in message
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        items.push(Item::function_call(tool_call, index as u64));
+    }
+
+    let (status, incomplete_details) = status(choice.get("finish_reason").and_then(Value::as_str));
+    let mut response = response.clone();
+    response["status"] = json!(status);
+    response["incomplete_details"] = incomplete_details;
+    response["output"] = items.iter().map(|item| item.to_json("completed")).collect();
+    response["usage"] = usage(completion.get("usage"));
+    response
+}
+
+/// Turns chat completion chunks into Responses API stream events
+struct StreamTranslator {
+    response: Value,
+    reader: SseReader,
+    sequence_number: u64,
+    started: bool,
+    finished: bool,
+    items: Vec<Item>,
+    /// Whether the last item still receives deltas
+    open: bool,
+    finish_reason: Option<String>,
+    usage: Option<Value>,
+}
+
+impl StreamTranslator {
+    fn new(response: Value) -> Self {
+        Self {
+            response,
+            reader: SseReader::default(),
+            sequence_number: 0,
+            started: false,
+            finished: false,
+            items: Vec::new(),
+            open: false,
+            finish_reason: None,
+            usage: None,
+        }
+    }
+
+    /// Feeds raw SSE bytes from upstream, returns the events to send
+    fn push(&mut self, bytes: &[u8]) -> String {
+        let mut events = String::new();
+        for data in self.reader.push(bytes) {
+            // The response is completed once upstream closes the stream
+            if data == "[DONE]" {
+                continue;
+            }
+            if let Ok(chunk) = serde_json::from_str::<Value>(&data) {
+                events.push_str(&self.chunk(&chunk));
+            }
+        }
+        events
+    }
+
+    fn event(&mut self, event_type: &str, mut data: Value) -> String {
+        data["type"] = json!(event_type);
+        data["sequence_number"] = json!(self.sequence_number);
+        self.sequence_number += 1;
+        format!("event: {}\ndata: {}\n\n", event_type, data)
+    }
+
+    fn start(&mut self) -> String {
+        if self.started {
+            return String::new();
+        }
+        self.started = true;
+        let response = self.response.clone();
+        let mut events = self.event("response.created", json!({"response": response}));
+        events.push_str(&self.event("response.in_progress", json!({"response": response})));
+        events
+    }
+
+    fn chunk(&mut self, chunk: &Value) -> String {
+        let mut events = self.start();
+        if let Some(usage) = chunk.get("usage").filter(|usage| usage.is_object()) {
+            self.usage = Some(usage.clone());
+        }
+        let Some(choice) = chunk.pointer("/choices/0") else {
+            return events;
+        };
+
+        if let Some(text) = choice
+            .pointer("/delta/content")
+            .and_then(Value::as_str)
+            .filter(|text| !text.is_empty())
+        {
+            if !(self.open && matches!(self.items.last(), Some(Item::Message { .. }))) {
+                events.push_str(&self.open_item(Item::message("")));
+            }
+            let output_index = self.items.len() - 1;
+            if let Some(Item::Message { id, text: output }) = self.items.last_mut() {
+                output.push_str(text);
+                let item_id = id.clone();
+                events.push_str(&self.event(
+                    "response.output_text.delta",
+                    json!({
+                        "item_id": item_id,
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "delta": text,
+                        "logprobs": [],
+                    }),
+                ));
+            }
+        }
+
+        for tool_call in choice
+            .pointer("/delta/tool_calls")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let index = tool_call.get("index").and_then(Value::as_u64).unwrap_or(0);
+            let position = self.items.iter().position(
+                |item| matches!(item, Item::FunctionCall { index: i, .. } if *i == index),
+            );
+            let output_index = match position {
+                Some(position) => position,
+                None => {
+                    let mut item = Item::function_call(tool_call, index);
+                    // Arguments arrive as deltas, also in the first chunk
+                    if let Item::FunctionCall { arguments, .. } = &mut item {
+                        arguments.clear();
+                    }
+                    events.push_str(&self.open_item(item));
+                    self.items.len() - 1
+                }
+            };
+            let Some(delta) = tool_call
+                .pointer("/function/arguments")
+                .and_then(Value::as_str)
+                .filter(|delta| !delta.is_empty())
+            else {
+                continue;
+            };
+            if let Item::FunctionCall { id, arguments, .. } = &mut self.items[output_index] {
+                arguments.push_str(delta);
+                let item_id = id.clone();
+                events.push_str(&self.event(
+                    "response.function_call_arguments.delta",
+                    json!({"item_id": item_id, "output_index": output_index, "delta": delta}),
+                ));
+            }
+        }
+
+        if let Some(finish_reason) = choice.get("finish_reason").and_then(Value::as_str) {
+            self.finish_reason = Some(finish_reason.to_string());
+        }
+        events
+    }
+
+    fn open_item(&mut self, item: Item) -> String {
+        let mut events = self.close_item();
+        let output_index = self.items.len();
+        events.push_str(&self.event(
+            "response.output_item.added",
+            json!({"output_index": output_index, "item": item.to_json("in_progress")}),
+        ));
+        if let Item::Message { id, .. } = &item {
+            events.push_str(&self.event(
+                "response.content_part.added",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": output_text(""),
+                }),
+            ));
+        }
+        self.items.push(item);
+        self.open = true;
+        events
+    }
+
+    fn close_item(&mut self) -> String {
+        if !self.open {
+            return String::new();
+        }
+        self.open = false;
+        let Some(item) = self.items.last().cloned() else {
+            return String::new();
+        };
+        let output_index = self.items.len() - 1;
+        let item_id = item.id().to_string();
+        let done = item.to_json("completed");
+
+        let mut events = String::new();
+        match item {
+            Item::Message { text, .. } => {
+                events.push_str(&self.event(
+                    "response.output_text.done",
+                    json!({
+                        "item_id": item_id,
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "text": text,
+                        "logprobs": [],
+                    }),
+                ));
+                events.push_str(&self.event(
+                    "response.content_part.done",
+                    json!({
+                        "item_id": item_id,
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "part": output_text(&text),
+                    }),
+                ));
+            }
+            Item::FunctionCall { arguments, .. } => {
+                events.push_str(&self.event(
+                    "response.function_call_arguments.done",
+                    json!({"item_id": item_id, "output_index": output_index, "arguments": arguments}),
+                ));
+            }
+        }
+        events.push_str(&self.event(
+            "response.output_item.done",
+            json!({"output_index": output_index, "item": done}),
+        ));
+        events
+    }
+
+    /// The assistant message produced so far, as a chat completion message
+    fn reply(&self) -> Value {
+        let mut text = String::new();
+        let mut tool_calls = Vec::new();
+        for item in &self.items {
+            match item {
+                Item::Message { text: output, .. } => text.push_str(output),
+                Item::FunctionCall {
+                    call_id,
+                    name,
+                    arguments,
+                    ..
+                } => tool_calls.push(json!({
+                    "id": call_id,
+                    "type": "function",
+                    "function": {"name": name, "arguments": arguments},
+                })),
+            }
+        }
+        let mut reply = json!({"role": "assistant", "content": text});
+        if !tool_calls.is_empty() {
+            reply["tool_calls"] = json!(tool_calls);
+        }
+        reply
+    }
+
+    /// Closes the response, also called when upstream ends without `[DONE]`
+    fn finish(&mut self) -> String {
+        if self.finished {
+            return String::new();
+        }
+        self.finished = true;
+
+        let mut events = self.start();
+        events.push_str(&self.close_item());
+        let (status, incomplete_details) = status(self.finish_reason.as_deref());
+        let mut response = self.response.clone();
+        response["status"] = json!(status);
+        response["incomplete_details"] = incomplete_details;
+        response["output"] = self
+            .items
+            .iter()
+            .map(|item| item.to_json("completed"))
+            .collect();
+        response["usage"] = usage(self.usage.as_ref());
+        events.push_str(&self.event(
+            &format!("response.{}", status),
+            json!({"response": response}),
+        ));
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_translation() {
+        let request = json!({
+            "model": "llama3.2-3b-instruct",
+            "instructions": "Be brief.",
+            "input": [
+                {"role": "user", "content": [{"type": "input_text", "text": "Weather in Hanoi?"}]},
+                {"type": "function_call", "call_id": "call_1", "name": "weather", "arguments": "{}"},
+                {"type": "function_call_output", "call_id": "call_1", "output": "Sunny"}
+            ],
+            "max_output_tokens": 64,
+            "tools": [{"type": "function", "name": "weather", "parameters": {"type": "object"}}],
+            "text": {"format": {"type": "json_object"}}
+        });
+        let request = request.as_object().unwrap();
+        let input = input_messages(request.get("input")).unwrap();
+        let history = [conversations::to_thread_message(
+            "thread-1",
+            "message-1",
+            &json!({"role": "user", "content": "Hi"}),
+        )];
+        let chat = to_chat_request(request, &history, &input).unwrap();
+
+        assert_eq!(chat["messages"][0]["content"], "Be brief.");
+        assert_eq!(chat["messages"][1]["content"], "Hi");
+        assert_eq!(chat["messages"][2]["content"], "Weather in Hanoi?");
+        assert_eq!(chat["messages"][3]["tool_calls"][0]["id"], "call_1");
+        assert_eq!(chat["messages"][4]["role"], "tool");
+        assert_eq!(chat["max_tokens"], 64);
+        assert_eq!(chat["tools"][0]["function"]["name"], "weather");
+        assert_eq!(chat["response_format"]["type"], "json_object");
+
+        let id = ResponseId::parse("resp_5f0e8a3c-4b1d-4a8e-9c6f-1d2e3f4a5b6c_message-1").unwrap();
+        assert_eq!(id.message_id, "message-1");
+        assert_eq!(ResponseId::parse("resp_../../etc_message-1"), None);
+    }
+
+    #[test]
+    fn test_stream_translation() {
+        let mut translator = StreamTranslator::new(json!({"id": "resp_1", "output": []}));
+        let mut events = translator.push(
+            b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\n\ndata: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+        );
+        assert_eq!(translator.reply()["content"], "Hello");
+        events.push_str(&translator.finish());
+
+        let names: Vec<&str> = events
+            .lines()
+            .filter_map(|line| line.strip_prefix("event: "))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "response.created",
+                "response.in_progress",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+                "response.completed",
+            ]
+        );
+        assert!(events.contains("\"text\":\"Hello\""));
+    }
+}
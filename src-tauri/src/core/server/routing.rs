@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::upstream::{Upstream, UpstreamConfig};
+
+/// Endpoints whose request body carries a `model` field used for routing
+const MODEL_ROUTED_PATHS: [&str; 3] = ["/v1/chat/completions", "/v1/completions", "/v1/embeddings"];
+
+/// Maps a model id, or a glob pattern over model ids, to a dedicated upstream
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModelRoute {
+    /// Exact model id or glob pattern, `*` matches any run of characters and `?` a single one
+    pub pattern: String,
+    /// Base URL of the upstream serving the matching models, without the `/v1` suffix
+    pub upstream: String,
+    /// Bearer token sent to the upstream, if it requires one
+    #[serde(default)]
+    pub api_key: Option<String>,
+}
+
+/// Routing table consulted before falling back to the upstream pool
+#[derive(Debug, Default)]
+pub struct ModelRouter {
+    routes: Vec<(String, Arc<Upstream>)>,
+}
+
+impl ModelRouter {
+    pub fn new(routes: Vec<ModelRoute>) -> Self {
+        let routes = routes
+            .into_iter()
+            .map(|route| {
+                let upstream = Upstream::new(UpstreamConfig {
+                    url: route.upstream,
+                    api_key: route.api_key,
+                    priority: 0,
+                    health_path: String::new(),
+                    cancel_path: None,
+                });
+                (route.pattern, Arc::new(upstream))
+            })
+            .collect();
+        Self { routes }
+    }
+
+    /// Returns the upstream of the first route matching the model, in configuration order
+    pub fn resolve(&self, model: &str) -> Option<Arc<Upstream>> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, model))
+            .map(|(_, upstream)| upstream.clone())
+    }
+}
+
+/// Checks whether requests to this path are routed by their `model` field
+pub fn is_model_routed_path(path: &str) -> bool {
+    MODEL_ROUTED_PATHS.contains(&path)
+}
+
+/// Reads the `model` field from a JSON request body
+pub fn extract_model(json: &Value) -> Option<String> {
+    json.get("model")?.as_str().map(|model| model.to_string())
+}
+
+/// Matches text against a glob pattern supporting `*` and `?`
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and the text index it was matched at
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            // Let the last `*` swallow one more character and retry
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("gpt-4o", "gpt-4o"));
+        assert!(!glob_match("gpt-4o", "gpt-4o-mini"));
+        assert!(glob_match("gpt-*", "gpt-4o-mini"));
+        assert!(glob_match("*:free", "llama3:free"));
+        assert!(glob_match("llama3.?-*", "llama3.1-8b-instruct"));
+        assert!(!glob_match("claude-*", "gpt-4o"));
+        assert!(glob_match("*", "anything"));
+    }
+
+    #[test]
+    fn test_resolve_uses_first_matching_route() {
+        let router = ModelRouter::new(vec![
+            ModelRoute {
+                pattern: "gpt-4o-mini".to_string(),
+                upstream: "http://mini".to_string(),
+                api_key: None,
+            },
+            ModelRoute {
+                pattern: "gpt-*".to_string(),
+                upstream: "https://api.openai.com".to_string(),
+                api_key: Some("sk-test".to_string()),
+            },
+        ]);
+
+        assert_eq!(router.resolve("gpt-4o-mini").unwrap().url, "http://mini");
+        let upstream = router.resolve("gpt-4.1").unwrap();
+        assert_eq!(upstream.url, "https://api.openai.com");
+        assert_eq!(upstream.api_key.as_deref(), Some("sk-test"));
+        assert!(router.resolve("llama3.1-8b-instruct").is_none());
+    }
+}
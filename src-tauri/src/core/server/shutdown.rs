@@ -0,0 +1,115 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Time given to connections to close after their streams have been cancelled
+pub const KILL_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Outcome of a graceful shutdown, returned by `stop_server`
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ShutdownSummary {
+    /// Streams and WebSocket sessions that finished on their own before the deadline
+    pub drained: u32,
+    /// Streams and WebSocket sessions still running at the deadline, which got cancelled
+    pub killed: u32,
+}
+
+/// Keeps track of the responses being streamed to clients and of WebSocket
+/// sessions, so a shutdown can wait for them and cancel the ones that
+/// outlive the drain deadline
+#[derive(Debug, Default)]
+pub struct StreamTracker {
+    active: AtomicU32,
+    cancel: CancellationToken,
+    idle: Notify,
+}
+
+impl StreamTracker {
+    /// Registers a stream, which counts as active until the guard is dropped
+    pub fn track(self: &Arc<Self>) -> StreamGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        StreamGuard {
+            tracker: self.clone(),
+        }
+    }
+
+    pub fn active(&self) -> u32 {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once no stream is left
+    pub async fn drained(&self) {
+        loop {
+            // Registered before checking, so a stream ending in between is not missed
+            let idle = self.idle.notified();
+            if self.active() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Asks every tracked stream to stop forwarding, returns how many were running
+    pub fn cancel_all(&self) -> u32 {
+        self.cancel.cancel();
+        self.active()
+    }
+}
+
+/// Held by a forwarding task for the lifetime of its stream
+#[derive(Debug)]
+pub struct StreamGuard {
+    tracker: Arc<StreamTracker>,
+}
+
+impl StreamGuard {
+    /// Resolves once the server gives up on draining this stream
+    pub async fn cancelled(&self) {
+        self.tracker.cancel.cancelled().await
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        if self.tracker.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.tracker.idle.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_reaches_tracked_streams() {
+        let tracker = Arc::new(StreamTracker::default());
+        let finished = tracker.track();
+        let running = tracker.track();
+        assert_eq!(tracker.active(), 2);
+
+        drop(finished);
+        assert_eq!(tracker.cancel_all(), 1);
+        running.cancelled().await;
+
+        drop(running);
+        assert_eq!(tracker.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drained_waits_for_the_last_stream() {
+        let tracker = Arc::new(StreamTracker::default());
+        let session = tracker.track();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            drop(session);
+        });
+
+        tokio::time::timeout(std::time::Duration::from_secs(1), tracker.drained())
+            .await
+            .expect("the stream ended");
+        assert_eq!(tracker.active(), 0);
+    }
+}
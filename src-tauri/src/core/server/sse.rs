@@ -0,0 +1,48 @@
+/// Splits a server-sent event stream into the payloads of its `data:` lines.
+/// Bytes are held until their line is complete, so a character split across
+/// two network chunks is decoded whole.
+#[derive(Debug, Default)]
+pub struct SseReader {
+    pending: Vec<u8>,
+}
+
+impl SseReader {
+    /// Feeds raw bytes, returns the data of every line they complete
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(bytes);
+        // A newline byte never occurs inside a multibyte UTF-8 sequence
+        let Some(end) = self.pending.iter().rposition(|byte| *byte == b'\n') else {
+            return Vec::new();
+        };
+        let lines: Vec<u8> = self.pending.drain(..=end).collect();
+        String::from_utf8_lossy(&lines)
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("data:"))
+            .map(|data| data.trim().to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_waits_for_complete_lines() {
+        let mut reader = SseReader::default();
+        assert!(reader.push(b"event: ping\ndata: {\"a\"").is_empty());
+        assert_eq!(
+            reader.push(b":1}\n\ndata: [DONE]\n"),
+            ["{\"a\":1}", "[DONE]"]
+        );
+    }
+
+    #[test]
+    fn test_reader_keeps_split_characters_whole() {
+        let line = "data: {\"content\":\"héllo 👋\"}\n".as_bytes();
+        let split = line.len() - 5; // Inside the emoji
+        let mut reader = SseReader::default();
+        assert!(reader.push(&line[..split]).is_empty());
+        assert_eq!(reader.push(&line[split..]), ["{\"content\":\"héllo 👋\"}"]);
+    }
+}
@@ -0,0 +1,257 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Arc;
+use sysinfo::System;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{self, crypto::ring};
+use tokio_rustls::TlsAcceptor;
+
+/// Self-signed certificate files, stored in the `certs` folder of the Jan data folder
+const SELF_SIGNED_CERT_FILE: &str = "server-cert.pem";
+const SELF_SIGNED_KEY_FILE: &str = "server-key.pem";
+/// Names the self-signed certificate is valid for, one per line
+const SELF_SIGNED_NAMES_FILE: &str = "server-cert.names";
+
+/// HTTPS settings of the local server.
+/// Without a certificate and key a self-signed certificate is generated and reused.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    /// PEM file with the private key
+    #[serde(default)]
+    pub key_path: Option<String>,
+}
+
+/// Certificate and key the server presents to clients
+pub struct TlsIdentity {
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    /// SHA-256 fingerprint of the leaf certificate, as shown by browsers
+    pub fingerprint: String,
+}
+
+impl TlsIdentity {
+    /// Loads the configured certificate, or the self-signed one from `certs_dir`
+    pub fn load(
+        config: &TlsConfig,
+        certs_dir: &Path,
+        host: &str,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let (cert_path, key_path) = match (&config.cert_path, &config.key_path) {
+            (Some(cert_path), Some(key_path)) => (cert_path.into(), key_path.into()),
+            (None, None) => {
+                let cert_path = certs_dir.join(SELF_SIGNED_CERT_FILE);
+                let key_path = certs_dir.join(SELF_SIGNED_KEY_FILE);
+                let names = subject_alt_names(host);
+                // A certificate made for another host is replaced
+                let covers_host = fs::read_to_string(certs_dir.join(SELF_SIGNED_NAMES_FILE))
+                    .is_ok_and(|stored| {
+                        names
+                            .iter()
+                            .all(|name| stored.lines().any(|line| line == name))
+                    });
+                if !cert_path.exists() || !key_path.exists() || !covers_host {
+                    generate_self_signed(certs_dir, names)?;
+                }
+                (cert_path, key_path)
+            }
+            _ => return Err("Both a certificate and a private key are required for TLS".into()),
+        };
+
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&cert_path)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        let leaf = certs
+            .first()
+            .ok_or_else(|| format!("No certificate found in {:?}", cert_path))?;
+        let fingerprint = fingerprint(leaf);
+
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&key_path)?))?
+            .ok_or_else(|| format!("No private key found in {:?}", key_path))?;
+
+        Ok(Self {
+            certs,
+            key,
+            fingerprint,
+        })
+    }
+
+    pub fn into_acceptor(self) -> Result<TlsAcceptor, Box<dyn std::error::Error + Send + Sync>> {
+        let config =
+            rustls::ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_single_cert(self.certs, self.key)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Names a self-signed certificate has to be valid for: localhost and the bind host.
+/// A server bound to every interface is reached through the machine's own addresses.
+fn subject_alt_names(host: &str) -> Vec<String> {
+    let mut subject_alt_names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    let hosts = if host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified()) {
+        machine_names()
+    } else {
+        vec![host.to_string()]
+    };
+    for host in hosts {
+        if !host.is_empty() && !subject_alt_names.contains(&host) {
+            subject_alt_names.push(host);
+        }
+    }
+    subject_alt_names
+}
+
+/// Host name of the machine, with its mDNS name, and the addresses of its interfaces
+fn machine_names() -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some(host_name) = System::host_name() {
+        names.push(format!("{}.local", host_name.trim_end_matches(".local")));
+        names.push(host_name);
+    }
+    match if_addrs::get_if_addrs() {
+        Ok(interfaces) => names.extend(
+            interfaces
+                .iter()
+                .filter(|interface| !interface.is_loopback())
+                .map(|interface| interface.ip().to_string()),
+        ),
+        Err(e) => log::warn!("Failed to list the network interfaces: {}", e),
+    }
+    names
+}
+
+/// Writes a file only the current user can read
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, a key left by an older version is fixed too
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents)
+}
+
+/// Generates a self-signed certificate valid for the given names
+fn generate_self_signed(
+    certs_dir: &Path,
+    subject_alt_names: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let names = subject_alt_names.join("\n");
+    let certified = rcgen::generate_simple_self_signed(subject_alt_names)?;
+    fs::create_dir_all(certs_dir)?;
+    write_private(
+        &certs_dir.join(SELF_SIGNED_KEY_FILE),
+        certified.key_pair.serialize_pem().as_bytes(),
+    )?;
+    fs::write(certs_dir.join(SELF_SIGNED_CERT_FILE), certified.cert.pem())?;
+    fs::write(certs_dir.join(SELF_SIGNED_NAMES_FILE), names)?;
+    log::info!("Generated self-signed certificate in {:?}", certs_dir);
+    Ok(())
+}
+
+/// Formats the SHA-256 digest of a certificate as colon-separated hex
+fn fingerprint(cert: &CertificateDer<'_>) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_self_signed_certificate_is_reused() {
+        let certs_dir = std::env::temp_dir().join(format!("jan-tls-{}", uuid::Uuid::new_v4()));
+        let config = TlsConfig::default();
+
+        let first = TlsIdentity::load(&config, &certs_dir, "192.168.1.10").unwrap();
+        let second = TlsIdentity::load(&config, &certs_dir, "192.168.1.10").unwrap();
+
+        assert_eq!(first.fingerprint, second.fingerprint);
+        assert_eq!(first.fingerprint.split(':').count(), 32);
+        assert!(second.into_acceptor().is_ok());
+
+        fs::remove_dir_all(certs_dir).unwrap();
+    }
+
+    #[test]
+    fn test_self_signed_certificate_follows_the_host() {
+        let certs_dir = std::env::temp_dir().join(format!("jan-tls-{}", uuid::Uuid::new_v4()));
+        let config = TlsConfig::default();
+
+        let first = TlsIdentity::load(&config, &certs_dir, "192.168.1.10").unwrap();
+        let moved = TlsIdentity::load(&config, &certs_dir, "192.168.1.20").unwrap();
+        assert_ne!(first.fingerprint, moved.fingerprint);
+        // Loopback names are always covered
+        let local = TlsIdentity::load(&config, &certs_dir, "127.0.0.1").unwrap();
+        assert_eq!(moved.fingerprint, local.fingerprint);
+
+        fs::remove_dir_all(certs_dir).unwrap();
+    }
+
+    #[test]
+    fn test_self_signed_certificate_covers_every_interface() {
+        let names = subject_alt_names("0.0.0.0");
+        assert!(!names.iter().any(|name| name == "0.0.0.0"));
+        for name in machine_names() {
+            assert!(names.contains(&name));
+        }
+        assert_eq!(subject_alt_names("::"), names);
+
+        // A certificate missing a current address is replaced
+        let certs_dir = std::env::temp_dir().join(format!("jan-tls-{}", uuid::Uuid::new_v4()));
+        let config = TlsConfig::default();
+        let first = TlsIdentity::load(&config, &certs_dir, "0.0.0.0").unwrap();
+        let names_file = certs_dir.join(SELF_SIGNED_NAMES_FILE);
+        fs::write(&names_file, "localhost\n127.0.0.1\n::1").unwrap();
+        let second = TlsIdentity::load(&config, &certs_dir, "0.0.0.0").unwrap();
+        assert_eq!(names.len() > 3, first.fingerprint != second.fingerprint);
+        assert_eq!(fs::read_to_string(names_file).unwrap(), names.join("\n"));
+
+        fs::remove_dir_all(certs_dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_self_signed_key_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let certs_dir = std::env::temp_dir().join(format!("jan-tls-{}", uuid::Uuid::new_v4()));
+        TlsIdentity::load(&TlsConfig::default(), &certs_dir, "localhost").unwrap();
+
+        let mode = fs::metadata(certs_dir.join(SELF_SIGNED_KEY_FILE))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_dir_all(certs_dir).unwrap();
+    }
+
+    #[test]
+    fn test_cert_without_key_is_rejected() {
+        let config = TlsConfig {
+            cert_path: Some("cert.pem".to_string()),
+            key_path: None,
+        };
+        assert!(TlsIdentity::load(&config, &std::env::temp_dir(), "localhost").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use super::errors::ApiError;
+
+/// Longest tool description sent to the model, as the UI does
+const DESCRIPTION_LENGTH: usize = 1024;
+
+/// Settings of the server-side MCP tool loop, disabled by default
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct McpToolsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Tool rounds per request, after that the model has to answer without tools
+    #[serde(default = "default_max_iterations")]
+    pub max_iterations: u32,
+}
+
+fn default_max_iterations() -> u32 {
+    10
+}
+
+impl Default for McpToolsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_iterations: default_max_iterations(),
+        }
+    }
+}
+
+/// The MCP servers the app is connected to
+pub trait ToolProvider: Send + Sync {
+    /// Tools of all connected servers, in MCP's JSON shape
+    fn list_tools(&self) -> BoxFuture<'_, Result<Vec<Value>, String>>;
+
+    /// Calls a tool by name and returns its `CallToolResult`
+    fn call_tool(
+        &self,
+        name: String,
+        arguments: Option<Map<String, Value>>,
+    ) -> BoxFuture<'_, Result<Value, String>>;
+}
+
+fn is_mcp_reference(tool: &Value) -> bool {
+    tool.get("type").and_then(Value::as_str) == Some("mcp")
+}
+
+/// Whether a chat request references MCP tools with `{"type": "mcp", "name": ...}`
+pub fn references_mcp_tools(request: &Value) -> bool {
+    request
+        .get("tools")
+        .and_then(Value::as_array)
+        .is_some_and(|tools| tools.iter().any(is_mcp_reference))
+}
+
+/// Replaces the MCP tool references of a request with the schemas of those
+/// tools, returning their names
+pub async fn inject_tools(
+    request: &mut Value,
+    provider: &dyn ToolProvider,
+) -> Result<Vec<String>, ApiError> {
+    let available = provider.list_tools().await.map_err(|e| {
+        ApiError::new(
+            hyper::StatusCode::BAD_GATEWAY,
+            format!("Failed to list MCP tools: {}", e),
+            "server_error",
+            "mcp_error",
+        )
+    })?;
+
+    let tools = request
+        .get("tools")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let mut names = Vec::new();
+    let mut functions = Vec::new();
+    for (index, tool) in tools.into_iter().enumerate() {
+        if !is_mcp_reference(&tool) {
+            functions.push(tool);
+            continue;
+        }
+        let name = tool.get("name").and_then(Value::as_str).ok_or_else(|| {
+            ApiError::invalid_request(
+                format!("Missing required parameter: 'tools[{}].name'.", index),
+                "missing_required_parameter",
+            )
+            .with_param(format!("tools[{}].name", index))
+        })?;
+        let schema = available
+            .iter()
+            .find(|tool| tool.get("name").and_then(Value::as_str) == Some(name))
+            .ok_or_else(|| {
+                ApiError::invalid_request(
+                    format!("MCP tool '{}' is not available.", name),
+                    "mcp_tool_not_found",
+                )
+                .with_param(format!("tools[{}].name", index))
+            })?;
+        let description = schema
+            .get("description")
+            .and_then(Value::as_str)
+            .map(|description| {
+                description
+                    .chars()
+                    .take(DESCRIPTION_LENGTH)
+                    .collect::<String>()
+            });
+        functions.push(json!({
+            "type": "function",
+            "function": {
+                "name": name,
+                "description": description,
+                "parameters": schema.get("inputSchema"),
+                "strict": false,
+            },
+        }));
+        names.push(name.to_string());
+    }
+
+    request["tools"] = json!(functions);
+    Ok(names)
+}
+
+/// The tool calls of a completion when they are all for MCP tools. Calls to
+/// the client's own functions are left to the client.
+pub fn mcp_tool_calls(completion: &Value, names: &[String]) -> Option<Vec<Value>> {
+    let tool_calls = completion
+        .pointer("/choices/0/message/tool_calls")
+        .and_then(Value::as_array)
+        .filter(|tool_calls| !tool_calls.is_empty())?;
+    let all_mcp = tool_calls.iter().all(|tool_call| {
+        tool_call
+            .pointer("/function/name")
+            .and_then(Value::as_str)
+            .is_some_and(|name| names.iter().any(|mcp| mcp == name))
+    });
+    all_mcp.then(|| tool_calls.clone())
+}
+
+/// Runs a tool call and returns the tool message to send back to the model.
+/// Failures are reported to the model rather than the client.
+pub async fn run_tool_call(provider: &dyn ToolProvider, tool_call: &Value) -> Value {
+    let name = tool_call
+        .pointer("/function/name")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let arguments = tool_call
+        .pointer("/function/arguments")
+        .and_then(Value::as_str)
+        .filter(|arguments| !arguments.trim().is_empty())
+        .unwrap_or("{}");
+
+    let content = match serde_json::from_str::<Map<String, Value>>(arguments) {
+        Ok(arguments) => match provider.call_tool(name.to_string(), Some(arguments)).await {
+            Ok(result) => result_text(&result),
+            Err(e) => {
+                log::warn!("MCP tool call '{}' failed: {}", name, e);
+                format!("Error calling tool {}: {}", name, e)
+            }
+        },
+        Err(e) => format!("Error calling tool {}: invalid arguments: {}", name, e),
+    };
+    json!({
+        "role": "tool",
+        "tool_call_id": tool_call.get("id"),
+        "content": content,
+    })
+}
+
+/// Text content of a `CallToolResult`
+fn result_text(result: &Value) -> String {
+    result
+        .get("content")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|content| content.get("text").and_then(Value::as_str))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Weather;
+
+    impl ToolProvider for Weather {
+        fn list_tools(&self) -> BoxFuture<'_, Result<Vec<Value>, String>> {
+            Box::pin(async {
+                Ok(vec![json!({
+                    "name": "weather",
+                    "description": "Current weather",
+                    "inputSchema": {"type": "object", "properties": {"city": {"type": "string"}}},
+                })])
+            })
+        }
+
+        fn call_tool(
+            &self,
+            _name: String,
+            arguments: Option<Map<String, Value>>,
+        ) -> BoxFuture<'_, Result<Value, String>> {
+            Box::pin(async move {
+                let city = arguments.unwrap_or_default()["city"].clone();
+                Ok(json!({"content": [{"type": "text", "text": format!("Sunny in {}", city)}]}))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_loop_helpers() {
+        let mut request = json!({
+            "model": "llama3.2-3b-instruct",
+            "messages": [{"role": "user", "content": "Weather in Hanoi?"}],
+            "tools": [{"type": "mcp", "name": "weather"}],
+        });
+        assert!(references_mcp_tools(&request));
+        let names = inject_tools(&mut request, &Weather).await.unwrap();
+        assert_eq!(names, ["weather"]);
+        assert_eq!(
+            request["tools"][0]["function"]["parameters"]["type"],
+            "object"
+        );
+
+        let mut unknown = json!({"tools": [{"type": "mcp", "name": "search"}]});
+        let error = inject_tools(&mut unknown, &Weather).await.unwrap_err();
+        assert_eq!(error.code, "mcp_tool_not_found");
+
+        let completion = json!({"choices": [{"message": {"role": "assistant", "tool_calls": [{
+            "id": "call_1",
+            "type": "function",
+            "function": {"name": "weather", "arguments": "{\"city\":\"Hanoi\"}"},
+        }]}}]});
+        let calls = mcp_tool_calls(&completion, &names).unwrap();
+        let message = run_tool_call(&Weather, &calls[0]).await;
+        assert_eq!(message["tool_call_id"], "call_1");
+        assert_eq!(message["content"], "Sunny in \"Hanoi\"");
+
+        assert_eq!(mcp_tool_calls(&completion, &[]), None);
+    }
+}
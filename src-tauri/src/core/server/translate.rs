@@ -0,0 +1,95 @@
+use hyper::{Body, Method, StatusCode};
+use serde_json::Value;
+
+use super::errors::ApiError;
+use super::{anthropic, ollama};
+
+/// Client protocols served by translating them to and from the OpenAI API
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForeignApi {
+    Anthropic,
+    Ollama(ollama::Endpoint),
+}
+
+impl ForeignApi {
+    /// Picks the protocol from the request path and its destination path
+    pub fn detect(method: &Method, path: &str, destination: &str) -> Option<Self> {
+        if method == Method::POST && destination == anthropic::MESSAGES_PATH {
+            return Some(Self::Anthropic);
+        }
+        ollama::Endpoint::from_request(method, path).map(Self::Ollama)
+    }
+
+    /// Whether the request carries a JSON body to translate
+    pub fn has_body(self) -> bool {
+        !matches!(self, Self::Ollama(ollama::Endpoint::Tags))
+    }
+
+    pub fn translate_request(self, request: &Value) -> Result<Value, ApiError> {
+        match self {
+            Self::Anthropic => anthropic::to_openai_request(request),
+            Self::Ollama(endpoint) => ollama::to_openai_request(endpoint, request),
+        }
+    }
+
+    /// OpenAI endpoint the request is sent to, relative to the server prefix
+    pub fn openai_path(self, request: &Value) -> &'static str {
+        match self {
+            Self::Anthropic => "/chat/completions",
+            Self::Ollama(endpoint) => endpoint.openai_path(ollama::is_raw(request)),
+        }
+    }
+
+    pub fn stream_content_type(self) -> &'static str {
+        match self {
+            Self::Anthropic => "text/event-stream",
+            Self::Ollama(_) => ollama::NDJSON_CONTENT_TYPE,
+        }
+    }
+
+    pub fn translate_stream(self, body: Body) -> Body {
+        match self {
+            Self::Anthropic => anthropic::translate_stream(body),
+            Self::Ollama(endpoint) => ollama::translate_stream(endpoint, body),
+        }
+    }
+
+    pub fn translate_response(self, response: &Value) -> Value {
+        match self {
+            Self::Anthropic => anthropic::to_anthropic_response(response),
+            Self::Ollama(endpoint) => ollama::to_ollama_response(endpoint, response),
+        }
+    }
+
+    pub fn translate_error(self, status: StatusCode, body: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Anthropic => anthropic::to_anthropic_error(status, body),
+            Self::Ollama(_) => ollama::to_ollama_error(body),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            ForeignApi::detect(&Method::POST, "/v1/messages", "/v1/messages"),
+            Some(ForeignApi::Anthropic)
+        );
+        assert_eq!(
+            ForeignApi::detect(&Method::GET, "/api/tags", "/api/tags"),
+            Some(ForeignApi::Ollama(ollama::Endpoint::Tags))
+        );
+        assert_eq!(
+            ForeignApi::detect(
+                &Method::POST,
+                "/v1/chat/completions",
+                "/v1/chat/completions"
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::build_upstream_url;
+
+/// How often every upstream in the pool is probed
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Timeout for a single health probe
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout for asking an upstream to stop a generation
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// OpenAI inference routes every upstream serves. Everything else, like
+/// model management and downloads, only exists on the first upstream.
+const FAILOVER_PATHS: [&str; 4] = [
+    "/v1/chat/completions",
+    "/v1/completions",
+    "/v1/embeddings",
+    "/v1/models",
+];
+
+fn default_health_path() -> String {
+    "/healthz".to_string()
+}
+
+/// User-facing configuration of an upstream inference server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UpstreamConfig {
+    /// Base URL of the server, without the `/v1` suffix (e.g. `http://192.168.1.20:8080`)
+    pub url: String,
+    /// Bearer token sent to the upstream, if it requires one
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Lower values are tried first
+    #[serde(default)]
+    pub priority: u32,
+    /// Path probed by the background health check
+    #[serde(default = "default_health_path")]
+    pub health_path: String,
+    /// Path called with POST when a client abandons a stream, `{id}` stands
+    /// for the completion id (e.g. `/v1/chat/completions/{id}/cancel`)
+    #[serde(default)]
+    pub cancel_path: Option<String>,
+}
+
+impl UpstreamConfig {
+    /// The cortex sidecar. Cortex has no endpoint to cancel a completion, its
+    /// llama.cpp engine stops generating once the connection is closed, which
+    /// is what the proxy does when a client goes away.
+    pub fn cortex(url: &str, auth_token: String) -> Self {
+        Self {
+            url: url.to_string(),
+            api_key: Some(auth_token),
+            priority: 0,
+            health_path: default_health_path(),
+            cancel_path: None,
+        }
+    }
+}
+
+/// An upstream server together with its last known health
+#[derive(Debug)]
+pub struct Upstream {
+    pub url: String,
+    pub api_key: Option<String>,
+    pub priority: u32,
+    health_path: String,
+    cancel_path: Option<String>,
+    healthy: AtomicBool,
+}
+
+impl Upstream {
+    pub fn new(config: UpstreamConfig) -> Self {
+        Self {
+            url: config.url,
+            api_key: config.api_key,
+            priority: config.priority,
+            health_path: config.health_path,
+            cancel_path: config.cancel_path,
+            healthy: AtomicBool::new(true),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn set_healthy(&self, healthy: bool) {
+        let was_healthy = self.healthy.swap(healthy, Ordering::Relaxed);
+        if was_healthy != healthy {
+            if healthy {
+                log::info!("Upstream {} is healthy again", self.url);
+            } else {
+                log::warn!("Upstream {} marked as unhealthy", self.url);
+            }
+        }
+    }
+
+    /// Whether the upstream can be asked to stop a generation
+    pub fn can_cancel(&self) -> bool {
+        self.cancel_path.is_some()
+    }
+
+    fn cancel_url(&self, completion_id: &str) -> Option<String> {
+        let path = self.cancel_path.as_ref()?.replace("{id}", completion_id);
+        Some(build_upstream_url(&self.url, &path))
+    }
+
+    /// Asks the upstream to stop generating a completion nobody reads anymore
+    pub async fn cancel(&self, client: &Client, completion_id: &str) {
+        let Some(url) = self.cancel_url(completion_id) else {
+            return;
+        };
+        let mut request = client.post(&url).timeout(CANCEL_TIMEOUT);
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+        match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                log::debug!("Cancelled completion {} on {}", completion_id, self.url);
+            }
+            Ok(response) => log::warn!(
+                "Upstream {} refused to cancel completion {}: {}",
+                self.url,
+                completion_id,
+                response.status()
+            ),
+            Err(e) => log::warn!("Failed to cancel completion on {}: {}", self.url, e),
+        }
+    }
+
+    /// Probes the health path, treating any non-5xx answer as healthy
+    async fn check_health(&self, client: &Client) {
+        let url = build_upstream_url(&self.url, &self.health_path);
+        let mut request = client.get(&url).timeout(HEALTH_CHECK_TIMEOUT);
+        if let Some(api_key) = &self.api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+
+        let healthy = match request.send().await {
+            Ok(response) => !response.status().is_server_error(),
+            Err(e) => {
+                log::debug!("Health check for {} failed: {}", url, e);
+                false
+            }
+        };
+        self.set_healthy(healthy);
+    }
+}
+
+/// Reads the completion id from the first event of a streamed response
+pub fn stream_completion_id(chunk: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(chunk)
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .filter_map(|data| serde_json::from_str::<Value>(data.trim()).ok())
+        .find_map(|event| event.get("id")?.as_str().map(str::to_string))
+}
+
+/// Whether requests to this path may be sent to any upstream of the pool
+pub fn is_failover_path(path: &str) -> bool {
+    FAILOVER_PATHS.contains(&path)
+}
+
+/// Ordered set of upstreams the proxy fails over between
+#[derive(Debug)]
+pub struct UpstreamPool {
+    upstreams: Vec<Arc<Upstream>>,
+    /// First configured upstream, the cortex sidecar
+    primary: Arc<Upstream>,
+}
+
+impl UpstreamPool {
+    pub fn new(configs: Vec<UpstreamConfig>) -> Self {
+        let mut upstreams: Vec<Arc<Upstream>> = configs
+            .into_iter()
+            .map(|config| Arc::new(Upstream::new(config)))
+            .collect();
+        let primary = upstreams
+            .first()
+            .expect("upstream pool is never empty")
+            .clone();
+        // Stable sort keeps the configured order for equal priorities
+        upstreams.sort_by_key(|upstream| upstream.priority);
+        Self { upstreams, primary }
+    }
+
+    /// The upstream serving the routes other upstreams don't have
+    pub fn primary(&self) -> Arc<Upstream> {
+        self.primary.clone()
+    }
+
+    /// Returns the upstreams in the order they should be tried.
+    /// Healthy upstreams come first; unhealthy ones are kept as a last resort
+    /// so a stale health state never makes the server refuse every request.
+    pub fn candidates(&self) -> Vec<Arc<Upstream>> {
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) = self
+            .upstreams
+            .iter()
+            .cloned()
+            .partition(|upstream| upstream.is_healthy());
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    /// Periodically probes every upstream until the surrounding task is dropped
+    pub async fn run_health_checks(self: Arc<Self>, client: Client) {
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            for upstream in &self.upstreams {
+                upstream.check_health(&client).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(url: &str, priority: u32) -> UpstreamConfig {
+        UpstreamConfig {
+            url: url.to_string(),
+            api_key: None,
+            priority,
+            health_path: default_health_path(),
+            cancel_path: None,
+        }
+    }
+
+    #[test]
+    fn test_candidates_sorted_by_priority() {
+        let pool = UpstreamPool::new(vec![
+            upstream("http://backup", 2),
+            upstream("http://primary", 0),
+            upstream("http://secondary", 1),
+        ]);
+
+        let urls: Vec<_> = pool.candidates().iter().map(|u| u.url.clone()).collect();
+        assert_eq!(
+            urls,
+            ["http://primary", "http://secondary", "http://backup"]
+        );
+    }
+
+    #[test]
+    fn test_candidates_put_unhealthy_last() {
+        let pool = UpstreamPool::new(vec![
+            upstream("http://primary", 0),
+            upstream("http://secondary", 1),
+        ]);
+        pool.candidates()[0].set_healthy(false);
+
+        let urls: Vec<_> = pool.candidates().iter().map(|u| u.url.clone()).collect();
+        assert_eq!(urls, ["http://secondary", "http://primary"]);
+    }
+
+    #[test]
+    fn test_only_inference_routes_fail_over() {
+        let pool = UpstreamPool::new(vec![
+            upstream("http://cortex", 0),
+            upstream("http://backup", 1),
+        ]);
+        pool.candidates()[0].set_healthy(false);
+
+        assert_eq!(pool.primary().url, "http://cortex");
+        assert!(is_failover_path("/v1/chat/completions"));
+        assert!(!is_failover_path("/v1/models/start"));
+        assert!(!is_failover_path("/v1/models/pull"));
+    }
+
+    #[tokio::test]
+    async fn test_cortex_stream_is_stopped_by_closing_the_connection() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let cortex = Upstream::new(UpstreamConfig::cortex(
+            &format!("http://{}", listener.local_addr().unwrap()),
+            "token".to_string(),
+        ));
+        assert!(!cortex.can_cancel());
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n6\r\ndata: \r\n")
+                .await
+                .unwrap();
+            // Generation goes on until the proxy hangs up
+            let mut rest = Vec::new();
+            socket.read_to_end(&mut rest).await.unwrap();
+        });
+
+        let client = Client::new();
+        let url = build_upstream_url(&cortex.url, "/v1/chat/completions");
+        let response = client.post(&url).send().await.unwrap();
+        let mut stream = response.bytes_stream();
+        futures_util::StreamExt::next(&mut stream)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(stream);
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("the connection to cortex is closed")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_cancel_url_uses_the_streamed_completion_id() {
+        let chunk = b"data: {\"id\":\"chatcmpl-42\",\"choices\":[]}\n\n";
+        let id = stream_completion_id(chunk).unwrap();
+        assert_eq!(id, "chatcmpl-42");
+
+        let mut config = upstream("http://gpu-box:8080/", 0);
+        assert_eq!(Upstream::new(config.clone()).cancel_url(&id), None);
+        config.cancel_path = Some("/v1/chat/completions/{id}/cancel".to_string());
+        assert_eq!(
+            Upstream::new(config).cancel_url(&id).as_deref(),
+            Some("http://gpu-box:8080/v1/chat/completions/chatcmpl-42/cancel")
+        );
+    }
+}
@@ -0,0 +1,306 @@
+use serde_json::{Map, Value};
+
+use super::errors::ApiError;
+
+/// Roles accepted in chat messages
+const MESSAGE_ROLES: [&str; 6] = [
+    "system",
+    "developer",
+    "user",
+    "assistant",
+    "tool",
+    "function",
+];
+
+/// Validates the JSON payload of the OpenAI endpoints the proxy understands.
+/// Other paths are passed through untouched.
+pub fn validate_request(path: &str, body: &[u8]) -> Result<(), ApiError> {
+    let validate: fn(&Map<String, Value>) -> Result<(), ApiError> = match path {
+        "/v1/chat/completions" => validate_chat_completion,
+        "/v1/completions" => validate_completion,
+        "/v1/embeddings" => validate_embedding,
+        _ => return Ok(()),
+    };
+
+    let json: Value = serde_json::from_slice(body).map_err(|e| {
+        ApiError::invalid_request(
+            format!("We could not parse the JSON body of your request: {}", e),
+            "invalid_json",
+        )
+    })?;
+    let object = json.as_object().ok_or_else(|| {
+        ApiError::invalid_request("The request body must be a JSON object", "invalid_type")
+    })?;
+
+    require_model(object)?;
+    validate(object)
+}
+
+fn validate_chat_completion(body: &Map<String, Value>) -> Result<(), ApiError> {
+    let messages = match body.get("messages") {
+        Some(Value::Array(messages)) if !messages.is_empty() => messages,
+        Some(Value::Array(_)) => {
+            return Err(invalid_value("messages", "'messages' must not be empty"))
+        }
+        Some(_) => return Err(invalid_type("messages", "an array")),
+        None => return Err(missing("messages")),
+    };
+
+    for (index, message) in messages.iter().enumerate() {
+        let param = format!("messages.[{}]", index);
+        let role = message.get("role").and_then(Value::as_str).ok_or_else(|| {
+            ApiError::invalid_request(
+                format!("Missing required parameter: '{}.role'.", param),
+                "missing_required_parameter",
+            )
+            .with_param(format!("{}.role", param))
+        })?;
+        if !MESSAGE_ROLES.contains(&role) {
+            return Err(invalid_value(
+                &format!("{}.role", param),
+                &format!(
+                    "Invalid value: '{}'. Supported values are: {}.",
+                    role,
+                    MESSAGE_ROLES.join(", ")
+                ),
+            ));
+        }
+        match message.get("content") {
+            None | Some(Value::Null) | Some(Value::String(_)) | Some(Value::Array(_)) => {}
+            Some(_) => {
+                return Err(invalid_type(
+                    &format!("{}.content", param),
+                    "a string or an array",
+                ))
+            }
+        }
+    }
+
+    validate_sampling_params(body)?;
+    check_range(body, "max_completion_tokens", 1.0, f64::MAX, true)
+}
+
+fn validate_completion(body: &Map<String, Value>) -> Result<(), ApiError> {
+    match body.get("prompt") {
+        Some(prompt) if is_text_or_tokens(prompt) => {}
+        Some(_) => {
+            return Err(invalid_type(
+                "prompt",
+                "a string, an array of strings or an array of tokens",
+            ))
+        }
+        None => return Err(missing("prompt")),
+    }
+    validate_sampling_params(body)
+}
+
+fn validate_embedding(body: &Map<String, Value>) -> Result<(), ApiError> {
+    match body.get("input") {
+        Some(Value::String(input)) if input.is_empty() => {
+            return Err(invalid_value("input", "'input' must not be empty"))
+        }
+        Some(Value::Array(input)) if input.is_empty() => {
+            return Err(invalid_value("input", "'input' must not be empty"))
+        }
+        Some(input) if is_text_or_tokens(input) => {}
+        Some(_) => {
+            return Err(invalid_type(
+                "input",
+                "a string, an array of strings or an array of tokens",
+            ))
+        }
+        None => return Err(missing("input")),
+    }
+
+    if let Some(format) = body.get("encoding_format") {
+        if !matches!(format.as_str(), Some("float") | Some("base64")) {
+            return Err(invalid_value(
+                "encoding_format",
+                "Invalid value for 'encoding_format'. Supported values are: float, base64.",
+            ));
+        }
+    }
+    check_range(body, "dimensions", 1.0, f64::MAX, true)
+}
+
+/// Parameters shared by chat completions and completions
+fn validate_sampling_params(body: &Map<String, Value>) -> Result<(), ApiError> {
+    check_range(body, "temperature", 0.0, 2.0, false)?;
+    check_range(body, "top_p", 0.0, 1.0, false)?;
+    check_range(body, "presence_penalty", -2.0, 2.0, false)?;
+    check_range(body, "frequency_penalty", -2.0, 2.0, false)?;
+    check_range(body, "n", 1.0, 128.0, true)?;
+    check_range(body, "max_tokens", 1.0, f64::MAX, true)?;
+
+    if let Some(stream) = body.get("stream") {
+        if !stream.is_boolean() && !stream.is_null() {
+            return Err(invalid_type("stream", "a boolean"));
+        }
+    }
+
+    match body.get("stop") {
+        None | Some(Value::Null) | Some(Value::String(_)) => Ok(()),
+        Some(Value::Array(stop)) if stop.len() <= 4 && stop.iter().all(Value::is_string) => Ok(()),
+        Some(_) => Err(invalid_type(
+            "stop",
+            "a string or an array of up to 4 strings",
+        )),
+    }
+}
+
+fn require_model(body: &Map<String, Value>) -> Result<(), ApiError> {
+    match body.get("model") {
+        Some(Value::String(model)) if !model.trim().is_empty() => Ok(()),
+        Some(Value::String(_)) => Err(invalid_value("model", "'model' must not be empty")),
+        Some(_) => Err(invalid_type("model", "a string")),
+        None => Err(missing("model")),
+    }
+}
+
+/// Checks an optional numeric parameter, `null` counts as unset
+fn check_range(
+    body: &Map<String, Value>,
+    param: &str,
+    min: f64,
+    max: f64,
+    integer: bool,
+) -> Result<(), ApiError> {
+    let value = match body.get(param) {
+        None | Some(Value::Null) => return Ok(()),
+        Some(value) => value,
+    };
+
+    let number = if integer {
+        value.as_i64().map(|number| number as f64)
+    } else {
+        value.as_f64()
+    };
+    let Some(number) = number else {
+        let expected = if integer { "an integer" } else { "a number" };
+        return Err(invalid_type(param, expected));
+    };
+
+    if number < min || number > max {
+        let message = if max == f64::MAX {
+            format!(
+                "{} is less than the minimum of {} - '{}'",
+                number, min, param
+            )
+        } else {
+            format!(
+                "{} is not in the range {} to {} - '{}'",
+                number, min, max, param
+            )
+        };
+        return Err(invalid_value(param, &message));
+    }
+    Ok(())
+}
+
+/// A string, a list of strings, a list of token ids or a list of token id lists
+fn is_text_or_tokens(value: &Value) -> bool {
+    match value {
+        Value::String(_) => true,
+        Value::Array(items) => {
+            items.iter().all(Value::is_string)
+                || items.iter().all(Value::is_u64)
+                || items.iter().all(|item| {
+                    item.as_array()
+                        .is_some_and(|tokens| tokens.iter().all(Value::is_u64))
+                })
+        }
+        _ => false,
+    }
+}
+
+fn missing(param: &str) -> ApiError {
+    ApiError::invalid_request(
+        format!("Missing required parameter: '{}'.", param),
+        "missing_required_parameter",
+    )
+    .with_param(param)
+}
+
+fn invalid_type(param: &str, expected: &str) -> ApiError {
+    ApiError::invalid_request(
+        format!("Invalid type for '{}': expected {}.", param, expected),
+        "invalid_type",
+    )
+    .with_param(param)
+}
+
+fn invalid_value(param: &str, message: &str) -> ApiError {
+    ApiError::invalid_request(message, "invalid_value").with_param(param)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(path: &str, body: Value) -> Result<(), ApiError> {
+        validate_request(path, body.to_string().as_bytes())
+    }
+
+    #[test]
+    fn test_validate_chat_completion() {
+        let valid = serde_json::json!({
+            "model": "llama3.2-3b-instruct",
+            "messages": [{"role": "user", "content": "Hello"}],
+            "temperature": 0,
+            "stop": ["\n"],
+        });
+        assert!(validate("/v1/chat/completions", valid).is_ok());
+
+        let error = validate(
+            "/v1/chat/completions",
+            serde_json::json!({"model": "llama3.2-3b-instruct"}),
+        )
+        .unwrap_err();
+        assert_eq!(error.code, "missing_required_parameter");
+        assert_eq!(error.param.as_deref(), Some("messages"));
+
+        let error = validate(
+            "/v1/chat/completions",
+            serde_json::json!({
+                "model": "llama3.2-3b-instruct",
+                "messages": [{"role": "user", "content": "Hello"}],
+                "temperature": 3,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(error.param.as_deref(), Some("temperature"));
+
+        let error = validate_request("/v1/chat/completions", b"{not json").unwrap_err();
+        assert_eq!(error.code, "invalid_json");
+    }
+
+    #[test]
+    fn test_validate_embedding_and_completion() {
+        assert!(validate(
+            "/v1/embeddings",
+            serde_json::json!({"model": "nomic-embed", "input": ["a", "b"]}),
+        )
+        .is_ok());
+        assert!(validate(
+            "/v1/embeddings",
+            serde_json::json!({"model": "nomic-embed", "input": [[1, 2], [3]]}),
+        )
+        .is_ok());
+        assert!(validate(
+            "/v1/embeddings",
+            serde_json::json!({"model": "nomic-embed", "input": 42}),
+        )
+        .is_err());
+
+        assert_eq!(
+            validate("/v1/completions", serde_json::json!({"prompt": "Once"}))
+                .unwrap_err()
+                .param
+                .as_deref(),
+            Some("model")
+        );
+
+        // Paths without a schema are not inspected
+        assert!(validate_request("/v1/models/pull", b"not json").is_ok());
+    }
+}
@@ -16,6 +16,7 @@ use tokio::time::{sleep, Duration}; // Using tokio::sync::Mutex
 use super::{
     cmd::{get_jan_data_folder_path, get_jan_extensions_path},
     mcp::run_mcp_commands,
+    server::ApiKeyStore,
     state::AppState,
 };
 
@@ -240,6 +241,15 @@ pub fn setup_mcp(app: &App) {
     });
 }
 
+pub fn setup_api_keys(app: &App) {
+    let state = app.state::<AppState>();
+    let data_folder = get_jan_data_folder_path(app.handle().clone());
+    let store = ApiKeyStore::load(&data_folder);
+    tauri::async_runtime::block_on(async {
+        *state.api_keys.lock().await = store;
+    });
+}
+
 pub fn setup_sidecar(app: &App) -> Result<(), String> {
     clean_up();
     let app_handle = app.handle().clone();
@@ -1,13 +1,13 @@
 use std::{collections::HashMap, sync::Arc};
 
+use crate::core::server::{ApiKeyStore, RunningServer, ServerMetrics};
 use crate::core::utils::download::DownloadManagerState;
 use rand::{distributions::Alphanumeric, Rng};
 use rmcp::{service::RunningService, RoleClient};
 use tokio::sync::Mutex;
-use tokio::task::JoinHandle;
 
 /// Server handle type for managing the proxy server lifecycle
-pub type ServerHandle = JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>;
+pub type ServerHandle = RunningServer;
 
 #[derive(Default)]
 pub struct AppState {
@@ -20,6 +20,9 @@ pub struct AppState {
     pub mcp_active_servers: Arc<Mutex<HashMap<String, serde_json::Value>>>,
     pub mcp_successfully_connected: Arc<Mutex<HashMap<String, bool>>>,
     pub server_handle: Arc<Mutex<Option<ServerHandle>>>,
+    pub api_keys: Arc<Mutex<ApiKeyStore>>,
+    pub server_metrics: Arc<ServerMetrics>,
+    pub server_tls_fingerprint: Arc<Mutex<Option<String>>>,
 }
 pub fn generate_app_token() -> String {
     rand::thread_rng()
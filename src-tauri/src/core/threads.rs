@@ -10,6 +10,7 @@
     - As a result, the messages.jsonl file for each thread is always consistent and never corrupted, even under concurrent access.
 */
 
+use futures_util::future::BoxFuture;
 use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Write};
@@ -27,6 +28,7 @@ use tokio::sync::Mutex;
 static MESSAGE_LOCKS: Lazy<Mutex<HashMap<String, Arc<Mutex<()>>>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
 
+use super::server::ConversationStore;
 use super::utils::{
     ensure_data_dirs, ensure_thread_dir_exists, get_data_dir, get_messages_path, get_thread_dir,
     get_thread_metadata_path, THREADS_FILE,
@@ -475,6 +477,50 @@ pub async fn modify_thread_assistant<R: Runtime>(
     Ok(assistant)
 }
 
+/// Lets the local API server keep its conversations as threads
+impl<R: Runtime> ConversationStore for tauri::AppHandle<R> {
+    fn create_thread(
+        &self,
+        thread: serde_json::Value,
+    ) -> BoxFuture<'_, Result<serde_json::Value, String>> {
+        Box::pin(create_thread(self.clone(), thread))
+    }
+
+    fn get_thread(
+        &self,
+        thread_id: String,
+    ) -> BoxFuture<'_, Result<Option<serde_json::Value>, String>> {
+        Box::pin(async move {
+            let path = get_thread_metadata_path(self.clone(), &thread_id);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let data = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+            serde_json::from_str(&data)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn modify_thread(&self, thread: serde_json::Value) -> BoxFuture<'_, Result<(), String>> {
+        Box::pin(modify_thread(self.clone(), thread))
+    }
+
+    fn list_messages(
+        &self,
+        thread_id: String,
+    ) -> BoxFuture<'_, Result<Vec<serde_json::Value>, String>> {
+        Box::pin(list_messages(self.clone(), thread_id))
+    }
+
+    fn create_message(
+        &self,
+        message: serde_json::Value,
+    ) -> BoxFuture<'_, Result<serde_json::Value, String>> {
+        Box::pin(create_message(self.clone(), message))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::core::cmd::get_jan_data_folder_path;
@@ -1,7 +1,8 @@
 mod core;
 use core::{
     cmd::get_jan_data_folder_path,
-    setup::{self, setup_engine_binaries, setup_mcp, setup_sidecar},
+    server::{ApiKeyStore, ServerMetrics},
+    setup::{self, setup_api_keys, setup_engine_binaries, setup_mcp, setup_sidecar},
     state::{generate_app_token, AppState},
     utils::download::DownloadManagerState,
 };
@@ -55,10 +56,19 @@ pub fn run() {
             core::cmd::app_token,
             core::cmd::start_server,
             core::cmd::stop_server,
+            core::cmd::update_server_config,
             core::cmd::get_server_status,
+            core::cmd::get_server_stats,
+            core::cmd::get_server_tls_fingerprint,
+            core::cmd::verify_audit_log,
+            core::cmd::browse_servers,
             core::cmd::read_logs,
             core::cmd::change_app_data_folder,
             core::cmd::reset_cortex_restart_count,
+            core::cmd::list_api_keys,
+            core::cmd::create_api_key,
+            core::cmd::revoke_api_key,
+            core::cmd::delete_api_key,
             // MCP commands
             core::mcp::get_tools,
             core::mcp::call_tool,
@@ -98,6 +108,9 @@ pub fn run() {
             mcp_active_servers: Arc::new(Mutex::new(HashMap::new())),
             mcp_successfully_connected: Arc::new(Mutex::new(HashMap::new())),
             server_handle: Arc::new(Mutex::new(None)),
+            api_keys: Arc::new(Mutex::new(ApiKeyStore::default())),
+            server_metrics: Arc::new(ServerMetrics::default()),
+            server_tls_fingerprint: Arc::new(Mutex::new(None)),
         })
         .setup(|app| {
             app.handle().plugin(
@@ -120,6 +133,7 @@ pub fn run() {
                 log::error!("Failed to install extensions: {}", e);
             }
             setup_mcp(app);
+            setup_api_keys(app);
             setup_sidecar(app).expect("Failed to setup sidecar");
             setup_engine_binaries(app).expect("Failed to setup engine binaries");
             Ok(())